@@ -15,7 +15,7 @@
 //! [`ffi::BotBuilder`](crate::ffi::BotBuilder) and [`ws::BotBuilder`](crate::ws::BotBuilder).
 
 use simploxide_api_types::{
-    AddressSettings, AutoAccept, BadgeProof, CIDeleteMode, ChatListQuery, ChatPeerType,
+    AddressSettings, AutoAccept, BadgeProof, CIDeleteMode, ChatItem, ChatListQuery, ChatPeerType,
     ConnectionPlan, Contact, CreatedConnLink, GroupInfo, GroupMember, GroupMemberRole,
     GroupPreferences, GroupProfile, JsonObject, LocalProfile, MsgContent, NewUser,
     PaginationByTime, Preferences, Profile, SimplexDomainClaim, User, UserInfo,
@@ -29,26 +29,30 @@ use simploxide_api_types::{
         ContactPrefsUpdatedResponse, ContactRequestRejectedResponse, GroupCreatedResponse,
         GroupLinkCreatedResponse, GroupLinkDeletedResponse, GroupUpdatedResponse,
         InvitationResponse, LeftMemberUserResponse, MemberAcceptedResponse,
-        MembersBlockedForAllUserResponse, MembersRoleUserResponse, SentGroupInvitationResponse,
-        UserAcceptedGroupSentResponse, UserDeletedMembersResponse, UserProfileUpdatedResponse,
+        MembersBlockedForAllUserResponse, MembersRoleUserResponse, NewChatItemsResponse,
+        SentGroupInvitationResponse, UserAcceptedGroupSentResponse, UserDeletedMembersResponse,
+        UserProfileUpdatedResponse,
     },
 };
 
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
 use futures::{FutureExt as _, TryFutureExt as _};
 
 use crate::{
+    dialogue::{Ask, Replies},
     ext::{
         AcceptFileBuilder, AddGroupRelaysResponse, ClientApiExt as _, DeleteMode,
-        GetGroupRelaysResponse, GroupLinkResult, Reaction,
+        GetGroupRelaysResponse, GroupLinkResult, History, Reaction, ReactionsExt as _,
     },
+    i18n,
     id::{
         ChatId, ContactId, ContactRequestId, FileId, GroupId, MemberId, MessageId, RelayId, UserId,
     },
     messages::{MessageBuilder, MessageLike, MulticastBuilder},
     preferences,
     preview::ImagePreview,
+    scheduler::{JobHandle, Schedule, Scheduler},
 };
 
 #[cfg(feature = "farm")]
@@ -75,8 +79,8 @@ impl<C> Bot<C> {
 }
 
 impl<C: ClientApi> Bot<C> {
-    #[cfg(feature = "farm")]
-    fn new(client: C, user_id: UserId) -> Self {
+    #[cfg(any(feature = "farm", all(test, feature = "mock")))]
+    pub(crate) fn new(client: C, user_id: UserId) -> Self {
         Self {
             client,
             user_id: user_id.raw(),
@@ -582,6 +586,15 @@ impl<C: ClientApi> Bot<C> {
         self.client.groups(self.user_id())
     }
 
+    /// Fill the directory cache with the bot contacts, groups and group members. See
+    /// [`Directory`](crate::directory::Directory)
+    pub fn load_directory<'a>(
+        &'a self,
+        directory: &'a crate::directory::Directory,
+    ) -> impl Future<Output = Result<(), C::Error>> {
+        directory.load(&self.client, self.user_id())
+    }
+
     /// Accept contact request
     pub fn accept_contact<CRID: Into<ContactRequestId>>(
         &self,
@@ -607,6 +620,18 @@ impl<C: ClientApi> Bot<C> {
         self.client.send_message(chat_id.into(), msg)
     }
 
+    /// Deliver a message later with the `scheduler`. A shortcut for
+    /// `bot.send_msg(chat_id, msg).schedule(scheduler, schedule)`, see [`crate::scheduler`]
+    pub fn schedule<CID: Into<ChatId>, M: MessageLike>(
+        &self,
+        scheduler: &Scheduler,
+        schedule: Schedule,
+        chat_id: CID,
+        msg: M,
+    ) -> impl Future<Output = std::io::Result<JobHandle>> {
+        self.send_msg(chat_id, msg).schedule(scheduler, schedule)
+    }
+
     /// Send the same message to multiple recepients
     pub fn multicast<I, M>(&self, chat_ids: I, msg: M) -> MulticastBuilder<'_, I, C, M::Kind>
     where
@@ -720,6 +745,37 @@ impl<C: ClientApi> Bot<C> {
             .batch_delete_messages(chat_id, message_ids, mode)
     }
 
+    /// Forward messages to another chat. The messages keep their content and files and are marked
+    /// as forwarded, so attachments don't need to be downloaded and sent again.
+    pub fn forward_msg<FCID, I, TCID>(
+        &self,
+        from_chat: FCID,
+        msg_ids: I,
+        to_chat: TCID,
+    ) -> impl Future<Output = Result<Arc<NewChatItemsResponse>, C::Error>>
+    where
+        FCID: Into<ChatId>,
+        I: IntoIterator<Item = MessageId>,
+        TCID: Into<ChatId>,
+    {
+        self.client.forward_messages(from_chat, msg_ids, to_chat)
+    }
+
+    /// Forward the same messages to multiple chats. Returns one result per chat.
+    pub fn multicast_forward<FCID, I, T>(
+        &self,
+        from_chat: FCID,
+        msg_ids: I,
+        to_chats: T,
+    ) -> impl Future<Output = Vec<Result<Arc<NewChatItemsResponse>, C::Error>>>
+    where
+        FCID: Into<ChatId>,
+        I: IntoIterator<Item = MessageId>,
+        T: IntoIterator<Item = ChatId>,
+    {
+        self.client.multicast_forward(from_chat, msg_ids, to_chats)
+    }
+
     /// Applies multiple reactions to a message. Returns one result per reaction.
     pub fn batch_msg_reactions<
         CID: Into<ChatId>,
@@ -745,6 +801,17 @@ impl<C: ClientApi> Bot<C> {
             .update_message_reaction(chat_id, message_id, reaction)
     }
 
+    /// Fetch the message and count its reactions, see [`ReactionsExt`](crate::ext::ReactionsExt)
+    pub fn reaction_tally<CID: Into<ChatId>, MID: Into<MessageId>>(
+        &self,
+        chat_id: CID,
+        message_id: MID,
+    ) -> impl Future<Output = Result<BTreeMap<String, usize>, C::Error>> {
+        self.client
+            .get_chat_item(chat_id, message_id)
+            .map_ok(|item| item.reaction_tally())
+    }
+
     /// Starts background file download. Catch `RcvFile*` events to track the progress
     pub fn accept_file<FID: Into<FileId>>(&self, file_id: FID) -> AcceptFileBuilder<'_, C> {
         self.client.accept_file(file_id)
@@ -1025,6 +1092,18 @@ impl<C: ClientApi> Bot<C> {
         self.client.set_contact_custom_data(contact_id, data)
     }
 
+    /// Stores the contact locale for [`Catalog::message_for`](crate::i18n::Catalog::message_for).
+    /// Other keys of the contact custom data are kept.
+    pub fn set_contact_locale(
+        &self,
+        contact: &Contact,
+        locale: &str,
+    ) -> impl Future<Output = Result<Arc<CmdOkResponse>, C::Error>> {
+        let data = i18n::with_locale(contact.custom_data.clone(), locale);
+        self.client
+            .set_contact_custom_data(ContactId::from(contact), Some(data))
+    }
+
     pub fn create_group_link<GID: Into<GroupId>>(
         &self,
         group_id: GID,
@@ -1094,6 +1173,52 @@ impl<C: ClientApi> Bot<C> {
         self.client.default_relays()
     }
 
+    /// Page through the chat history from the newest to the oldest message.
+    ///
+    /// ```ignore
+    /// let last_50: Vec<ChatItem> = bot.history(chat_id).limit(50).try_collect().await?;
+    /// let older = bot.history(chat_id).before(msg_id).limit(20);
+    /// ```
+    pub fn history<CID: Into<ChatId>>(&self, chat_id: CID) -> History<'_, C> {
+        self.client.chat_history(chat_id)
+    }
+
+    /// Search the chat history for messages containing the text. Results go from the newest to
+    /// the oldest message, see [`History`] for paging options.
+    pub fn search<CID: Into<ChatId>>(
+        &self,
+        chat_id: CID,
+        text: impl Into<String>,
+    ) -> History<'_, C> {
+        self.client.chat_history(chat_id).search(text)
+    }
+
+    /// Get a single chat item by its ID
+    pub fn get_item<CID: Into<ChatId>, MID: Into<MessageId>>(
+        &self,
+        chat_id: CID,
+        message_id: MID,
+    ) -> impl Future<Output = Result<ChatItem, C::Error>> {
+        self.client.get_chat_item(chat_id, message_id)
+    }
+
+    /// Send the question and await the next message from the chat, see [`Ask`].
+    ///
+    /// Requires [`Replies`] installed on the event stream via
+    /// [`EventStream::hook_replies`](crate::EventStream::hook_replies).
+    ///
+    /// # Deadlock warning
+    ///
+    /// See [`Ask`].
+    pub fn ask<'a, CID: Into<ChatId>>(
+        &'a self,
+        replies: &'a Replies,
+        chat_id: CID,
+        question: impl Into<String>,
+    ) -> Ask<'a, C> {
+        Ask::new(&self.client, replies, chat_id.into(), question.into())
+    }
+
     /// Accept an incoming remote control session from a SimpleX Desktop client.
     ///
     /// Requires a [`CtrlHandle`](crate::remote::CtrlHandle) installed on the event
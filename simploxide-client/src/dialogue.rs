@@ -0,0 +1,1124 @@
+//! Typed per-chat conversation state machines.
+//!
+//! Declare dialogue states as an enum and write a single handler receiving the current state of
+//! the chat, the incoming message and the bot. The handler returns the next state, states are
+//! kept per [`ChatId`] in a [`DialogueStorage`] ([`InMemory`] by default).
+//!
+//! ```ignore
+//! #[derive(Clone, Default)]
+//! enum Survey {
+//!     #[default]
+//!     Start,
+//!     AskName,
+//!     AskAge { name: String },
+//! }
+//!
+//! let dialogue = Dialogue::new(async |state, input: Input, bot: ws::Bot| {
+//!     let next = match state {
+//!         Survey::Start => {
+//!             bot.send_msg(input.chat_id, "What's your name?").await?;
+//!             Survey::AskName
+//!         }
+//!         Survey::AskName => {
+//!             bot.send_msg(input.chat_id, "How old are you?").await?;
+//!             Survey::AskAge { name: input.text().to_owned() }
+//!         }
+//!         Survey::AskAge { name } => {
+//!             bot.send_msg(input.chat_id, format!("Thanks, {name}!")).await?;
+//!             return ClientResult::Ok(Transition::Exit);
+//!         }
+//!     };
+//!
+//!     Ok(next.into())
+//! })
+//! .timeout(Duration::from_secs(600));
+//!
+//! events.into_dispatcher(bot)
+//!     .on(dialogue.handler())
+//!     .dispatch()
+//!     .await?;
+//! ```
+//!
+//! Messages from the same chat are processed one at a time even with concurrent dispatchers,
+//! messages from different chats are processed concurrently.
+//!
+//! ### Asking
+//!
+//! Simple wizards don't need a state machine. Hook [`Replies`] into the event stream and await the
+//! answers with [`Bot::ask`], the replies are captured and never reach the dispatcher:
+//!
+//! ```ignore
+//! let (replies, events) = events.hook_replies();
+//!
+//! // From a concurrent handler
+//! let email = bot
+//!     .ask(&replies, chat, "What's your email?")
+//!     .validate(|input| {
+//!         let text = input.text();
+//!         text.contains('@').then(|| text.to_owned()).ok_or("That's not an email, try again")
+//!     })
+//!     .timeout(Duration::from_secs(300))
+//!     .await?;
+//! ```
+
+use simploxide_api_types::{CIDirection, ChatItem, MsgContent, events::NewChatItems};
+
+use std::{
+    collections::HashMap,
+    future::{Future, IntoFuture},
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    Capture, ClientApi, Hook, StreamEvents,
+    bot::Bot,
+    dispatcher,
+    events::{Event, EventKind},
+    ext::ClientApiExt as _,
+    id::{ChatId, MemberId},
+    messages::MsgContentExt as _,
+};
+
+pub type DialogueFuture<T, E> = Pin<Box<dyn Send + Future<Output = Result<T, E>>>>;
+
+type BoxHandler<S, C, E> =
+    Box<dyn Send + Sync + Fn(S, Input, Bot<C>) -> DialogueFuture<Transition<S>, E>>;
+
+/// What to do with the dialogue after the handler returns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transition<S> {
+    /// Move to the next state
+    Next(S),
+    /// Finish the dialogue. The next message starts it over from `S::default()`
+    Exit,
+    /// Finish the dialogue and stop the dispatcher
+    Break,
+}
+
+impl<S> From<S> for Transition<S> {
+    fn from(state: S) -> Self {
+        Self::Next(state)
+    }
+}
+
+/// A received message passed into the dialogue handler
+pub struct Input {
+    /// The chat where the message was received
+    pub chat_id: ChatId,
+    event: Arc<NewChatItems>,
+    index: usize,
+}
+
+impl Input {
+    pub fn item(&self) -> &ChatItem {
+        &self.event.chat_items[self.index].chat_item
+    }
+
+    /// Received message content
+    pub fn content(&self) -> &MsgContent {
+        self.item()
+            .content
+            .rcv_msg_content()
+            .expect("Input is created only for received messages")
+    }
+
+    /// Message text with surrounding whitespace trimmed. Empty if the message has no text
+    pub fn text(&self) -> &str {
+        self.content().text_part().unwrap_or_default().trim()
+    }
+
+    /// The full event the message was received in
+    pub fn event(&self) -> &Arc<NewChatItems> {
+        &self.event
+    }
+}
+
+/// A dialogue state with its last update time
+#[derive(Debug, Clone)]
+pub struct StoredState<S> {
+    pub state: S,
+    pub updated_at: SystemTime,
+}
+
+/// Per-chat state storage. `E` is the dialogue handler error, storages that can fail must
+/// convert their errors into it.
+pub trait DialogueStorage<S, E>: 'static + Send + Sync {
+    fn load(
+        &self,
+        chat_id: ChatId,
+    ) -> impl Future<Output = Result<Option<StoredState<S>>, E>> + Send;
+
+    fn store(
+        &self,
+        chat_id: ChatId,
+        state: StoredState<S>,
+    ) -> impl Future<Output = Result<(), E>> + Send;
+
+    fn remove(&self, chat_id: ChatId) -> impl Future<Output = Result<(), E>> + Send;
+}
+
+/// The default in-memory storage. States are lost on restart.
+pub struct InMemory<S> {
+    states: Mutex<HashMap<ChatId, StoredState<S>>>,
+}
+
+impl<S> Default for InMemory<S> {
+    fn default() -> Self {
+        Self {
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<S> InMemory<S> {
+    /// Drop states not updated for longer than `timeout`
+    pub fn purge_stale(&self, timeout: Duration) {
+        self.states
+            .lock()
+            .unwrap()
+            .retain(|_, stored| !is_stale(stored.updated_at, timeout));
+    }
+
+    /// Number of active dialogues
+    pub fn len(&self) -> usize {
+        self.states.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<S, E> DialogueStorage<S, E> for InMemory<S>
+where
+    S: 'static + Send + Clone,
+{
+    async fn load(&self, chat_id: ChatId) -> Result<Option<StoredState<S>>, E> {
+        Ok(self.states.lock().unwrap().get(&chat_id).cloned())
+    }
+
+    async fn store(&self, chat_id: ChatId, state: StoredState<S>) -> Result<(), E> {
+        self.states.lock().unwrap().insert(chat_id, state);
+        Ok(())
+    }
+
+    async fn remove(&self, chat_id: ChatId) -> Result<(), E> {
+        self.states.lock().unwrap().remove(&chat_id);
+        Ok(())
+    }
+}
+
+/// A per-chat state machine. Cheap to clone.
+pub struct Dialogue<S, C, E, St = InMemory<S>> {
+    inner: Arc<Inner<S, C, E, St>>,
+}
+
+struct Inner<S, C, E, St> {
+    storage: St,
+    handler: BoxHandler<S, C, E>,
+    timeout: Option<Duration>,
+    locks: Mutex<HashMap<ChatId, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl<S, C, E, St> Clone for Dialogue<S, C, E, St> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<S, C, E> Dialogue<S, C, E>
+where
+    S: 'static + Send + Clone + Default,
+    C: 'static + Clone + Send + ClientApi,
+    E: 'static + Send,
+{
+    /// Creates a dialogue with the [`InMemory`] storage.
+    ///
+    /// The handler signature is `AsyncFn(state: S, input: Input, bot: Bot<C>) -> Result<Transition<S>, E>`
+    pub fn new<F, Fut>(handler: F) -> Self
+    where
+        F: 'static + Send + Sync + Fn(S, Input, Bot<C>) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<Transition<S>, E>>,
+    {
+        Self::with_storage(InMemory::default(), handler)
+    }
+}
+
+impl<S, C, E, St> Dialogue<S, C, E, St>
+where
+    S: 'static + Send + Default,
+    C: 'static + Clone + Send + ClientApi,
+    E: 'static + Send,
+    St: DialogueStorage<S, E>,
+{
+    /// Creates a dialogue with a custom storage
+    pub fn with_storage<F, Fut>(storage: St, handler: F) -> Self
+    where
+        F: 'static + Send + Sync + Fn(S, Input, Bot<C>) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<Transition<S>, E>>,
+    {
+        Self {
+            inner: Arc::new(Inner {
+                storage,
+                handler: Box::new(move |state, input, bot| Box::pin(handler(state, input, bot))),
+                timeout: None,
+                locks: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Reset dialogues not updated for longer than `timeout` to `S::default()`
+    ///
+    /// # Panics
+    ///
+    /// When called after the dialogue was cloned
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        Arc::get_mut(&mut self.inner)
+            .expect("Dialogue must be configured before cloning")
+            .timeout = Some(timeout);
+        self
+    }
+
+    pub fn storage(&self) -> &St {
+        &self.inner.storage
+    }
+
+    /// The current state of the chat. `None` if the dialogue is not started or has timed out
+    pub async fn state(&self, chat_id: ChatId) -> Result<Option<S>, E> {
+        let stored = self.inner.storage.load(chat_id).await?;
+
+        Ok(stored
+            .filter(|stored| !self.is_stale(stored))
+            .map(|stored| stored.state))
+    }
+
+    /// Force the chat into the given state
+    pub async fn set_state(&self, chat_id: ChatId, state: S) -> Result<(), E> {
+        let _guard = self.lock(chat_id).await;
+        self.inner
+            .storage
+            .store(
+                chat_id,
+                StoredState {
+                    state,
+                    updated_at: SystemTime::now(),
+                },
+            )
+            .await
+    }
+
+    /// Drop the chat state so the next message starts the dialogue over
+    pub async fn reset(&self, chat_id: ChatId) -> Result<(), E> {
+        let _guard = self.lock(chat_id).await;
+        self.inner.storage.remove(chat_id).await
+    }
+
+    /// The [`NewChatItems`] handler for [`Dispatcher::on`](crate::dispatcher::Dispatcher::on)
+    pub fn handler(
+        &self,
+    ) -> impl Fn(Arc<NewChatItems>, Bot<C>) -> DialogueFuture<StreamEvents, E> + use<S, C, E, St>
+    {
+        let dialogue = self.clone();
+        move |ev, bot| {
+            let dialogue = dialogue.clone();
+            Box::pin(async move { dialogue.route(ev, bot).await })
+        }
+    }
+
+    /// Feed all received messages from the event into the dialogue
+    pub async fn route(&self, ev: Arc<NewChatItems>, bot: Bot<C>) -> Result<StreamEvents, E> {
+        for (index, item) in ev.chat_items.iter().enumerate() {
+            if item.chat_item.content.rcv_msg_content().is_none() {
+                continue;
+            }
+
+            let Some(chat_id) = ChatId::from_chat_info(&item.chat_info) else {
+                continue;
+            };
+
+            let input = Input {
+                chat_id,
+                event: Arc::clone(&ev),
+                index,
+            };
+
+            if let StreamEvents::Break = self.step(input, bot.clone()).await? {
+                return Ok(StreamEvents::Break);
+            }
+        }
+
+        Ok(StreamEvents::Continue)
+    }
+
+    async fn step(&self, input: Input, bot: Bot<C>) -> Result<StreamEvents, E> {
+        let chat_id = input.chat_id;
+        let _guard = self.lock(chat_id).await;
+
+        let state = self
+            .inner
+            .storage
+            .load(chat_id)
+            .await?
+            .filter(|stored| !self.is_stale(stored))
+            .map(|stored| stored.state)
+            .unwrap_or_default();
+
+        let result = match (self.inner.handler)(state, input, bot).await? {
+            Transition::Next(state) => {
+                let stored = StoredState {
+                    state,
+                    updated_at: SystemTime::now(),
+                };
+
+                self.inner.storage.store(chat_id, stored).await?;
+                StreamEvents::Continue
+            }
+            Transition::Exit => {
+                self.inner.storage.remove(chat_id).await?;
+                StreamEvents::Continue
+            }
+            Transition::Break => {
+                self.inner.storage.remove(chat_id).await?;
+                StreamEvents::Break
+            }
+        };
+
+        Ok(result)
+    }
+
+    fn is_stale(&self, stored: &StoredState<S>) -> bool {
+        self.inner
+            .timeout
+            .is_some_and(|timeout| is_stale(stored.updated_at, timeout))
+    }
+
+    async fn lock(&self, chat_id: ChatId) -> ChatGuard<'_> {
+        let lock = Arc::clone(self.inner.locks.lock().unwrap().entry(chat_id).or_default());
+
+        let guard = lock.clone().lock_owned().await;
+
+        ChatGuard {
+            locks: &self.inner.locks,
+            chat_id,
+            guard: Some(guard),
+            lock,
+        }
+    }
+}
+
+/// Removes the chat lock from the map when nobody else waits for it
+struct ChatGuard<'a> {
+    locks: &'a Mutex<HashMap<ChatId, Arc<tokio::sync::Mutex<()>>>>,
+    chat_id: ChatId,
+    guard: Option<tokio::sync::OwnedMutexGuard<()>>,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Drop for ChatGuard<'_> {
+    fn drop(&mut self) {
+        self.guard.take();
+        let mut locks = self.locks.lock().unwrap();
+
+        // The map, this guard and no one else
+        if Arc::strong_count(&self.lock) == 2 {
+            locks.remove(&self.chat_id);
+        }
+    }
+}
+
+fn is_stale(updated_at: SystemTime, timeout: Duration) -> bool {
+    updated_at.elapsed().is_ok_and(|elapsed| elapsed > timeout)
+}
+
+/// Routes the received messages to the pending [`Bot::ask`] calls. Can be obtained via
+/// [`EventStream::hook_replies`](crate::EventStream::hook_replies).
+///
+/// Each pending call receives a single message, the replies are taken out of the event stream. If
+/// a [`NewChatItems`] event also carries other messages, they still reach the dispatcher. When the
+/// event stream gets dropped all pending calls fail with [`AskError::Cancelled`].
+#[derive(Default)]
+pub struct Replies {
+    state: Mutex<RepliesState>,
+    next_id: AtomicU64,
+}
+
+#[derive(Default)]
+struct RepliesState {
+    waiters: HashMap<ChatId, Pending>,
+    closed: bool,
+}
+
+struct Pending {
+    id: u64,
+    from: Option<MemberId>,
+    sender: tokio::sync::oneshot::Sender<Input>,
+}
+
+impl Replies {
+    /// Returns `None` when the event stream is dropped
+    fn wait(&self, chat_id: ChatId, from: Option<MemberId>) -> Option<Waiter<'_>> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let mut state = self.state.lock().unwrap();
+
+        if state.closed {
+            return None;
+        }
+
+        // Replaces the pending call in the chat, it gets cancelled
+        state.waiters.insert(chat_id, Pending { id, from, sender });
+
+        Some(Waiter {
+            replies: self,
+            chat_id,
+            id,
+            receiver,
+        })
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.waiters.clear();
+    }
+
+    fn capture(&self, event: &Event) -> Capture {
+        let Event::NewChatItems(ev) = event else {
+            return Capture::Pass;
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if state.waiters.is_empty() {
+            return Capture::Pass;
+        }
+
+        let mut delivered = Vec::new();
+
+        for (index, item) in ev.chat_items.iter().enumerate() {
+            if item.chat_item.content.rcv_msg_content().is_none() {
+                continue;
+            }
+
+            let Some(chat_id) = ChatId::from_chat_info(&item.chat_info) else {
+                continue;
+            };
+
+            let Some(pending) = state.waiters.get(&chat_id) else {
+                continue;
+            };
+
+            if let Some(from) = pending.from {
+                let sender = match &item.chat_item.chat_dir {
+                    CIDirection::GroupRcv { group_member, .. } => {
+                        Some(MemberId::from(group_member))
+                    }
+                    _ => None,
+                };
+
+                if sender != Some(from) {
+                    continue;
+                }
+            }
+
+            // Unregistered before delivering, the following messages reach the dispatcher
+            let pending = state.waiters.remove(&chat_id).unwrap();
+            let input = Input {
+                chat_id,
+                event: Arc::clone(ev),
+                index,
+            };
+
+            if pending.sender.send(input).is_ok() {
+                delivered.push(index);
+            }
+        }
+
+        if delivered.is_empty() {
+            Capture::Pass
+        } else if delivered.len() == ev.chat_items.len() {
+            Capture::Take
+        } else {
+            let mut rest = NewChatItems::clone(ev);
+            let mut index = 0;
+            rest.chat_items.retain(|_| {
+                index += 1;
+                !delivered.contains(&(index - 1))
+            });
+
+            Capture::Replace(Event::NewChatItems(Arc::new(rest)))
+        }
+    }
+}
+
+/// The [`Replies`] hook owned by the event stream. Cancels the pending calls when the stream is
+/// dropped.
+pub(crate) struct RepliesHook(Arc<Replies>);
+
+impl RepliesHook {
+    pub(crate) fn new(replies: Arc<Replies>) -> Self {
+        Self(replies)
+    }
+}
+
+impl Drop for RepliesHook {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+impl Hook for RepliesHook {
+    fn should_intercept(&self, kind: EventKind) -> bool {
+        kind == EventKind::NewChatItems
+    }
+
+    fn intercept_event(&self, event: Event) {
+        self.0.capture(&event);
+    }
+
+    fn capture_event(&self, event: Event) -> Capture {
+        self.0.capture(&event)
+    }
+}
+
+/// Unregisters the pending call on drop unless it was replaced or delivered
+struct Waiter<'a> {
+    replies: &'a Replies,
+    chat_id: ChatId,
+    id: u64,
+    receiver: tokio::sync::oneshot::Receiver<Input>,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        let mut state = self.replies.state.lock().unwrap();
+
+        if state
+            .waiters
+            .get(&self.chat_id)
+            .is_some_and(|pending| pending.id == self.id)
+        {
+            state.waiters.remove(&self.chat_id);
+        }
+    }
+}
+
+type AcceptAny = fn(Input) -> Result<Input, String>;
+
+/// An awaitable question created by [`Bot::ask`]. Awaiting sends the question and resolves with
+/// the next message received in the chat. In groups any member can answer unless
+/// [`Self::from_member`] is set.
+///
+/// # Deadlock warning
+///
+/// The reply only arrives when the event loop is running concurrently. Awaiting the question in a
+/// sequential handler blocks the event loop and the call never completes(or times out). Only ask
+/// from a concurrent handler or outside the event dispatching logic entirely.
+///
+/// With [`Dispatcher::max_in_flight`] a concurrent handler frees its slot while it waits for the
+/// reply. This only works when the handler awaits the question itself: a handler waiting for a
+/// spawned task that asks keeps its slot, and `n` such handlers take every slot of
+/// `max_in_flight(n)` so the replies are never read.
+///
+/// [`Dispatcher::max_in_flight`]: crate::dispatcher::Dispatcher::max_in_flight
+pub struct Ask<'a, C, V = AcceptAny> {
+    pub(crate) client: &'a C,
+    pub(crate) replies: &'a Replies,
+    pub(crate) chat_id: ChatId,
+    pub(crate) from: Option<MemberId>,
+    pub(crate) question: String,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) validator: V,
+}
+
+impl<'a, C> Ask<'a, C> {
+    pub(crate) fn new(
+        client: &'a C,
+        replies: &'a Replies,
+        chat_id: ChatId,
+        question: String,
+    ) -> Self {
+        Self {
+            client,
+            replies,
+            chat_id,
+            from: None,
+            question,
+            timeout: None,
+            validator: Ok,
+        }
+    }
+}
+
+impl<'a, C, V> Ask<'a, C, V> {
+    /// Only accept the replies of the group member, messages of other members reach the
+    /// dispatcher
+    pub fn from_member(mut self, member_id: impl Into<MemberId>) -> Self {
+        self.from = Some(member_id.into());
+        self
+    }
+
+    /// Fail with [`AskError::Timeout`] if no valid reply arrives in time
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Convert the reply into the result. On `Err` the error text is sent back to the chat and the
+    /// next reply is awaited.
+    pub fn validate<T, F, H>(self, validator: F) -> Ask<'a, C, F>
+    where
+        F: Fn(Input) -> Result<T, H>,
+        H: Into<String>,
+    {
+        Ask {
+            client: self.client,
+            replies: self.replies,
+            chat_id: self.chat_id,
+            from: self.from,
+            question: self.question,
+            timeout: self.timeout,
+            validator,
+        }
+    }
+}
+
+impl<'a, C, V, T, H> IntoFuture for Ask<'a, C, V>
+where
+    C: 'static + ClientApi,
+    C::Error: 'static + Send,
+    V: 'a + Send + Fn(Input) -> Result<T, H>,
+    T: 'a + Send,
+    H: Into<String>,
+{
+    type Output = Result<T, AskError<C::Error>>;
+    type IntoFuture = Pin<Box<dyn 'a + Send + Future<Output = Self::Output>>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            let deadline = self
+                .timeout
+                .map(|timeout| tokio::time::Instant::now() + timeout);
+            let mut message = self.question;
+
+            loop {
+                // Registered before sending to not miss fast replies. Each waiter receives a
+                // single reply, messages arriving during the validation reach the dispatcher.
+                let Some(mut waiter) = self.replies.wait(self.chat_id, self.from) else {
+                    return Err(AskError::Cancelled);
+                };
+
+                self.client
+                    .send_message(self.chat_id, message)
+                    .await
+                    .map_err(AskError::Api)?;
+
+                let _parked = dispatcher::park_handler();
+                let reply = match deadline {
+                    Some(deadline) => tokio::time::timeout_at(deadline, &mut waiter.receiver)
+                        .await
+                        .map_err(|_| AskError::Timeout)?,
+                    None => (&mut waiter.receiver).await,
+                };
+
+                message = match (self.validator)(reply.map_err(|_| AskError::Cancelled)?) {
+                    Ok(value) => return Ok(value),
+                    Err(hint) => hint.into(),
+                };
+            }
+        })
+    }
+}
+
+/// Error returned when an [`Ask`] future resolves unsuccessfully.
+#[derive(Debug)]
+pub enum AskError<E> {
+    /// No valid reply arrived in time
+    Timeout,
+    /// Another question was asked in the same chat or the event stream with the [`Replies`] hook
+    /// was dropped
+    Cancelled,
+    /// Sending the question or the validation hint failed
+    Api(E),
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for AskError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "no reply received in time"),
+            Self::Cancelled => write!(f, "the question was cancelled"),
+            Self::Api(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl<E> std::error::Error for AskError<E>
+where
+    E: 'static + std::error::Error,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Api(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::{commands::ApiSendMessages, id::UserId, mock, mock::MockError, test_utils};
+
+    fn event(items: Vec<serde_json::Value>) -> Event {
+        let ev = serde_json::json!({
+            "user": test_utils::user(),
+            "chatItems": items,
+        });
+
+        Event::NewChatItems(Arc::new(serde_json::from_value(ev).unwrap()))
+    }
+
+    fn item(id: i64, text: &str, from: Option<i64>) -> serde_json::Value {
+        let mut chat_item = test_utils::chat_item(id, text, true, serde_json::json!([]));
+
+        if let Some(member) = from {
+            chat_item["chatDir"] = serde_json::json!({
+                "type": "groupRcv",
+                "groupMember": test_utils::group_member(member),
+            });
+        }
+
+        serde_json::json!({
+            "chatInfo": test_utils::local_chat(),
+            "chatItem": chat_item,
+        })
+    }
+
+    fn message(id: i64, text: &str) -> Event {
+        event(vec![item(id, text, None)])
+    }
+
+    fn mock_client() -> mock::MockClient {
+        let (client, _, _) = mock::init();
+        client
+            .expect::<ApiSendMessages>()
+            .repeatedly()
+            .respond_json(serde_json::json!({
+                "type": "newChatItems",
+                "user": test_utils::user(),
+                "chatItems": [],
+            }));
+
+        client
+    }
+
+    #[tokio::test]
+    async fn ask_and_validate() {
+        let client = mock_client();
+        let chat = ChatId::Local(UserId::from_raw(1));
+        let replies = Replies::default();
+        assert!(matches!(
+            replies.capture(&message(1, "not awaited")),
+            Capture::Pass
+        ));
+
+        let ask = Ask::new(&client, &replies, chat, "How old are you?".to_owned())
+            .validate(|input| input.text().parse::<u32>().map_err(|_| "Send a number"))
+            .timeout(Duration::from_secs(10));
+
+        let (age, ()) = tokio::join!(ask.into_future(), async {
+            tokio::task::yield_now().await;
+            assert!(matches!(
+                replies.capture(&message(2, "old enough")),
+                Capture::Take
+            ));
+            // The reply is not validated yet, the next waiter is not registered
+            assert!(matches!(
+                replies.capture(&message(3, "in between")),
+                Capture::Pass
+            ));
+            tokio::task::yield_now().await;
+            assert!(matches!(replies.capture(&message(4, "42")), Capture::Take));
+        });
+        assert_eq!(age.unwrap(), 42);
+
+        let sent = client.sent_of::<ApiSendMessages>();
+        assert_eq!(sent.len(), 2);
+        assert!(sent[0].command.contains("How old are you?"));
+        assert!(sent[1].command.contains("Send a number"));
+
+        // Unregistered after completion
+        assert!(matches!(replies.capture(&message(5, "43")), Capture::Pass));
+
+        let ask = Ask::new(&client, &replies, chat, "Still there?".to_owned())
+            .timeout(Duration::from_millis(10));
+        assert!(matches!(ask.await, Err(AskError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn partial_capture_and_sender_filter() {
+        let client = mock_client();
+        let chat = ChatId::Local(UserId::from_raw(1));
+        let replies = Replies::default();
+
+        let ask = Ask::new(&client, &replies, chat, "Your name?".to_owned())
+            .from_member(MemberId::from_raw(7));
+
+        let (name, ()) = tokio::join!(ask.into_future(), async {
+            tokio::task::yield_now().await;
+
+            let batch = event(vec![
+                item(1, "I'm not asked", Some(8)),
+                item(2, "Alice", Some(7)),
+                item(3, "and more", Some(7)),
+            ]);
+
+            let Capture::Replace(Event::NewChatItems(rest)) = replies.capture(&batch) else {
+                panic!("Expected a partial capture");
+            };
+
+            let ids: Vec<_> = rest
+                .chat_items
+                .iter()
+                .map(|item| item.chat_item.meta.item_id)
+                .collect();
+            assert_eq!(ids, [1, 3]);
+        });
+
+        assert_eq!(name.unwrap().text(), "Alice");
+    }
+
+    #[tokio::test]
+    async fn cancelled_when_stream_dropped() {
+        let client = mock_client();
+        let chat = ChatId::Local(UserId::from_raw(1));
+        let (_, events, _) = mock::init();
+        let (replies, stream) = events.hook_replies();
+
+        let ask = Ask::new(&client, &replies, chat, "Anyone?".to_owned());
+
+        let (result, ()) = tokio::join!(ask.into_future(), async {
+            tokio::task::yield_now().await;
+            drop(stream);
+        });
+        assert!(matches!(result, Err(AskError::Cancelled)));
+
+        // Asking without the stream fails right away without sending the question
+        let ask = Ask::new(&client, &replies, chat, "Anyone?".to_owned());
+        assert!(matches!(ask.await, Err(AskError::Cancelled)));
+        assert_eq!(client.sent_of::<ApiSendMessages>().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ask_frees_in_flight_slot() {
+        let (_, events, sender) = mock::init();
+        let bot = mock::Bot::new(mock_client(), UserId::from_raw(1));
+        let (replies, events) = events.hook_replies();
+
+        sender.push_event(message(1, "start"));
+        sender.push_event(message(2, "Alice"));
+        sender.push_event(message(3, "after"));
+        sender.close();
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        events
+            .into_dispatcher((bot, replies, Arc::clone(&log)))
+            .on(async |ev: Arc<NewChatItems>, (bot, replies, log)| {
+                let text = ev.chat_items[0].chat_item.meta.item_text.clone();
+
+                if text == "start" {
+                    let chat = ChatId::Local(UserId::from_raw(1));
+                    let name = bot
+                        .ask(&replies, chat, "Your name?")
+                        .timeout(Duration::from_secs(1))
+                        .await
+                        .map_err(|e| MockError::Unexpected(e.to_string()))?;
+
+                    log.lock().unwrap().push(format!("name {}", name.text()));
+                } else {
+                    log.lock().unwrap().push(text);
+                }
+
+                Ok::<_, MockError>(StreamEvents::Continue)
+            })
+            .max_in_flight(1)
+            .dispatch()
+            .await
+            .unwrap();
+
+        let mut log = log.lock().unwrap().clone();
+        log.sort();
+
+        assert_eq!(log, ["after", "name Alice"]);
+    }
+
+    fn received(ev: Event) -> Arc<NewChatItems> {
+        let Event::NewChatItems(ev) = ev else {
+            unreachable!()
+        };
+
+        ev
+    }
+
+    /// Collects the message texts, "exit" and "stop" finish the dialogue
+    fn collector() -> Dialogue<Vec<String>, mock::MockClient, mock::MockError> {
+        Dialogue::new(async |mut state: Vec<String>, input: Input, _| {
+            let transition = match input.text() {
+                "exit" => Transition::Exit,
+                "stop" => Transition::Break,
+                text => {
+                    state.push(text.to_owned());
+                    state.into()
+                }
+            };
+
+            Ok(transition)
+        })
+    }
+
+    #[tokio::test]
+    async fn transitions() {
+        let bot = mock::Bot::new(mock_client(), UserId::from_raw(1));
+        let chat = ChatId::Local(UserId::from_raw(1));
+        let dialogue = collector();
+
+        let result = dialogue
+            .route(
+                received(event(vec![item(1, "a", None), item(2, "b", None)])),
+                bot.clone(),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(result, StreamEvents::Continue));
+        assert_eq!(dialogue.state(chat).await.unwrap().unwrap(), ["a", "b"]);
+
+        // Exit starts the dialogue over
+        dialogue
+            .route(
+                received(event(vec![item(3, "exit", None), item(4, "c", None)])),
+                bot.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(dialogue.state(chat).await.unwrap().unwrap(), ["c"]);
+
+        // Break drops the state and skips the rest of the event
+        let result = dialogue
+            .route(
+                received(event(vec![item(5, "stop", None), item(6, "d", None)])),
+                bot.clone(),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(result, StreamEvents::Break));
+        assert!(dialogue.state(chat).await.unwrap().is_none());
+        assert!(dialogue.storage().is_empty());
+
+        dialogue
+            .set_state(chat, vec!["forced".to_owned()])
+            .await
+            .unwrap();
+        dialogue
+            .route(received(message(7, "e")), bot)
+            .await
+            .unwrap();
+        assert_eq!(
+            dialogue.state(chat).await.unwrap().unwrap(),
+            ["forced", "e"]
+        );
+
+        dialogue.reset(chat).await.unwrap();
+        assert!(dialogue.state(chat).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn timeout_resets_state() {
+        let bot = mock::Bot::new(mock_client(), UserId::from_raw(1));
+        let chat = ChatId::Local(UserId::from_raw(1));
+        let dialogue = collector().timeout(Duration::from_millis(20));
+
+        dialogue
+            .route(received(message(1, "a")), bot.clone())
+            .await
+            .unwrap();
+        assert_eq!(dialogue.state(chat).await.unwrap().unwrap(), ["a"]);
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert!(dialogue.state(chat).await.unwrap().is_none());
+        // Stale states stay in the storage until purged or overwritten
+        assert_eq!(dialogue.storage().len(), 1);
+
+        dialogue
+            .route(received(message(2, "b")), bot.clone())
+            .await
+            .unwrap();
+        assert_eq!(dialogue.state(chat).await.unwrap().unwrap(), ["b"]);
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        dialogue.storage().purge_stale(Duration::from_millis(20));
+        assert!(dialogue.storage().is_empty());
+    }
+
+    #[tokio::test]
+    async fn chats_are_locked_separately() {
+        let bot = mock::Bot::new(mock_client(), UserId::from_raw(1));
+
+        let active = Arc::new(AtomicU64::new(0));
+        let overlaps = Arc::new(AtomicU64::new(0));
+
+        let dialogue: Dialogue<(), _, mock::MockError> = Dialogue::new({
+            let active = Arc::clone(&active);
+            let overlaps = Arc::clone(&overlaps);
+
+            move |(), _, _| {
+                let active = Arc::clone(&active);
+                let overlaps = Arc::clone(&overlaps);
+
+                async move {
+                    if active.fetch_add(1, Ordering::SeqCst) > 0 {
+                        overlaps.fetch_add(1, Ordering::SeqCst);
+                    }
+
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    Ok(().into())
+                }
+            }
+        });
+
+        let group_message = |id, text| {
+            let mut item = item(id, text, Some(2));
+            item["chatInfo"] = serde_json::json!({
+                "type": "group",
+                "groupInfo": test_utils::group_info(1),
+            });
+            received(event(vec![item]))
+        };
+
+        // The same chat is handled one message at a time
+        tokio::try_join!(
+            dialogue.route(received(message(1, "a")), bot.clone()),
+            dialogue.route(received(message(2, "b")), bot.clone()),
+            dialogue.route(received(message(3, "c")), bot.clone()),
+        )
+        .unwrap();
+        assert_eq!(overlaps.load(Ordering::SeqCst), 0);
+
+        // Different chats are not
+        tokio::try_join!(
+            dialogue.route(received(message(4, "a")), bot.clone()),
+            dialogue.route(group_message(5, "b"), bot.clone()),
+        )
+        .unwrap();
+        assert_eq!(overlaps.load(Ordering::SeqCst), 1);
+
+        assert!(dialogue.inner.locks.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,634 @@
+//! Contact and group directory cache.
+//!
+//! [`Bot::contacts`](crate::bot::Bot::contacts) and [`Bot::groups`](crate::bot::Bot::groups) query
+//! the SimpleX instance on every call. The [`Directory`] keeps a local copy of contacts, groups and
+//! group members updated from the events, so handlers can look up display names and member roles
+//! without round trips.
+//!
+//! # Usage
+//!
+//! ```ignore
+//! let (directory, events) = events.hook_directory();
+//! bot.load_directory(&directory).await?;
+//!
+//! // In handlers
+//! if let Some(member) = directory.member(group_id, member_id) {
+//!     println!("{} is {:?}", member.member_profile.display_name, member.member_role);
+//! }
+//! ```
+//!
+//! Load the directory after hooking it into the stream so no updates are lost between loading and
+//! dispatching. Events that arrive while [`Directory::load`] is querying take precedence over the
+//! loaded snapshot. Contacts and groups created by the bot's own commands are added once SimpleX
+//! reports them via events(e.g. [`Event::ContactConnected`], [`Event::UserJoinedGroup`]), use
+//! [`Directory::load`] to refresh everything manually.
+
+use simploxide_api_types::{
+    Contact, GroupInfo, GroupMember, GroupMemberRole,
+    client_api::ClientApi,
+    events::{Event, EventKind},
+};
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    Hook,
+    ext::ClientApiExt as _,
+    id::{ChatId, ContactId, GroupId, MemberId, UserId},
+};
+
+/// Can be obtained via [`EventStream::hook_directory`](crate::EventStream::hook_directory).
+#[derive(Default)]
+pub struct Directory {
+    state: RwLock<State>,
+}
+
+#[derive(Default)]
+struct State {
+    contacts: HashMap<ContactId, Arc<Contact>>,
+    groups: HashMap<GroupId, Group>,
+    /// The number of [`Directory::load`] calls in progress
+    loads: usize,
+    /// Entries updated by events while loading
+    touched: Touched,
+}
+
+#[derive(Default)]
+struct Touched {
+    contacts: HashSet<ContactId>,
+    groups: HashSet<GroupId>,
+    members: HashSet<(GroupId, MemberId)>,
+}
+
+#[derive(Clone)]
+struct Group {
+    info: Arc<GroupInfo>,
+    members: HashMap<MemberId, Arc<GroupMember>>,
+}
+
+impl Directory {
+    /// Refresh the cached data with contacts, groups and group members of the user. Entries updated
+    /// by events while the load is in progress keep their event state.
+    pub async fn load<C: ClientApi>(&self, client: &C, user_id: UserId) -> Result<(), C::Error> {
+        let _guard = LoadGuard::new(self);
+
+        let contacts = client.contacts(user_id).await?;
+        let groups = client.groups(user_id).await?;
+
+        let members = futures::future::try_join_all(
+            groups
+                .iter()
+                .map(|group| client.list_members(GroupId::from(group))),
+        )
+        .await?;
+
+        let contacts = contacts
+            .into_iter()
+            .map(|contact| (ContactId::from(&contact), Arc::new(contact)))
+            .collect();
+
+        let groups = groups
+            .into_iter()
+            .zip(members)
+            .map(|(info, members)| {
+                let group = Group {
+                    info: Arc::new(info),
+                    members: members
+                        .into_iter()
+                        .map(|member| (MemberId::from(&member), Arc::new(member)))
+                        .collect(),
+                };
+
+                (GroupId::from(&*group.info), group)
+            })
+            .collect();
+
+        self.state.write().unwrap().merge(contacts, groups);
+        Ok(())
+    }
+
+    pub fn contact(&self, contact_id: impl Into<ContactId>) -> Option<Arc<Contact>> {
+        let state = self.state.read().unwrap();
+        state.contacts.get(&contact_id.into()).cloned()
+    }
+
+    /// Find the contact by its local or profile display name
+    pub fn contact_by_name(&self, name: &str) -> Option<Arc<Contact>> {
+        let state = self.state.read().unwrap();
+
+        state
+            .contacts
+            .values()
+            .find(|contact| {
+                contact.local_display_name == name || contact.profile.display_name == name
+            })
+            .cloned()
+    }
+
+    pub fn contacts(&self) -> Vec<Arc<Contact>> {
+        let state = self.state.read().unwrap();
+        state.contacts.values().cloned().collect()
+    }
+
+    pub fn group(&self, group_id: impl Into<GroupId>) -> Option<Arc<GroupInfo>> {
+        let state = self.state.read().unwrap();
+        state
+            .groups
+            .get(&group_id.into())
+            .map(|group| Arc::clone(&group.info))
+    }
+
+    /// Find the group by its local or profile display name
+    pub fn group_by_name(&self, name: &str) -> Option<Arc<GroupInfo>> {
+        let state = self.state.read().unwrap();
+
+        state
+            .groups
+            .values()
+            .find(|group| {
+                group.info.local_display_name == name
+                    || group.info.group_profile.display_name == name
+            })
+            .map(|group| Arc::clone(&group.info))
+    }
+
+    pub fn groups(&self) -> Vec<Arc<GroupInfo>> {
+        let state = self.state.read().unwrap();
+        state
+            .groups
+            .values()
+            .map(|group| Arc::clone(&group.info))
+            .collect()
+    }
+
+    pub fn member(
+        &self,
+        group_id: impl Into<GroupId>,
+        member_id: impl Into<MemberId>,
+    ) -> Option<Arc<GroupMember>> {
+        let state = self.state.read().unwrap();
+        state
+            .groups
+            .get(&group_id.into())?
+            .members
+            .get(&member_id.into())
+            .cloned()
+    }
+
+    /// Find the group member by the local or profile display name
+    pub fn member_by_name(
+        &self,
+        group_id: impl Into<GroupId>,
+        name: &str,
+    ) -> Option<Arc<GroupMember>> {
+        let state = self.state.read().unwrap();
+
+        state
+            .groups
+            .get(&group_id.into())?
+            .members
+            .values()
+            .find(|member| {
+                member.local_display_name == name || member.member_profile.display_name == name
+            })
+            .cloned()
+    }
+
+    /// Known members of the group, the user's membership is not included
+    pub fn members(&self, group_id: impl Into<GroupId>) -> Vec<Arc<GroupMember>> {
+        let state = self.state.read().unwrap();
+
+        state
+            .groups
+            .get(&group_id.into())
+            .map(|group| group.members.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn member_role(
+        &self,
+        group_id: impl Into<GroupId>,
+        member_id: impl Into<MemberId>,
+    ) -> Option<GroupMemberRole> {
+        self.member(group_id, member_id)
+            .map(|member| member.member_role)
+    }
+
+    /// The display name of the contact or group
+    pub fn display_name(&self, chat_id: ChatId) -> Option<String> {
+        match chat_id {
+            ChatId::Direct(contact_id) => self
+                .contact(contact_id)
+                .map(|contact| contact.profile.display_name.clone()),
+            ChatId::Group { id, .. } => self
+                .group(id)
+                .map(|group| group.group_profile.display_name.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl Hook for Directory {
+    fn should_intercept(&self, kind: EventKind) -> bool {
+        const EVENT_KINDS: [EventKind; 17] = [
+            EventKind::ContactConnected,
+            EventKind::ContactSndReady,
+            EventKind::ContactUpdated,
+            EventKind::ContactDeletedByContact,
+            EventKind::UserJoinedGroup,
+            EventKind::GroupUpdated,
+            EventKind::GroupDeleted,
+            EventKind::DeletedMemberUser,
+            EventKind::JoinedGroupMember,
+            EventKind::ConnectedToGroupMember,
+            EventKind::MemberAcceptedByOther,
+            EventKind::MemberRole,
+            EventKind::MemberBlockedForAll,
+            EventKind::GroupMemberUpdated,
+            EventKind::LeftMember,
+            EventKind::DeletedMember,
+            EventKind::NewMemberContactReceivedInv,
+        ];
+
+        EVENT_KINDS.contains(&kind)
+    }
+
+    fn intercept_event(&self, event: Event) {
+        let mut state = self.state.write().unwrap();
+
+        match event {
+            Event::ContactConnected(ev) => state.upsert_contact(&ev.contact),
+            Event::ContactSndReady(ev) => state.upsert_contact(&ev.contact),
+            Event::ContactUpdated(ev) => state.upsert_contact(&ev.to_contact),
+            Event::NewMemberContactReceivedInv(ev) => state.upsert_contact(&ev.contact),
+            Event::ContactDeletedByContact(ev) => state.remove_contact(&ev.contact),
+            Event::UserJoinedGroup(ev) => {
+                state.upsert_member(&ev.group_info, &ev.host_member);
+            }
+            Event::GroupUpdated(ev) => {
+                state.upsert_group(&ev.to_group);
+            }
+            Event::GroupDeleted(ev) => state.remove_group(&ev.group_info),
+            Event::DeletedMemberUser(ev) => state.remove_group(&ev.group_info),
+            Event::JoinedGroupMember(ev) => state.upsert_member(&ev.group_info, &ev.member),
+            Event::ConnectedToGroupMember(ev) => {
+                state.upsert_member(&ev.group_info, &ev.member);
+
+                if let Some(contact) = &ev.member_contact {
+                    state.upsert_contact(contact);
+                }
+            }
+            Event::MemberAcceptedByOther(ev) => state.upsert_member(&ev.group_info, &ev.member),
+            Event::MemberRole(ev) => {
+                let mut member = ev.member.clone();
+                member.member_role = ev.to_role;
+                state.upsert_member(&ev.group_info, &member);
+            }
+            Event::MemberBlockedForAll(ev) => state.upsert_member(&ev.group_info, &ev.member),
+            Event::GroupMemberUpdated(ev) => state.upsert_member(&ev.group_info, &ev.to_member),
+            Event::LeftMember(ev) => state.remove_member(&ev.group_info, &ev.member),
+            Event::DeletedMember(ev) => state.remove_member(&ev.group_info, &ev.deleted_member),
+            _ => (),
+        }
+    }
+}
+
+impl State {
+    /// Replace the cached data with the loaded snapshot except for the entries touched by events
+    fn merge(
+        &mut self,
+        mut contacts: HashMap<ContactId, Arc<Contact>>,
+        mut groups: HashMap<GroupId, Group>,
+    ) {
+        for contact_id in &self.touched.contacts {
+            match self.contacts.get(contact_id) {
+                Some(contact) => contacts.insert(*contact_id, Arc::clone(contact)),
+                None => contacts.remove(contact_id),
+            };
+        }
+
+        for group_id in &self.touched.groups {
+            match self.groups.get(group_id) {
+                Some(current) => match groups.get_mut(group_id) {
+                    Some(group) => group.info = Arc::clone(&current.info),
+                    None => {
+                        groups.insert(*group_id, current.clone());
+                    }
+                },
+                None => {
+                    groups.remove(group_id);
+                }
+            }
+        }
+
+        for (group_id, member_id) in &self.touched.members {
+            let Some(group) = groups.get_mut(group_id) else {
+                continue;
+            };
+
+            match self
+                .groups
+                .get(group_id)
+                .and_then(|current| current.members.get(member_id))
+            {
+                Some(member) => group.members.insert(*member_id, Arc::clone(member)),
+                None => group.members.remove(member_id),
+            };
+        }
+
+        self.contacts = contacts;
+        self.groups = groups;
+    }
+
+    fn is_loading(&self) -> bool {
+        self.loads > 0
+    }
+
+    fn upsert_contact(&mut self, contact: &Contact) {
+        let contact_id = ContactId::from(contact);
+
+        if self.is_loading() {
+            self.touched.contacts.insert(contact_id);
+        }
+
+        self.contacts.insert(contact_id, Arc::new(contact.clone()));
+    }
+
+    fn remove_contact(&mut self, contact: &Contact) {
+        let contact_id = ContactId::from(contact);
+
+        if self.is_loading() {
+            self.touched.contacts.insert(contact_id);
+        }
+
+        self.contacts.remove(&contact_id);
+    }
+
+    fn remove_group(&mut self, info: &GroupInfo) {
+        let group_id = GroupId::from(info);
+
+        if self.is_loading() {
+            self.touched.groups.insert(group_id);
+        }
+
+        self.groups.remove(&group_id);
+    }
+
+    fn upsert_group(&mut self, info: &GroupInfo) -> &mut Group {
+        let info = Arc::new(info.clone());
+
+        if self.is_loading() {
+            self.touched.groups.insert(GroupId::from(&*info));
+        }
+
+        let group = self
+            .groups
+            .entry(GroupId::from(&*info))
+            .or_insert_with(|| Group {
+                info: Arc::clone(&info),
+                members: HashMap::new(),
+            });
+
+        group.info = info;
+        group
+    }
+
+    fn upsert_member(&mut self, info: &GroupInfo, member: &GroupMember) {
+        let member_id = MemberId::from(member);
+        self.touch_member(info, member_id);
+
+        self.upsert_group(info)
+            .members
+            .insert(member_id, Arc::new(member.clone()));
+    }
+
+    fn remove_member(&mut self, info: &GroupInfo, member: &GroupMember) {
+        let member_id = MemberId::from(member);
+        self.touch_member(info, member_id);
+
+        self.upsert_group(info).members.remove(&member_id);
+    }
+
+    fn touch_member(&mut self, info: &GroupInfo, member_id: MemberId) {
+        if self.is_loading() {
+            self.touched
+                .members
+                .insert((GroupId::from(info), member_id));
+        }
+    }
+}
+
+/// Tracks a [`Directory::load`] in progress, the touched entries are forgotten once the last load
+/// completes or gets cancelled
+struct LoadGuard<'a>(&'a Directory);
+
+impl<'a> LoadGuard<'a> {
+    fn new(directory: &'a Directory) -> Self {
+        directory.state.write().unwrap().loads += 1;
+        Self(directory)
+    }
+}
+
+impl Drop for LoadGuard<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.state.write().unwrap();
+        state.loads -= 1;
+
+        if state.loads == 0 {
+            state.touched = Touched::default();
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{ApiListContacts, ApiListGroups, ApiListMembers},
+        mock, test_utils,
+    };
+    use simploxide_api_types::JsonObject;
+
+    fn event(kind: &str, mut fields: serde_json::Value) -> Event {
+        fields["type"] = kind.into();
+        fields["user"] = test_utils::user();
+        serde_json::from_value(fields).unwrap()
+    }
+
+    fn renamed(contact_id: i64, name: &str) -> Event {
+        let mut contact = test_utils::contact(contact_id);
+        contact["profile"]["displayName"] = name.into();
+
+        event(
+            "contactUpdated",
+            serde_json::json!({
+                "fromContact": test_utils::contact(contact_id),
+                "toContact": contact,
+            }),
+        )
+    }
+
+    fn member_event(kind: &str, member_id: i64) -> Event {
+        event(
+            kind,
+            serde_json::json!({
+                "groupInfo": test_utils::group_info(1),
+                "member": test_utils::group_member(member_id),
+            }),
+        )
+    }
+
+    fn expect_load(client: &mock::MockClient) {
+        client
+            .expect::<ApiListContacts>()
+            .respond_json(serde_json::json!({
+                "type": "contactsList",
+                "user": test_utils::user(),
+                "contacts": [test_utils::contact(1), test_utils::contact(2)],
+            }));
+
+        client
+            .expect::<ApiListMembers>()
+            .respond_json(serde_json::json!({
+                "type": "groupMembers",
+                "user": test_utils::user(),
+                "group": {
+                    "groupInfo": test_utils::group_info(1),
+                    "members": [test_utils::group_member(2), test_utils::group_member(3)],
+                },
+            }));
+    }
+
+    fn groups_list() -> JsonObject {
+        serde_json::json!({
+            "type": "groupsList",
+            "user": test_utils::user(),
+            "groups": [test_utils::group_info(1)],
+        })
+    }
+
+    fn member_ids(directory: &Directory) -> Vec<i64> {
+        let mut ids: Vec<i64> = directory
+            .members(GroupId::from_raw(1))
+            .iter()
+            .map(|member| member.group_member_id)
+            .collect();
+
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn events_update_directory() {
+        let directory = Directory::default();
+        let group_id = GroupId::from_raw(1);
+
+        directory.intercept_event(member_event("joinedGroupMember", 2));
+        directory.intercept_event(member_event("joinedGroupMember", 3));
+        assert_eq!(
+            directory.group(group_id).unwrap().local_display_name,
+            "group1"
+        );
+        assert_eq!(member_ids(&directory), [2, 3]);
+        assert!(directory.member_by_name(group_id, "member3").is_some());
+
+        directory.intercept_event(member_event("leftMember", 3));
+        assert_eq!(member_ids(&directory), [2]);
+
+        directory.intercept_event(renamed(4, "Alice"));
+        let contact = directory.contact_by_name("Alice").unwrap();
+        assert_eq!(contact.contact_id, 4);
+        assert_eq!(
+            directory
+                .display_name(ChatId::Direct(ContactId::from_raw(4)))
+                .as_deref(),
+            Some("Alice")
+        );
+
+        directory.intercept_event(event(
+            "contactDeletedByContact",
+            serde_json::json!({ "contact": test_utils::contact(4) }),
+        ));
+        assert!(directory.contacts().is_empty());
+
+        directory.intercept_event(event(
+            "groupDeleted",
+            serde_json::json!({
+                "groupInfo": test_utils::group_info(1),
+                "member": test_utils::group_member(2),
+            }),
+        ));
+        assert!(directory.groups().is_empty());
+        assert!(directory.member(group_id, MemberId::from_raw(2)).is_none());
+    }
+
+    #[tokio::test]
+    async fn load_keeps_event_updates() {
+        let (client, _events, _sender) = mock::init();
+        let directory = Arc::new(Directory::default());
+        let user_id = UserId::from_raw(1);
+
+        // Events arrive after the contacts were listed but before the members were
+        expect_load(&client);
+        let hook = Arc::clone(&directory);
+        client.expect::<ApiListGroups>().respond_fn(move |_| {
+            hook.intercept_event(renamed(2, "Bob"));
+            hook.intercept_event(event(
+                "contactDeletedByContact",
+                serde_json::json!({ "contact": test_utils::contact(1) }),
+            ));
+            hook.intercept_event(member_event("joinedGroupMember", 5));
+            hook.intercept_event(member_event("leftMember", 3));
+
+            groups_list()
+        });
+
+        directory.load(&client, user_id).await.unwrap();
+
+        assert!(directory.contact(ContactId::from_raw(1)).is_none());
+        assert_eq!(
+            directory
+                .contact(ContactId::from_raw(2))
+                .unwrap()
+                .profile
+                .display_name,
+            "Bob"
+        );
+        assert_eq!(member_ids(&directory), [2, 5]);
+
+        // Nothing is touched on the next load, the snapshot wins
+        expect_load(&client);
+        client.expect::<ApiListGroups>().respond_json(groups_list());
+        directory.load(&client, user_id).await.unwrap();
+
+        assert_eq!(directory.contacts().len(), 2);
+        assert_eq!(
+            directory
+                .contact(ContactId::from_raw(2))
+                .unwrap()
+                .profile
+                .display_name,
+            "contact2"
+        );
+        assert_eq!(member_ids(&directory), [2, 3]);
+        client.assert_satisfied();
+    }
+
+    #[tokio::test]
+    async fn failed_load_keeps_state() {
+        let (client, _events, _sender) = mock::init();
+        let directory = Directory::default();
+
+        directory.intercept_event(member_event("joinedGroupMember", 2));
+        assert!(directory.load(&client, UserId::from_raw(1)).await.is_err());
+
+        assert_eq!(member_ids(&directory), [2]);
+
+        let state = directory.state.read().unwrap();
+        assert_eq!(state.loads, 0);
+        assert!(state.touched.members.is_empty());
+    }
+}
@@ -0,0 +1,670 @@
+//! Text command router handling `/command arg1 arg2` messages.
+//!
+//! [`Commands`] registers handlers per command name, parses arguments into typed values with
+//! [`CommandArgs`] and replies with help to unknown commands and bad arguments. The router is
+//! installed as a regular [`NewChatItems`] handler and can publish the command list into the bot
+//! profile so SimpleX clients show the command menu.
+//!
+//! ```ignore
+//! let commands = Commands::new()
+//!     .command(CommandInfo::new("start").label("Show the greeting"), start)
+//!     .command(
+//!         CommandInfo::new("square").label("Square a number").params("<number>"),
+//!         async |cmd: Invocation<(i64,)>, bot: ws::Bot| {
+//!             let (n,) = cmd.args;
+//!             bot.send_msg(cmd.chat_id, format!("{}", n * n)).reply_to(cmd.item()).await?;
+//!             ClientResult::Ok(StreamEvents::Continue)
+//!         },
+//!     )
+//!     .plain_text(echo);
+//!
+//! commands.publish(&bot).await?;
+//!
+//! events.into_dispatcher(bot)
+//!     .on(commands.handler())
+//!     .dispatch()
+//!     .await?;
+//! ```
+
+use simploxide_api_types::{
+    ChatBotCommand, ChatItem, MsgContent, events::NewChatItems, responses::ApiUpdateProfileResponse,
+};
+
+use std::{collections::HashMap, fmt::Write as _, future::Future, pin::Pin, sync::Arc};
+
+use crate::{ClientApi, StreamEvents, bot::Bot, id::ChatId, messages::MsgContentExt as _};
+
+pub type CommandFuture<E> = Pin<Box<dyn Send + Future<Output = Result<StreamEvents, E>>>>;
+
+type BoxHandler<C, E> =
+    Box<dyn Send + Sync + Fn(Invocation<&str>, Bot<C>) -> Result<CommandFuture<E>, String>>;
+
+/// A parsed command or a plain text message passed into handlers
+pub struct Invocation<A> {
+    /// The chat where the message was received
+    pub chat_id: ChatId,
+    /// Command name without the prefix. Empty for plain text messages
+    pub name: String,
+    /// Parsed arguments
+    pub args: A,
+    event: Arc<NewChatItems>,
+    index: usize,
+}
+
+impl<A> Invocation<A> {
+    /// The message containing the command. Can be used with
+    /// [`MessageBuilder::reply_to`](crate::messages::MessageBuilder::reply_to)
+    pub fn item(&self) -> &ChatItem {
+        &self.event.chat_items[self.index].chat_item
+    }
+
+    /// The full event the command was received in
+    pub fn event(&self) -> &Arc<NewChatItems> {
+        &self.event
+    }
+
+    /// The full message text
+    pub fn text(&self) -> &str {
+        message_text(self.item()).unwrap_or_default()
+    }
+
+    fn map<B>(self, args: B) -> Invocation<B> {
+        Invocation {
+            chat_id: self.chat_id,
+            name: self.name,
+            args,
+            event: self.event,
+            index: self.index,
+        }
+    }
+}
+
+/// Command arguments parsed from the text following the command name.
+///
+/// Implemented for:
+/// - `()`: the command doesn't take arguments
+/// - Tuples of up to 4 [`FromStr`](std::str::FromStr) values separated by whitespace, trailing
+///   [`Opt`] values can be omitted
+/// - [`Rest`]: the whole argument string
+///
+/// Implement it manually to parse arguments into custom structs.
+pub trait CommandArgs: Sized {
+    fn parse(args: &str) -> Result<Self, String>;
+}
+
+impl CommandArgs for () {
+    fn parse(args: &str) -> Result<Self, String> {
+        if args.trim().is_empty() {
+            Ok(())
+        } else {
+            Err("The command doesn't take arguments".to_owned())
+        }
+    }
+}
+
+/// All the text after the command name with surrounding whitespace trimmed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rest(pub String);
+
+impl CommandArgs for Rest {
+    fn parse(args: &str) -> Result<Self, String> {
+        Ok(Self(args.trim().to_owned()))
+    }
+}
+
+/// A single tuple argument. Implemented for all [`FromStr`](std::str::FromStr) types and [`Opt`]
+pub trait CommandArg: Sized {
+    fn parse_arg(arg: Option<&str>) -> Result<Self, String>;
+}
+
+impl<T> CommandArg for T
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    fn parse_arg(arg: Option<&str>) -> Result<Self, String> {
+        let arg = arg.ok_or_else(|| "Not enough arguments".to_owned())?;
+        arg.parse()
+            .map_err(|e| format!("Invalid argument {arg:?}: {e}"))
+    }
+}
+
+macro_rules! impl_tuple_args {
+    ($($arg:ident),+) => {
+        impl<$($arg: CommandArg),+> CommandArgs for ($($arg,)+) {
+            fn parse(args: &str) -> Result<Self, String> {
+                let mut tokens = args.split_whitespace();
+                let parsed = ($($arg::parse_arg(tokens.next())?,)+);
+
+                if tokens.next().is_some() {
+                    return Err("Too many arguments".to_owned());
+                }
+
+                Ok(parsed)
+            }
+        }
+    };
+}
+
+impl_tuple_args!(A1);
+impl_tuple_args!(A1, A2);
+impl_tuple_args!(A1, A2, A3);
+impl_tuple_args!(A1, A2, A3, A4);
+
+/// Optional trailing arguments. `Option<T>` cannot implement [`CommandArg`] directly because
+/// of the blanket implementation for [`FromStr`](std::str::FromStr) types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Opt<T>(pub Option<T>);
+
+impl<T> CommandArg for Opt<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    fn parse_arg(arg: Option<&str>) -> Result<Self, String> {
+        arg.map(|arg| T::parse_arg(Some(arg))).transpose().map(Self)
+    }
+}
+
+/// Command description shown in the help message and in the SimpleX clients command menu
+#[derive(Debug, Clone)]
+pub struct CommandInfo {
+    name: String,
+    label: Option<String>,
+    params: Option<String>,
+    hidden: bool,
+}
+
+impl CommandInfo {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            label: None,
+            params: None,
+            hidden: false,
+        }
+    }
+
+    /// Human readable command description
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Parameters hint, e.g. `<number>`
+    pub fn params(mut self, params: impl Into<String>) -> Self {
+        self.params = Some(params.into());
+        self
+    }
+
+    /// Don't show the command in the help message and in the command menu
+    pub fn hidden(mut self) -> Self {
+        self.hidden = true;
+        self
+    }
+
+    fn usage(&self, prefix: &str) -> String {
+        let mut usage = format!("{prefix}{}", self.name);
+
+        if let Some(params) = &self.params {
+            let _ = write!(usage, " {params}");
+        }
+
+        usage
+    }
+}
+
+impl<S: Into<String>> From<S> for CommandInfo {
+    fn from(name: S) -> Self {
+        Self::new(name)
+    }
+}
+
+/// A command router. Cheap to clone.
+pub struct Commands<C, E> {
+    inner: Arc<Router<C, E>>,
+}
+
+impl<C, E> Clone for Commands<C, E> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+struct Router<C, E> {
+    prefix: String,
+    infos: Vec<CommandInfo>,
+    handlers: HashMap<String, BoxHandler<C, E>>,
+    unknown: Option<BoxHandler<C, E>>,
+    plain_text: Option<BoxHandler<C, E>>,
+}
+
+impl<C, E> Default for Commands<C, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C, E> Commands<C, E> {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Router {
+                prefix: "/".to_owned(),
+                infos: Vec::new(),
+                handlers: HashMap::new(),
+                unknown: None,
+                plain_text: None,
+            }),
+        }
+    }
+
+    /// The available commands in the registration order
+    pub fn infos(&self) -> &[CommandInfo] {
+        &self.inner.infos
+    }
+
+    /// Builds the help message listing all visible commands
+    pub fn help(&self) -> String {
+        let mut help = "Available commands:".to_owned();
+
+        for info in self.inner.infos.iter().filter(|info| !info.hidden) {
+            let _ = write!(help, "\n{}", info.usage(&self.inner.prefix));
+
+            if let Some(label) = &info.label {
+                let _ = write!(help, " - {label}");
+            }
+        }
+
+        help
+    }
+
+    /// The command list in the format of the `commands` profile preference
+    pub fn menu(&self) -> Vec<ChatBotCommand> {
+        self.inner
+            .infos
+            .iter()
+            .filter(|info| !info.hidden)
+            .map(|info| {
+                ChatBotCommand::make_command(
+                    info.name.clone(),
+                    info.label.clone().unwrap_or_else(|| info.name.clone()),
+                    info.params.clone(),
+                )
+            })
+            .collect()
+    }
+
+    fn router_mut(&mut self) -> &mut Router<C, E> {
+        Arc::get_mut(&mut self.inner).expect("Commands must be configured before cloning")
+    }
+}
+
+impl<C, E> Commands<C, E>
+where
+    C: 'static + Clone + Send + ClientApi,
+    C::Error: 'static + Send,
+    E: 'static + Send + From<C::Error>,
+{
+    /// Change the command prefix. Default: `/`
+    ///
+    /// # Panics
+    ///
+    /// When called after the router was cloned
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.router_mut().prefix = prefix.into();
+        self
+    }
+
+    /// Register a command handler. Handlers with the same name override each other.
+    ///
+    /// - The handler signature is `AsyncFn(cmd: Invocation<{Args}>, bot: Bot<C>) -> Result<StreamEvents, E>`
+    /// - `{Args}` implements [`CommandArgs`]. When arguments cannot be parsed the router replies
+    ///   with the command usage instead of calling the handler
+    ///
+    /// # Panics
+    ///
+    /// When called after the router was cloned
+    pub fn command<A, F, Fut>(mut self, info: impl Into<CommandInfo>, f: F) -> Self
+    where
+        A: CommandArgs,
+        F: 'static + Send + Sync + Fn(Invocation<A>, Bot<C>) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<StreamEvents, E>>,
+    {
+        let info = info.into();
+        let router = self.router_mut();
+
+        router.infos.retain(|old| old.name != info.name);
+        router.handlers.insert(
+            info.name.clone(),
+            Box::new(move |inv, bot| {
+                let args = A::parse(inv.args)?;
+                Ok(Box::pin(f(inv.map(args), bot)))
+            }),
+        );
+        router.infos.push(info);
+
+        self
+    }
+
+    /// Handle unknown commands. The `args` contain the whole text after the command name. By
+    /// default the router replies with the [help](Self::help) message.
+    ///
+    /// # Panics
+    ///
+    /// When called after the router was cloned
+    pub fn unknown<F, Fut>(mut self, f: F) -> Self
+    where
+        F: 'static + Send + Sync + Fn(Invocation<Rest>, Bot<C>) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<StreamEvents, E>>,
+    {
+        self.router_mut().unknown = Some(Box::new(move |inv, bot| {
+            let args = Rest::parse(inv.args)?;
+            Ok(Box::pin(f(inv.map(args), bot)))
+        }));
+
+        self
+    }
+
+    /// Handle messages that are not commands. The `args` contain the whole message text. Such
+    /// messages are ignored by default.
+    ///
+    /// # Panics
+    ///
+    /// When called after the router was cloned
+    pub fn plain_text<F, Fut>(mut self, f: F) -> Self
+    where
+        F: 'static + Send + Sync + Fn(Invocation<Rest>, Bot<C>) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<StreamEvents, E>>,
+    {
+        self.router_mut().plain_text = Some(Box::new(move |inv, bot| {
+            let args = Rest::parse(inv.args)?;
+            Ok(Box::pin(f(inv.map(args), bot)))
+        }));
+
+        self
+    }
+
+    /// Publish the command list into the bot profile `commands` preference
+    pub fn publish(
+        &self,
+        bot: &Bot<C>,
+    ) -> impl Future<Output = Result<ApiUpdateProfileResponse, C::Error>> {
+        let menu = self.menu();
+        bot.update_preferences(move |prefs| prefs.commands = Some(menu))
+    }
+
+    /// The [`NewChatItems`] handler for [`Dispatcher::on`](super::Dispatcher::on)
+    pub fn handler(&self) -> impl Fn(Arc<NewChatItems>, Bot<C>) -> CommandFuture<E> + use<C, E> {
+        let commands = self.clone();
+        move |ev, bot| {
+            let commands = commands.clone();
+            Box::pin(async move { commands.route(ev, bot).await })
+        }
+    }
+
+    /// Route all messages from the event to the corresponding handlers one by one
+    pub async fn route(&self, ev: Arc<NewChatItems>, bot: Bot<C>) -> Result<StreamEvents, E> {
+        for index in 0..ev.chat_items.len() {
+            let item = &ev.chat_items[index];
+
+            let Some(chat_id) = ChatId::from_chat_info(&item.chat_info) else {
+                continue;
+            };
+
+            let Some(text) = message_text(&item.chat_item) else {
+                continue;
+            };
+
+            let text = text.trim();
+            let router = &self.inner;
+
+            let (name, args, handler) = match text.strip_prefix(router.prefix.as_str()) {
+                Some(cmd) if !cmd.is_empty() && !cmd.starts_with(char::is_whitespace) => {
+                    let (name, args) = cmd.split_once(char::is_whitespace).unwrap_or((cmd, ""));
+
+                    let handler = router.handlers.get(name).or(router.unknown.as_ref());
+                    (name, args, handler)
+                }
+                _ => ("", text, router.plain_text.as_ref()),
+            };
+
+            let invocation = Invocation {
+                chat_id,
+                name: name.to_owned(),
+                args,
+                event: Arc::clone(&ev),
+                index,
+            };
+
+            let reply = match handler {
+                Some(handler) => match handler(invocation, bot.clone()) {
+                    Ok(fut) => {
+                        if let StreamEvents::Break = fut.await? {
+                            return Ok(StreamEvents::Break);
+                        }
+
+                        continue;
+                    }
+                    Err(error) => {
+                        let usage = router
+                            .infos
+                            .iter()
+                            .find(|info| info.name == name)
+                            .map(|info| info.usage(&router.prefix))
+                            .unwrap_or_default();
+
+                        format!("{error}\nUsage: {usage}")
+                    }
+                },
+                // Unknown command without the custom handler
+                None if !name.is_empty() => {
+                    format!("Unknown command {}{name}\n\n{}", router.prefix, self.help())
+                }
+                None => continue,
+            };
+
+            bot.send_msg(chat_id, reply)
+                .reply_to(&item.chat_item)
+                .await?;
+        }
+
+        Ok(StreamEvents::Continue)
+    }
+}
+
+fn message_text(item: &ChatItem) -> Option<&str> {
+    item.content
+        .rcv_msg_content()
+        .and_then(MsgContent::text_part)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_args() {
+        assert_eq!(<(i64,)>::parse(" 42 "), Ok((42,)));
+        assert_eq!(
+            <(String, Opt<u8>)>::parse("name"),
+            Ok(("name".to_owned(), Opt(None)))
+        );
+        assert_eq!(
+            <(String, Opt<u8>)>::parse("name 7"),
+            Ok(("name".to_owned(), Opt(Some(7))))
+        );
+        assert!(<(i64,)>::parse("").is_err());
+        assert!(<(i64,)>::parse("1 2").is_err());
+        assert!(<(i64,)>::parse("x").is_err());
+        assert!(<()>::parse("x").is_err());
+        assert_eq!(Rest::parse("  a  b "), Ok(Rest("a  b".to_owned())));
+    }
+
+    #[cfg(feature = "mock")]
+    fn event(texts: &[&str]) -> Arc<NewChatItems> {
+        use crate::test_utils;
+
+        let items: Vec<_> = texts
+            .iter()
+            .zip(1..)
+            .map(|(text, id)| {
+                serde_json::json!({
+                    "chatInfo": test_utils::local_chat(),
+                    "chatItem": test_utils::chat_item(id, text, true, serde_json::json!([])),
+                })
+            })
+            .collect();
+
+        Arc::new(
+            serde_json::from_value(serde_json::json!({
+                "user": test_utils::user(),
+                "chatItems": items,
+            }))
+            .unwrap(),
+        )
+    }
+
+    #[cfg(feature = "mock")]
+    fn commands(
+        log: &Arc<std::sync::Mutex<Vec<String>>>,
+    ) -> Commands<crate::mock::MockClient, crate::mock::MockError> {
+        let push = |log: &Arc<std::sync::Mutex<Vec<String>>>| {
+            let log = Arc::clone(log);
+            move |entry: String| log.lock().unwrap().push(entry)
+        };
+
+        let square = push(log);
+        let greet = push(log);
+        let say = push(log);
+        let plain = push(log);
+
+        Commands::new()
+            .command(
+                CommandInfo::new("square")
+                    .label("Square a number")
+                    .params("<number>"),
+                move |cmd: Invocation<(i64,)>, _| {
+                    square(format!("square {}", cmd.args.0 * cmd.args.0));
+                    async { Ok(StreamEvents::Continue) }
+                },
+            )
+            .command("greet", move |cmd: Invocation<(String, Opt<String>)>, _| {
+                let (name, Opt(from)) = cmd.args;
+                greet(format!("greet {name} {from:?}"));
+                async { Ok(StreamEvents::Continue) }
+            })
+            .command("say", move |cmd: Invocation<Rest>, _| {
+                say(format!("say {}", cmd.args.0));
+                async { Ok(StreamEvents::Continue) }
+            })
+            .command(
+                CommandInfo::new("secret").hidden(),
+                |_: Invocation<()>, _| async { Ok(StreamEvents::Break) },
+            )
+            .plain_text(move |cmd: Invocation<Rest>, _| {
+                plain(format!("plain {}", cmd.args.0));
+                async { Ok(StreamEvents::Continue) }
+            })
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn routing() {
+        use crate::{commands::ApiSendMessages, id::UserId, mock, test_utils};
+
+        let (client, _, _) = mock::init();
+        client
+            .expect::<ApiSendMessages>()
+            .repeatedly()
+            .respond_json(serde_json::json!({
+                "type": "newChatItems",
+                "user": test_utils::user(),
+                "chatItems": [],
+            }));
+
+        let log = Arc::default();
+        let commands = commands(&log);
+        let bot = Bot::new(client.clone(), UserId::from_raw(1));
+
+        let ev = event(&[
+            "/square 4",
+            "/square x",
+            "/greet Bob",
+            "/greet Bob Alice",
+            "/say  hi  there ",
+            "/nope",
+            "hello",
+        ]);
+        let result = commands.route(ev, bot.clone()).await.unwrap();
+        assert!(matches!(result, StreamEvents::Continue));
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "square 16",
+                "greet Bob None",
+                "greet Bob Some(\"Alice\")",
+                "say hi  there",
+                "plain hello",
+            ]
+        );
+
+        let replies = client.sent_of::<ApiSendMessages>();
+        assert_eq!(replies.len(), 2);
+        assert!(replies[0].command.contains("Invalid argument"));
+        assert!(replies[0].command.contains("Usage: /square <number>"));
+        assert!(replies[1].command.contains("Unknown command /nope"));
+        assert!(
+            replies[1]
+                .command
+                .contains("/square <number> - Square a number")
+        );
+        assert!(!replies[1].command.contains("secret"));
+
+        // Break stops routing the remaining messages
+        let ev = event(&["/secret", "/square 3"]);
+        let result = commands.route(ev, bot).await.unwrap();
+        assert!(matches!(result, StreamEvents::Break));
+        assert_eq!(log.lock().unwrap().len(), 5);
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn publish() {
+        use crate::{
+            commands::{ApiUpdateProfile, ShowActiveUser},
+            id::UserId,
+            mock, test_utils,
+        };
+
+        let (client, _, _) = mock::init();
+        client
+            .expect::<ShowActiveUser>()
+            .respond_json(serde_json::json!({
+                "type": "activeUser",
+                "user": test_utils::user(),
+            }));
+        client
+            .expect::<ApiUpdateProfile>()
+            .respond_json(serde_json::json!({
+                "type": "userProfileNoChange",
+                "user": test_utils::user(),
+            }));
+
+        let commands = commands(&Arc::default());
+        let menu = commands.menu();
+        assert_eq!(menu.len(), 3);
+
+        let bot = Bot::new(client.clone(), UserId::from_raw(1));
+        commands.publish(&bot).await.unwrap();
+        client.assert_satisfied();
+
+        let update = &client.sent_of::<ApiUpdateProfile>()[0].command;
+        assert!(update.contains("\"commands\""));
+        assert!(update.contains("Square a number"));
+        assert!(update.contains("<number>"));
+        assert!(!update.contains("secret"));
+    }
+}
@@ -0,0 +1,2473 @@
+//! Zero-cost[^1] type-safe event dispatchers handling events mostly at compile time(event matching logic should
+//! reduce to a jump table used in a loop, the implementation doesn't use runtime maps or
+//! virtual calls giving the compiler full information required to do optimizations). See
+//! [`DispatchChain`] for a quick start
+//!
+//! [^1]: _Sequential dispatchers erase types with `Box<dyn Future>` making them non-zerocost, this
+//! will be fixed after `async_fn_traits` stabilization, today's Rust lacks expressive power to
+//! bind Future lifetimes correctly. For sequential scenarios reading events as a stream can
+//! produce more efficient assembly_
+
+pub mod commands;
+
+use futures::TryStreamExt as _;
+use simploxide_api_types::events::{Event, EventData, EventKind};
+use tokio::sync::{Notify, oneshot};
+#[cfg(feature = "cancellation")]
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    any::Any,
+    cell::Cell,
+    collections::HashMap,
+    future::Future,
+    panic::AssertUnwindSafe,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::Poll,
+    time::Duration,
+};
+
+use crate::{
+    EventParser, EventStream, StreamEvents, ext::EventExt as _, id::ChatId,
+    predicate::EventPredicate,
+};
+
+/// [`Dispatcher`] builder. Obtained from [`EventStream::into_dispatcher`].
+pub struct DispatchChain<P, Ctx> {
+    events: EventStream<P>,
+    ctx: Ctx,
+}
+
+impl<P, Ctx> DispatchChain<P, Ctx>
+where
+    P: EventParser,
+{
+    pub fn with_ctx(events: EventStream<P>, ctx: Ctx) -> Self {
+        Self { ctx, events }
+    }
+
+    /// The sequential version of [Self::fallback]
+    pub fn seq_fallback<E, F>(mut self, f: F) -> Dispatcher<P, Ctx, Fallback<F>>
+    where
+        F: AsyncFnMut(Event, &mut Ctx) -> Result<StreamEvents, E>,
+    {
+        self.events.accept_all();
+        Dispatcher {
+            events: self.events,
+            ctx: self.ctx,
+            opts: DispatchOptions::default(),
+            chain: Fallback { f },
+        }
+    }
+
+    /// Accepts all events ([`crate::Event`]) unhandled by regular handlers.
+    /// It is mostly useful for debug logging. Prefer to remove this call in production because it
+    /// forces full parsing of all event types when by default only the events with set up handlers
+    /// get parsed.
+    pub fn fallback<E, F, Fut>(mut self, f: F) -> Dispatcher<P, Ctx, Fallback<F>>
+    where
+        Ctx: 'static + Send,
+        E: 'static + Send + From<P::Error>,
+        F: Fn(Event, Ctx) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<StreamEvents, E>>,
+    {
+        self.events.accept_all();
+        Dispatcher {
+            events: self.events,
+            ctx: self.ctx,
+            opts: DispatchOptions::default(),
+            chain: Fallback { f },
+        }
+    }
+
+    /// Register the sequential handler. This call determines the [`Dispatcher`] type. You won't be
+    /// able to mix `seq` with `on`,  after calling this all handlers must be `seq`. See
+    /// [`Dispatcher::seq`] for full docs.
+    pub fn seq<Ev, E, F>(mut self, f: F) -> Dispatcher<P, Ctx, Match<Ev, F>>
+    where
+        Ev: EventData,
+        F: AsyncFnMut(Arc<Ev>, &mut Ctx) -> Result<StreamEvents, E>,
+    {
+        self.events.reject_all();
+        self.events.accept(Ev::KIND);
+        Dispatcher {
+            events: self.events,
+            ctx: self.ctx,
+            opts: DispatchOptions::default(),
+            chain: Match {
+                f,
+                _phantom: std::marker::PhantomData,
+            },
+        }
+    }
+
+    /// The guarded version of [Self::seq]. See [`Dispatcher::seq_if`]
+    pub fn seq_if<Ev, E, Pr, F>(mut self, pred: Pr, f: F) -> Dispatcher<P, Ctx, MatchIf<Ev, Pr, F>>
+    where
+        Ev: EventData,
+        Pr: EventPredicate,
+        F: AsyncFnMut(Arc<Ev>, &mut Ctx) -> Result<StreamEvents, E>,
+    {
+        self.events.reject_all();
+        self.events.accept(Ev::KIND);
+        Dispatcher {
+            events: self.events,
+            ctx: self.ctx,
+            opts: DispatchOptions::default(),
+            chain: MatchIf {
+                pred,
+                f,
+                _phantom: std::marker::PhantomData,
+            },
+        }
+    }
+
+    /// Register the concurrent handler. This call determines the [`Dispatcher`] type. You won't be
+    /// able to mix `seq` with `on`,  after calling this all handlers must be `on`. See
+    /// [`Dispatcher::on`] for full docs. Concurrent handlers require `Ctx: 'static + Send +
+    /// Clone`. `Ctx` is getting cloned into each handler, ensure that `impl Clone for Ctx` is
+    /// cheap. If you need to mutate the `Ctx` you will have to use `Arc<Mutex>` or similar.
+    pub fn on<Ev, E, F, Fut>(mut self, f: F) -> Dispatcher<P, Ctx, Match<Ev, F>>
+    where
+        Ctx: 'static + Send,
+        E: 'static + Send,
+        Ev: 'static + EventData,
+        F: Fn(Arc<Ev>, Ctx) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<StreamEvents, E>>,
+    {
+        self.events.reject_all();
+        self.events.accept(Ev::KIND);
+        Dispatcher {
+            events: self.events,
+            ctx: self.ctx,
+            opts: DispatchOptions::default(),
+            chain: Match {
+                f,
+                _phantom: std::marker::PhantomData,
+            },
+        }
+    }
+
+    /// The guarded version of [Self::on]. See [`Dispatcher::on_if`]
+    pub fn on_if<Ev, E, Pr, F, Fut>(
+        mut self,
+        pred: Pr,
+        f: F,
+    ) -> Dispatcher<P, Ctx, MatchIf<Ev, Pr, F>>
+    where
+        Ctx: 'static + Send,
+        E: 'static + Send,
+        Ev: 'static + EventData,
+        Pr: EventPredicate,
+        F: Fn(Arc<Ev>, Ctx) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<StreamEvents, E>>,
+    {
+        self.events.reject_all();
+        self.events.accept(Ev::KIND);
+        Dispatcher {
+            events: self.events,
+            ctx: self.ctx,
+            opts: DispatchOptions::default(),
+            chain: MatchIf {
+                pred,
+                f,
+                _phantom: std::marker::PhantomData,
+            },
+        }
+    }
+}
+
+/// Builds a compile-time dispatch chain in its type parameter `D`.
+/// The chain is unrolled in [`Self::sequential_dispatch`] or [`Self::dispatch`].
+///
+/// Use [`Self::seq`] to add sequential handlers (`&mut Ctx`, `!Send` future) or
+/// [`Self::on`] to add concurrent handlers (`Ctx: Clone + Send`, spawned as tasks).
+pub struct Dispatcher<P, Ctx, D> {
+    events: EventStream<P>,
+    ctx: Ctx,
+    chain: D,
+    opts: DispatchOptions,
+}
+
+impl<P, Ctx, D> Dispatcher<P, Ctx, D>
+where
+    D: DispatchEvent<Ctx>,
+{
+    /// Register a sequential event handler.
+    ///
+    /// - The handler signature is `AsyncFnMut(ev: Arc<{EventType}>, ctx: &mut Ctx) -> Result<StreamEvents, {ErrorType}>`
+    /// - `Ctx` is whatever type you pass when creating the dispatcher with
+    ///   `events.into_local_dispatcher(ctx)`. It will be passed by a mutable reference into all handlers
+    /// - `{Eventype}` is one of the data structs defined in [`crate::events`]. Events are dispatched
+    ///   statically so this type links the handler to event. When handlers with the same `{EvenType}` are set
+    ///   multiple times the last one wins.
+    /// - `{ErrorType}` can be arbitrary but all handlers must share it and it must implement `From<ClientError>`.
+    /// - Events are processed one at a time; handlers have exclusive `&mut` access to `Ctx`.
+    ///
+    /// ## Usage
+    ///
+    /// - Set async fn
+    ///
+    /// ```rust
+    /// events.into_dispatcher(client)
+    ///     .seq(contact_connected)
+    ///     .sequential_dispatch()
+    ///     .await;
+    ///
+    /// async fn contact_connected(
+    ///     ev: Arc<ContactConnected>,
+    ///     ctx: &mut ws::Client,
+    /// ) -> ClientResult<StreamEvents> { ... }
+    /// ```
+    ///
+    /// - Set async closure by fully qualifying types
+    ///
+    /// ```rust
+    /// events.into_local_dispatcher(client)
+    ///     .seq_fallback(async |ev, _| log::debug!("{ev:?}"))
+    ///     .seq::<ContactConnected, _>(async |ev, &mut client| { ... })
+    ///     .sequential_dispatch()
+    ///     .await;
+    /// ```
+    ///
+    /// - Set async closure by specifying closure argument type
+    ///
+    /// ```rust
+    /// events.into_local_dispatcher(client)
+    ///     .seq_fallback(async |ev, _| log::debug!("{ev:?}"))
+    ///     .seq(async |ev: Arc<ContactConnected>, &mut client| {
+    ///         //...
+    ///     })
+    ///     .sequential_dispatch()
+    ///     .await;
+    /// ```
+    pub fn seq<Ev, F>(mut self, f: F) -> Dispatcher<P, Ctx, Intercept<Match<Ev, F>, D>>
+    where
+        Ev: EventData,
+        F: AsyncFnMut(Arc<Ev>, &mut Ctx) -> Result<StreamEvents, D::Error>,
+    {
+        self.events.accept(Ev::KIND);
+        Dispatcher {
+            events: self.events,
+            ctx: self.ctx,
+            opts: self.opts,
+            chain: Intercept {
+                d1: Match {
+                    f,
+                    _phantom: std::marker::PhantomData,
+                },
+                d2: self.chain,
+            },
+        }
+    }
+
+    /// Register a sequential event handler running only for events matching the predicate. Events
+    /// rejected by the predicate fall through to handlers of the same event type registered
+    /// earlier or get dropped if there are none. See [`crate::predicate`] and [`Self::seq`].
+    ///
+    /// ```rust
+    /// events.into_local_dispatcher(client)
+    ///     .seq(new_msgs)
+    ///     .seq_if(predicate::content(ContentType::Image), new_images)
+    ///     .sequential_dispatch()
+    ///     .await;
+    /// ```
+    pub fn seq_if<Ev, Pr, F>(
+        mut self,
+        pred: Pr,
+        f: F,
+    ) -> Dispatcher<P, Ctx, Intercept<MatchIf<Ev, Pr, F>, D>>
+    where
+        Ev: EventData,
+        Pr: EventPredicate,
+        F: AsyncFnMut(Arc<Ev>, &mut Ctx) -> Result<StreamEvents, D::Error>,
+    {
+        self.events.accept(Ev::KIND);
+        Dispatcher {
+            events: self.events,
+            ctx: self.ctx,
+            opts: self.opts,
+            chain: Intercept {
+                d1: MatchIf {
+                    pred,
+                    f,
+                    _phantom: std::marker::PhantomData,
+                },
+                d2: self.chain,
+            },
+        }
+    }
+
+    /// Wrap all sequential handlers registered before this call with a middleware. Call it last to
+    /// wrap the whole chain, layers registered later wrap the earlier ones.
+    ///
+    /// - The middleware signature is `AsyncFnMut(ev: Event, ctx: &mut Ctx, next: Next<'_, D>) -> Result<StreamEvents, {ErrorType}>`
+    /// - `ev` is the event about to be handled, call [`Next::run`] to execute the handler.
+    ///   Returning without calling `next.run` short-circuits the handler.
+    /// - Events without handlers never reach the middleware.
+    ///
+    /// ```rust
+    /// events.into_local_dispatcher(client)
+    ///     .seq(contact_connected)
+    ///     .seq(new_msgs)
+    ///     .seq_layer(async |ev, client, next| {
+    ///         let started = std::time::Instant::now();
+    ///         let result = next.run(client).await;
+    ///         log::debug!("{:?} handled in {:?}", ev.kind(), started.elapsed());
+    ///         result
+    ///     })
+    ///     .sequential_dispatch()
+    ///     .await;
+    /// ```
+    pub fn seq_layer<F>(self, f: F) -> Dispatcher<P, Ctx, Layer<D, F>>
+    where
+        F: AsyncFnMut(Event, &mut Ctx, Next<'_, D>) -> Result<StreamEvents, D::Error>,
+    {
+        Dispatcher {
+            chain: Layer {
+                kinds: self.events.kind_filter,
+                inner: self.chain,
+                f,
+            },
+            events: self.events,
+            ctx: self.ctx,
+            opts: self.opts,
+        }
+    }
+
+    /// Re-run failed sequential handlers registered before this call up to `attempts` more times
+    /// waiting for `backoff` between the attempts. The last error is returned if all attempts fail.
+    /// Panics are not retried.
+    ///
+    /// ```rust
+    /// events.into_local_dispatcher(client)
+    ///     .seq(new_msgs)
+    ///     .seq_retry(3, Backoff::exponential(Duration::from_millis(100), Duration::from_secs(2)))
+    ///     .sequential_dispatch()
+    ///     .await;
+    /// ```
+    pub fn seq_retry(self, attempts: u32, backoff: Backoff) -> Dispatcher<P, Ctx, SeqRetry<D>> {
+        Dispatcher {
+            chain: SeqRetry {
+                kinds: self.events.kind_filter,
+                inner: self.chain,
+                attempts,
+                backoff,
+            },
+            events: self.events,
+            ctx: self.ctx,
+            opts: self.opts,
+        }
+    }
+
+    /// Route errors and panics of sequential handlers registered before this call to an error
+    /// handler.
+    ///
+    /// - The error handler signature is `AsyncFnMut(err: HandlerError<{ErrorType}>, ev: Event, ctx: &mut Ctx) -> Result<StreamEvents, {ErrorType}>`
+    /// - `ev` is the event that failed to be handled
+    /// - Returning `Ok(StreamEvents::Continue)` ignores the error, returning `Ok(StreamEvents::Break)`
+    ///   stops the dispatcher gracefully and returning `Err(_)` stops it with an error.
+    ///   [`HandlerError::into_error`] restores the default behaviour.
+    /// - Continuing after a panic is on you: the state shared through `Ctx` may be left
+    ///   inconsistent by the panicked handler and the dispatcher keeps using it.
+    ///
+    /// ```rust
+    /// events.into_local_dispatcher(client)
+    ///     .seq(new_msgs)
+    ///     .seq_on_error(async |err, ev, client| {
+    ///         log::error!("{:?} handler failed: {err}", ev.kind());
+    ///         report_to_admin(client, &err).await;
+    ///         Ok(StreamEvents::Continue)
+    ///     })
+    ///     .sequential_dispatch()
+    ///     .await;
+    /// ```
+    pub fn seq_on_error<F>(self, f: F) -> Dispatcher<P, Ctx, SeqRecover<D, F>>
+    where
+        F: AsyncFnMut(HandlerError<D::Error>, Event, &mut Ctx) -> Result<StreamEvents, D::Error>,
+    {
+        Dispatcher {
+            chain: SeqRecover {
+                kinds: self.events.kind_filter,
+                inner: self.chain,
+                f,
+            },
+            events: self.events,
+            ctx: self.ctx,
+            opts: self.opts,
+        }
+    }
+
+    /// Log errors and panics of sequential handlers registered before this call and keep
+    /// dispatching. A shortcut for [`Self::seq_on_error`] with a logging handler.
+    #[allow(clippy::type_complexity)]
+    pub fn seq_log_and_continue(
+        self,
+    ) -> Dispatcher<P, Ctx, SeqRecover<D, SeqLogAndContinue<D::Error, Ctx>>>
+    where
+        D::Error: std::fmt::Display,
+    {
+        self.seq_on_error(seq_log_and_continue as SeqLogAndContinue<D::Error, Ctx>)
+    }
+
+    /// Cancel sequential handlers registered before this call when they run longer than
+    /// `duration`. Timeouts are logged and the dispatcher continues. Handlers already limited by an
+    /// earlier timeout keep their limit, so call it right after a handler to limit only this
+    /// handler or last to limit all the remaining ones.
+    ///
+    /// ```rust
+    /// events.into_local_dispatcher(client)
+    ///     .seq(file_ready)
+    ///     .seq_timeout(Duration::from_secs(600))
+    ///     .seq(contact_connected)
+    ///     .seq(new_msgs)
+    ///     // Limits contact_connected and new_msgs
+    ///     .seq_timeout(Duration::from_secs(30))
+    ///     .sequential_dispatch()
+    ///     .await;
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn seq_timeout(
+        self,
+        duration: Duration,
+    ) -> Dispatcher<P, Ctx, SeqTimeout<D, SeqLogTimeout<D::Error, Ctx>>> {
+        self.seq_timeout_with(duration, seq_log_timeout as SeqLogTimeout<D::Error, Ctx>)
+    }
+
+    /// Like [`Self::seq_timeout`] but calls `f` when a handler times out.
+    ///
+    /// - The callback signature is `AsyncFnMut(ev: Event, ctx: &mut Ctx) -> Result<StreamEvents, {ErrorType}>`
+    /// - `ev` is the event handled by the cancelled handler
+    pub fn seq_timeout_with<F>(
+        self,
+        duration: Duration,
+        f: F,
+    ) -> Dispatcher<P, Ctx, SeqTimeout<D, F>>
+    where
+        F: AsyncFnMut(Event, &mut Ctx) -> Result<StreamEvents, D::Error>,
+    {
+        Dispatcher {
+            chain: SeqTimeout {
+                kinds: self.events.kind_filter,
+                inner: self.chain,
+                duration,
+                f,
+            },
+            events: self.events,
+            ctx: self.ctx,
+            opts: self.opts,
+        }
+    }
+
+    /// Dispatch events sequentially. Handlers block the event loop, allowing exclusive `&mut Ctx`
+    /// access. Returning [`StreamEvents::Break`] stops the dispatcher and returns the event stream
+    /// and `ctx` for further processing.
+    ///
+    /// Produces a `!Send` future that can be used with [`tokio::task::LocalSet`], on the main
+    /// tokio thread, or with a single-threaded runtime.
+    pub async fn sequential_dispatch(self) -> Result<(EventStream<P>, Ctx), D::Error>
+    where
+        P: EventParser,
+        D::Error: From<P::Error>,
+    {
+        let Self {
+            ctx,
+            events,
+            mut chain,
+            ..
+        } = self;
+
+        events
+            .stream_events_with_ctx_mut(
+                async move |ev, ctx| {
+                    // Events rejected by all guards of seq_if handlers
+                    let Ok(handler) = chain.dispatch_event(ev, ctx) else {
+                        return Ok(StreamEvents::Continue);
+                    };
+
+                    handler.await
+                },
+                ctx,
+            )
+            .await
+    }
+
+    /// Like [`Self::sequential_dispatch`] but stops when `token` is cancelled. Token cancellation
+    /// is equivalent to returning [`StreamEvents::Break`].
+    #[cfg(feature = "cancellation")]
+    pub async fn sequential_dispatch_with_cancellation(
+        self,
+        token: CancellationToken,
+    ) -> Result<(EventStream<P>, Ctx), D::Error>
+    where
+        P: EventParser,
+        D::Error: From<P::Error>,
+    {
+        let Self {
+            mut ctx,
+            mut events,
+            mut chain,
+            ..
+        } = self;
+
+        loop {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => break,
+                res = events.try_next() => match res {
+                    Ok(Some(ev)) => {
+                        // Events rejected by all guards of seq_if handlers
+                        let Ok(handler) = chain.dispatch_event(ev, &mut ctx) else {
+                            continue;
+                        };
+                        if let StreamEvents::Break = handler.await? {
+                            break;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+
+        Ok((events, ctx))
+    }
+}
+
+impl<P, Ctx, D> Dispatcher<P, Ctx, D>
+where
+    P: 'static + EventParser,
+    Ctx: 'static + Send + Clone,
+    D: ConcurrentDispatchEvent<Ctx>,
+    D::Error: From<P::Error>,
+{
+    /// Register a concurrent event handler.
+    ///
+    /// - The handler signature is `AsyncFn(ev: Arc<{EventType}>, ctx: Ctx) -> Result<StreamEvents, {ErrorType}>;`
+    /// - `Ctx` is whatever is passed into the `into_dispatcher` call. It is cloned into every handler invocation
+    /// - `{Eventype}` is one of the data structs defined in [`crate::events`]. Events are dispatched
+    ///   statically so this type links the handler to event. When handlers with the same `{EvenType}` are set
+    ///   multiple times the last one wins.
+    /// - `{ErrorType}` can be arbitrary but all handlers must share it and it must implement `From<ClientError>`.
+    /// - All handlers run as tokio tasks so events are processed concurrently(and in parallel on
+    ///   multithreaded runtimes).
+    ///
+    ///
+    /// ## Usage
+    ///
+    /// - Set async fn
+    ///
+    /// ```rust
+    /// events.into_dispatcher(client)
+    ///     .on(contact_connected)
+    ///     .dispatch()
+    ///     .await;
+    ///
+    /// async fn contact_connected(
+    ///     ev: Arc<ContactConnected>,
+    ///     ctx: ws::Client,
+    /// ) -> ClientResult<StreamEvents> { ... }
+    /// ```
+    ///
+    /// - Set async closure by fully qualifying types
+    ///
+    /// ```rust
+    /// events.into_dispatcher(client)
+    ///     .fallback(async |ev, _| log::debug!("{ev:?}"))
+    ///     .on::<ContactConnected, _, _>(async |ev, client| { ... })
+    ///     .dispatch()
+    ///     .await;
+    /// ```
+    ///
+    /// - Set async closure by specifying closure argument type
+    ///
+    /// ```rust
+    /// events.into_dispatcher(client)
+    ///     .fallback(async |ev, _| log::debug!("{ev:?}"))
+    ///     .on(async |ev: Arc<ContactConnected>, client| { ... })
+    ///     .dispatch()
+    ///     .await;
+    /// ```
+    pub fn on<Ev, F, Fut>(mut self, f: F) -> Dispatcher<P, Ctx, Intercept<Match<Ev, F>, D>>
+    where
+        Ev: 'static + EventData,
+        F: Fn(Arc<Ev>, Ctx) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<StreamEvents, D::Error>>,
+    {
+        self.events.accept(Ev::KIND);
+        Dispatcher {
+            events: self.events,
+            ctx: self.ctx,
+            opts: self.opts,
+            chain: Intercept {
+                d1: Match {
+                    f,
+                    _phantom: std::marker::PhantomData,
+                },
+                d2: self.chain,
+            },
+        }
+    }
+
+    /// Register a concurrent event handler running only for events matching the predicate. Events
+    /// rejected by the predicate fall through to handlers of the same event type registered
+    /// earlier or get dropped if there are none. See [`crate::predicate`] and [`Self::on`].
+    ///
+    /// ```rust
+    /// events.into_dispatcher(bot)
+    ///     .on(direct_msgs)
+    ///     .on_if(predicate::groups(), group_msgs)
+    ///     .on_if(predicate::sender_role_at_least(GroupMemberRole::Admin), admin_msgs)
+    ///     .dispatch()
+    ///     .await;
+    /// ```
+    pub fn on_if<Ev, Pr, F, Fut>(
+        mut self,
+        pred: Pr,
+        f: F,
+    ) -> Dispatcher<P, Ctx, Intercept<MatchIf<Ev, Pr, F>, D>>
+    where
+        Ev: 'static + EventData,
+        Pr: EventPredicate,
+        F: Fn(Arc<Ev>, Ctx) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<StreamEvents, D::Error>>,
+    {
+        self.events.accept(Ev::KIND);
+        Dispatcher {
+            events: self.events,
+            ctx: self.ctx,
+            opts: self.opts,
+            chain: Intercept {
+                d1: MatchIf {
+                    pred,
+                    f,
+                    _phantom: std::marker::PhantomData,
+                },
+                d2: self.chain,
+            },
+        }
+    }
+
+    /// Wrap all concurrent handlers registered before this call with a middleware. Call it last to
+    /// wrap the whole chain, layers registered later wrap the earlier ones.
+    ///
+    /// - The middleware signature is `AsyncFn(ev: Event, ctx: Ctx, next: impl Future<Output = Result<StreamEvents, {ErrorType}>>) -> Result<StreamEvents, {ErrorType}>`
+    /// - `ev` is the event about to be handled, `next` is the handler future. Awaiting `next`
+    ///   runs the handler, dropping it short-circuits the handler.
+    /// - The middleware runs inside the handler task so it can observe handler errors and panics.
+    /// - Events without handlers never reach the middleware.
+    ///
+    /// ```rust
+    /// events.into_dispatcher(bot)
+    ///     .on(contact_connected)
+    ///     .on(new_msgs)
+    ///     .layer(async |ev, bot, next| {
+    ///         if ev.user_id() != Some(bot.user_id()) {
+    ///             return Ok(StreamEvents::Continue);
+    ///         }
+    ///
+    ///         let result = next.await;
+    ///         if let Err(e) = &result {
+    ///             log::error!("Handler failed: {e}");
+    ///         }
+    ///
+    ///         result
+    ///     })
+    ///     .dispatch()
+    ///     .await;
+    /// ```
+    pub fn layer<F, Fut>(self, f: F) -> Dispatcher<P, Ctx, Layer<D, F>>
+    where
+        F: Fn(Event, Ctx, D::Future) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<StreamEvents, D::Error>>,
+    {
+        Dispatcher {
+            chain: Layer {
+                kinds: self.events.kind_filter,
+                inner: self.chain,
+                f,
+            },
+            events: self.events,
+            ctx: self.ctx,
+            opts: self.opts,
+        }
+    }
+
+    /// Re-run failed concurrent handlers registered before this call up to `attempts` more times
+    /// waiting for `backoff` between the attempts. Retries happen inside the handler task so they
+    /// don't block other handlers. The last error is returned if all attempts fail. Panics are not
+    /// retried.
+    ///
+    /// ```rust
+    /// events.into_dispatcher(bot)
+    ///     .on(new_msgs)
+    ///     .retry(3, Backoff::exponential(Duration::from_millis(100), Duration::from_secs(2)))
+    ///     .log_and_continue()
+    ///     .dispatch()
+    ///     .await;
+    /// ```
+    pub fn retry(self, attempts: u32, backoff: Backoff) -> Dispatcher<P, Ctx, Retry<D>>
+    where
+        D: 'static + Send + Sync,
+    {
+        Dispatcher {
+            chain: Retry {
+                kinds: self.events.kind_filter,
+                inner: Arc::new(self.chain),
+                attempts,
+                backoff,
+            },
+            events: self.events,
+            ctx: self.ctx,
+            opts: self.opts,
+        }
+    }
+
+    /// Route errors and panics of concurrent handlers registered before this call to an error
+    /// handler. Without an error handler the first error or panic stops the dispatcher.
+    ///
+    /// - The error handler signature is `AsyncFn(err: HandlerError<{ErrorType}>, ev: Event, ctx: Ctx) -> Result<StreamEvents, {ErrorType}>`
+    /// - `ev` is the event that failed to be handled, `ctx` is the context the handler received
+    /// - Returning `Ok(StreamEvents::Continue)` ignores the error, returning `Ok(StreamEvents::Break)`
+    ///   stops the dispatcher gracefully and returning `Err(_)` stops it with an error.
+    ///   [`HandlerError::into_error`] restores the default behaviour.
+    /// - Continuing after a panic is on you: the state shared through `Ctx` may be left
+    ///   inconsistent by the panicked handler and the dispatcher keeps using it.
+    /// - The error handler runs inside the failed handler task.
+    ///
+    /// ```rust
+    /// events.into_dispatcher(bot)
+    ///     .on(new_msgs)
+    ///     .on_error(async |err, ev, bot| {
+    ///         log::error!("{:?} handler failed: {err}", ev.kind());
+    ///         if let HandlerError::Panicked(_) = err {
+    ///             bot.send_msg(ADMIN, "Bot handler panicked").await?;
+    ///         }
+    ///         Ok(StreamEvents::Continue)
+    ///     })
+    ///     .dispatch()
+    ///     .await;
+    /// ```
+    pub fn on_error<F, Fut>(self, f: F) -> Dispatcher<P, Ctx, Recover<D, F>>
+    where
+        F: 'static + Send + Sync + Fn(HandlerError<D::Error>, Event, Ctx) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<StreamEvents, D::Error>>,
+    {
+        Dispatcher {
+            chain: Recover {
+                kinds: self.events.kind_filter,
+                inner: self.chain,
+                f: Arc::new(f),
+            },
+            events: self.events,
+            ctx: self.ctx,
+            opts: self.opts,
+        }
+    }
+
+    /// Log errors and panics of concurrent handlers registered before this call and keep
+    /// dispatching. A shortcut for [`Self::on_error`] with a logging handler.
+    #[allow(clippy::type_complexity)]
+    pub fn log_and_continue(self) -> Dispatcher<P, Ctx, Recover<D, LogAndContinue<D::Error, Ctx>>>
+    where
+        D::Error: std::fmt::Display,
+    {
+        self.on_error(log_and_continue as LogAndContinue<D::Error, Ctx>)
+    }
+
+    /// Cancel concurrent handlers registered before this call when they run longer than
+    /// `duration`. Timeouts are logged and the dispatcher continues. Handlers already limited by an
+    /// earlier timeout keep their limit, so call it right after a handler to limit only this
+    /// handler or last to limit all the remaining ones.
+    ///
+    /// ```rust
+    /// events.into_dispatcher(bot)
+    ///     .on(file_ready)
+    ///     .timeout(Duration::from_secs(600))
+    ///     .on(new_msgs)
+    ///     // Limits only new_msgs, file_ready keeps 600 seconds
+    ///     .timeout(Duration::from_secs(30))
+    ///     .dispatch()
+    ///     .await;
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn timeout(
+        self,
+        duration: Duration,
+    ) -> Dispatcher<P, Ctx, Timeout<D, LogTimeout<D::Error, Ctx>>> {
+        self.timeout_with(duration, log_timeout as LogTimeout<D::Error, Ctx>)
+    }
+
+    /// Like [`Self::timeout`] but calls `f` when a handler times out.
+    ///
+    /// - The callback signature is `AsyncFn(ev: Event, ctx: Ctx) -> Result<StreamEvents, {ErrorType}>`
+    /// - `ev` is the event handled by the cancelled handler, `ctx` is the context the handler
+    ///   received
+    pub fn timeout_with<F, Fut>(self, duration: Duration, f: F) -> Dispatcher<P, Ctx, Timeout<D, F>>
+    where
+        F: 'static + Send + Sync + Fn(Event, Ctx) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<StreamEvents, D::Error>>,
+    {
+        Dispatcher {
+            chain: Timeout {
+                kinds: self.events.kind_filter,
+                inner: self.chain,
+                duration,
+                f: Arc::new(f),
+            },
+            events: self.events,
+            ctx: self.ctx,
+            opts: self.opts,
+        }
+    }
+
+    /// Limit the number of in-flight concurrent handlers. When the limit is reached the dispatcher
+    /// stops reading the [`EventStream`] until some handler completes, so new events wait in the
+    /// stream instead of being spawned as tasks. Handlers waiting for their turn because of
+    /// [`Self::order_by`] count towards the limit, handlers waiting for a reply in
+    /// [`Bot::ask`](crate::bot::Bot::ask) don't. The limit cannot be less than 1.
+    ///
+    /// ```rust
+    /// events.into_dispatcher(bot)
+    ///     .on(new_msgs)
+    ///     .max_in_flight(64)
+    ///     .order_by(OrderBy::Chat)
+    ///     .dispatch()
+    ///     .await;
+    /// ```
+    pub fn max_in_flight(mut self, limit: usize) -> Self {
+        self.opts.max_in_flight = Some(limit.max(1));
+        self
+    }
+
+    /// Run handlers of events sharing the same key one at a time in the order the events were
+    /// received. Handlers of events with different keys still run concurrently. Events without a
+    /// key, e.g. [`Event::HostConnected`] with [`OrderBy::Chat`], are not ordered.
+    pub fn order_by(mut self, order_by: OrderBy) -> Self {
+        self.opts.order_by = Some(order_by);
+        self
+    }
+
+    /// Limit the time the dispatcher waits for in-flight handlers after it stops. Handlers still
+    /// running after the `timeout` are aborted. By default the dispatcher waits for all handlers
+    /// to complete, `Duration::ZERO` aborts them immediately.
+    ///
+    /// ```rust
+    /// events.into_dispatcher(bot)
+    ///     .on(new_msgs)
+    ///     .shutdown_timeout(Duration::from_secs(5))
+    ///     .dispatch_with_cancellation(token)
+    ///     .await;
+    /// ```
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.opts.shutdown_timeout = Some(timeout);
+        self
+    }
+
+    /// Spawns handlers as tokio tasks. Handlers execute and resolve in arbitrary order unless
+    /// [`Self::order_by`] is set.
+    /// [`StreamEvents::Break`] eventually stops the dispatcher after all in-flight handlers finish.
+    /// The returned [`EventStream`] filters should be reset via [`EventStream::accept_all`] if you
+    /// want to query the stream manually and process all events afterwards.
+    ///
+    /// # Errors and panics
+    ///
+    /// If a handler returns an error or panics, the dispatcher stops, waits for in-flight handlers
+    /// to complete, then returns the first error or resumes the first panic. Panics of handlers
+    /// completing after that are logged. Use [`Self::retry`], [`Self::on_error`] or
+    /// [`Self::log_and_continue`] to change this behaviour.
+    pub async fn dispatch(self) -> Result<(EventStream<P>, Ctx, Vec<Event>), D::Error> {
+        let chain = self.chain;
+        let ctx = self.ctx;
+        let mut events = self.events;
+        let (event_buffer, result) = run_concurrent_dispatch(
+            &chain,
+            &ctx,
+            self.opts,
+            &mut events,
+            std::future::pending::<()>(),
+            #[cfg(feature = "cancellation")]
+            None,
+        )
+        .await;
+        match result {
+            Ok(inner) => inner.map(move |_| (events, ctx, event_buffer)),
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+
+    /// Like [`Self::dispatch`] but stops when `token` is cancelled. Token cancellation behaviour
+    /// is equivalent to returning [`StreamEvents::Break`].
+    ///
+    /// Every handler receives a child of the `token` available through [`handler_token`], tasks
+    /// spawned by handlers don't inherit it and must receive the token explicitly. Use
+    /// [`Self::shutdown_timeout`] to limit the time spent waiting for in-flight handlers.
+    #[cfg(feature = "cancellation")]
+    pub async fn dispatch_with_cancellation(
+        self,
+        token: CancellationToken,
+    ) -> Result<(EventStream<P>, Ctx, Vec<Event>), D::Error> {
+        let chain = self.chain;
+        let ctx = self.ctx;
+        let mut events = self.events;
+        let (event_buffer, result) = run_concurrent_dispatch(
+            &chain,
+            &ctx,
+            self.opts,
+            &mut events,
+            token.cancelled(),
+            Some(&token),
+        )
+        .await;
+        match result {
+            Ok(inner) => inner.map(move |_| (events, ctx, event_buffer)),
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+
+    /// Runs concurrent handlers one at a time, producing a `'static + Send` future.
+    ///
+    /// Unlike [Dispatcher::sequential_dispatch] this clones `Ctx` per event (no `&mut`) and the
+    /// resulting future is `Send`. Use when handler execution order matters but you need a
+    /// sendable future, e.g. inside `tokio::spawn`.
+    pub async fn dispatch_sequentially(self) -> Result<(EventStream<P>, Ctx), D::Error> {
+        let ctx = self.ctx;
+        let events = self.events;
+        let chain = self.chain;
+
+        events
+            .stream_events_with_ctx_cloned(
+                async move |ev, ctx| {
+                    // Events rejected by all guards of on_if handlers
+                    let Ok(handler) = chain.concurrent_dispatch_event(ev, ctx) else {
+                        return Ok(StreamEvents::Continue);
+                    };
+                    handler.await
+                },
+                ctx,
+            )
+            .await
+    }
+
+    /// Like [`Self::dispatch_sequentially`] but stops when `token` is cancelled.
+    #[cfg(feature = "cancellation")]
+    pub async fn dispatch_sequentially_with_cancellation(
+        self,
+        token: CancellationToken,
+    ) -> Result<(EventStream<P>, Ctx), D::Error> {
+        let Self {
+            ctx,
+            mut events,
+            chain,
+            ..
+        } = self;
+
+        loop {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => break,
+                res = events.try_next() => match res {
+                    Ok(Some(ev)) => {
+                        // Events rejected by all guards of on_if handlers
+                        let Ok(handler) = chain.concurrent_dispatch_event(ev, ctx.clone()) else {
+                            continue;
+                        };
+                        if let StreamEvents::Break = handler.await? {
+                            break;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+
+        Ok((events, ctx))
+    }
+}
+
+#[cfg(feature = "cancellation")]
+tokio::task_local! {
+    static HANDLER_TOKEN: CancellationToken;
+}
+
+/// Returns the cancellation token of the running concurrent handler. The token is a child of the
+/// token passed into [`Dispatcher::dispatch_with_cancellation`] so handlers can observe the
+/// shutdown and finish early. Returns `None` outside of handlers spawned by
+/// `dispatch_with_cancellation`.
+///
+/// The token is stored in a task-local and is **not** propagated into tasks the handler spawns
+/// with `tokio::spawn` or similar, inside them this function returns `None`. Obtain the token in
+/// the handler itself and move it into the spawned task instead:
+///
+/// ```rust
+/// async fn download(ev: Arc<RcvFileDescrReady>, bot: Bot) -> ClientResult<StreamEvents> {
+///     let token = dispatcher::handler_token().unwrap_or_default();
+///
+///     tokio::select! {
+///         _ = token.cancelled() => log::info!("Shutting down, download aborted"),
+///         res = bot.download(&ev) => handle(res?),
+///     }
+///
+///     Ok(StreamEvents::Continue)
+/// }
+///
+/// async fn process_later(ev: Arc<NewChatItems>, bot: Bot) -> ClientResult<StreamEvents> {
+///     let token = dispatcher::handler_token().unwrap_or_default();
+///
+///     tokio::spawn(async move {
+///         // dispatcher::handler_token() is `None` here
+///         token.run_until_cancelled(process(ev, bot)).await;
+///     });
+///
+///     Ok(StreamEvents::Continue)
+/// }
+/// ```
+#[cfg(feature = "cancellation")]
+pub fn handler_token() -> Option<CancellationToken> {
+    HANDLER_TOKEN.try_with(CancellationToken::clone).ok()
+}
+
+/// Concurrent handlers that freed their [`Dispatcher::max_in_flight`] slot, see [`park_handler`]
+#[derive(Default)]
+struct Parked {
+    count: AtomicUsize,
+    notify: Notify,
+}
+
+tokio::task_local! {
+    static PARKED: Arc<Parked>;
+}
+
+/// Frees the [`Dispatcher::max_in_flight`] slot of the running concurrent handler until dropped
+/// so the dispatcher keeps reading the event stream while the handler waits for events in it.
+/// Returns `None` outside of concurrent handlers.
+pub(crate) fn park_handler() -> Option<ParkedHandler> {
+    PARKED
+        .try_with(|parked| {
+            parked.count.fetch_add(1, Ordering::SeqCst);
+            parked.notify.notify_one();
+            ParkedHandler(Arc::clone(parked))
+        })
+        .ok()
+}
+
+/// Takes the slot back on drop. The limit may be exceeded until some handler completes
+pub(crate) struct ParkedHandler(Arc<Parked>);
+
+impl Drop for ParkedHandler {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Drives the main dispatch loop until the event stream closes, a handler signals
+// Break/Err, or the stop future resolves. Then drains the join set to completion,
+// concurrently pulling from the event stream so that handlers blocked on incoming
+// events (e.g. XFTP downloads) can make progress. Returns buffered events that
+// arrived during the drain and the final loop result.
+async fn run_concurrent_dispatch<P, Ctx, D, Fut>(
+    chain: &D,
+    ctx: &Ctx,
+    opts: DispatchOptions,
+    events: &mut EventStream<P>,
+    stop: Fut,
+    #[cfg(feature = "cancellation")] token: Option<&CancellationToken>,
+) -> (
+    Vec<Event>,
+    Result<Result<StreamEvents, D::Error>, tokio::task::JoinError>,
+)
+where
+    P: 'static + EventParser,
+    Ctx: 'static + Send + Clone,
+    D: ConcurrentDispatchEvent<Ctx>,
+    D::Error: From<P::Error>,
+    Fut: Future<Output = ()>,
+{
+    let mut join_set: tokio::task::JoinSet<Result<StreamEvents, D::Error>> =
+        tokio::task::JoinSet::new();
+
+    let mut stop = std::pin::pin!(stop);
+    let max_in_flight = opts.max_in_flight.unwrap_or(usize::MAX);
+    let parked = Arc::new(Parked::default());
+    let mut queues = OrderQueues::new(opts.order_by);
+
+    let mut result = loop {
+        let in_flight = join_set
+            .len()
+            .saturating_sub(parked.count.load(Ordering::SeqCst));
+
+        tokio::select! {
+            _ = stop.as_mut() => break Ok(Ok(StreamEvents::Break)),
+            // Re-check the limit when a handler frees its slot
+            _ = parked.notify.notified(), if in_flight >= max_in_flight => continue,
+            result = events.try_next(), if in_flight < max_in_flight => match result {
+                Ok(Some(event)) => {
+                    let turn = queues.enqueue(&event);
+                    // Events rejected by all guards of on_if handlers
+                    let Ok(handler) = chain.concurrent_dispatch_event(event, ctx.clone()) else {
+                        continue;
+                    };
+
+                    let task = async move {
+                        let _done = match turn {
+                            Some((prev, done)) => {
+                                if let Some(prev) = prev {
+                                    // Resolves with an error when the previous handler completes
+                                    let _ = prev.await;
+                                }
+                                Some(done)
+                            }
+                            None => None,
+                        };
+
+                        handler.await
+                    };
+                    let task = PARKED.scope(Arc::clone(&parked), task);
+
+                    #[cfg(feature = "cancellation")]
+                    if let Some(token) = token {
+                        join_set.spawn(HANDLER_TOKEN.scope(token.child_token(), task));
+                        continue;
+                    }
+
+                    join_set.spawn(task);
+                }
+                Ok(None) => break Ok(Ok(StreamEvents::Break)),
+                Err(e) => break Ok(Err(e.into())),
+            },
+            result = join_set.join_next(), if !join_set.is_empty() => match result {
+                Some(Ok(Ok(StreamEvents::Continue))) => continue,
+                Some(Ok(Ok(StreamEvents::Break))) => break Ok(Ok(StreamEvents::Break)),
+                Some(err) => break err,
+                None => unreachable!("Dummy task must be running during the whole tokio select! loop"),
+            }
+        }
+    };
+
+    let mut event_buffer = Vec::new();
+    let deadline = opts
+        .shutdown_timeout
+        .map(|timeout| tokio::time::Instant::now() + timeout);
+    let mut aborted = false;
+
+    loop {
+        tokio::select! {
+            joined = join_set.join_next() => match joined {
+                Some(Err(e)) if e.is_cancelled() => (),
+                Some(next) => {
+                    if matches!(result, Ok(Ok(_))) {
+                        result = next;
+                    } else if let Err(e) = next {
+                        log::error!("Handler failed after the dispatcher stopped: {e}");
+                    }
+                }
+                None => break,
+            },
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)), if deadline.is_some() && !aborted => {
+                log::warn!("Aborting {} handlers still running after the shutdown timeout", join_set.len());
+                join_set.abort_all();
+                aborted = true;
+            }
+            event = events.try_next() => match event {
+                Ok(Some(ev)) => event_buffer.push(ev),
+                Ok(None) => (),
+                Err(e) => {
+                    result = Ok(Err(e.into()));
+                    break;
+                }
+            }
+        }
+    }
+
+    (event_buffer, result)
+}
+
+/// Keys used to serialize concurrent handlers. See [`Dispatcher::order_by`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderBy {
+    /// Order handlers of events from the same chat, see [`crate::ext::EventExt::chat_id`]
+    Chat,
+    /// Order handlers of events addressed to the same user
+    User,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct DispatchOptions {
+    max_in_flight: Option<usize>,
+    order_by: Option<OrderBy>,
+    shutdown_timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum OrderKey {
+    Chat(ChatId),
+    User(i64),
+}
+
+type Turn = (Option<oneshot::Receiver<()>>, oneshot::Sender<()>);
+
+// Chains handlers of events with the same key: every handler holds a sender dropped on completion
+// and waits until the sender of the previous handler is dropped.
+struct OrderQueues {
+    order_by: Option<OrderBy>,
+    tails: HashMap<OrderKey, oneshot::Receiver<()>>,
+    purge_at: usize,
+}
+
+impl OrderQueues {
+    const MIN_PURGE_AT: usize = 64;
+
+    fn new(order_by: Option<OrderBy>) -> Self {
+        Self {
+            order_by,
+            tails: HashMap::new(),
+            purge_at: Self::MIN_PURGE_AT,
+        }
+    }
+
+    fn enqueue(&mut self, ev: &Event) -> Option<Turn> {
+        let key = match self.order_by? {
+            OrderBy::Chat => OrderKey::Chat(ev.chat_id()?),
+            OrderBy::User => OrderKey::User(ev.user_id()?),
+        };
+
+        let (done, tail) = oneshot::channel();
+        let prev = self.tails.insert(key, tail);
+
+        if self.tails.len() > self.purge_at {
+            self.tails.retain(|_, tail| {
+                matches!(tail.try_recv(), Err(oneshot::error::TryRecvError::Empty))
+            });
+            self.purge_at = (self.tails.len() * 2).max(Self::MIN_PURGE_AT);
+        }
+
+        Some((prev, done))
+    }
+}
+
+pub trait DispatchEvent<Ctx> {
+    type Error;
+    type Future<'s>: Future<Output = Result<StreamEvents, Self::Error>>
+    where
+        Self: 's,
+        Ctx: 's;
+
+    fn dispatch_event<'s>(
+        &'s mut self,
+        ev: Event,
+        ctx: &'s mut Ctx,
+    ) -> Result<Self::Future<'s>, (Event, &'s mut Ctx)>;
+}
+
+pub trait ConcurrentDispatchEvent<Ctx>
+where
+    Ctx: 'static + Send,
+{
+    type Error: 'static + Send;
+    type Future: 'static + Send + Future<Output = Result<StreamEvents, Self::Error>>;
+
+    fn concurrent_dispatch_event(&self, ev: Event, ctx: Ctx) -> Result<Self::Future, (Event, Ctx)>;
+}
+
+// ---- Dispatcher combinators -----
+
+pub struct Fallback<F> {
+    f: F,
+}
+
+impl<Ctx, E, F> DispatchEvent<Ctx> for Fallback<F>
+where
+    F: AsyncFnMut(Event, &mut Ctx) -> Result<StreamEvents, E>,
+{
+    type Error = E;
+    // TODO: Wait for `async_fn_traits` stabilization and use AsyncFnMut::CallRefFuture<'s> here
+    type Future<'s>
+        = Pin<Box<dyn 's + Future<Output = Result<StreamEvents, E>>>>
+    where
+        Self: 's,
+        Ctx: 's;
+
+    fn dispatch_event<'s>(
+        &'s mut self,
+        ev: Event,
+        ctx: &'s mut Ctx,
+    ) -> Result<Self::Future<'s>, (Event, &'s mut Ctx)> {
+        Ok(Box::pin((self.f)(ev, ctx)))
+    }
+}
+
+impl<Ctx, E, F, Fut> ConcurrentDispatchEvent<Ctx> for Fallback<F>
+where
+    Ctx: 'static + Send,
+    E: 'static + Send,
+    // TODO: Wait for `async_fn_traits` stabilization and use AsyncFn with for<'a>
+    // CallRefFuture<'a>: 'static + Send instead
+    F: Fn(Event, Ctx) -> Fut,
+    Fut: 'static + Send + Future<Output = Result<StreamEvents, E>>,
+{
+    type Error = E;
+    type Future = Fut;
+
+    fn concurrent_dispatch_event(&self, ev: Event, ctx: Ctx) -> Result<Self::Future, (Event, Ctx)> {
+        Ok((self.f)(ev, ctx))
+    }
+}
+
+pub struct Match<Ev, F> {
+    f: F,
+    _phantom: std::marker::PhantomData<Ev>,
+}
+
+impl<Ctx, Ev, E, F> DispatchEvent<Ctx> for Match<Ev, F>
+where
+    Ev: EventData,
+    F: AsyncFnMut(Arc<Ev>, &mut Ctx) -> Result<StreamEvents, E>,
+{
+    type Error = E;
+    // TODO: Wait for `async_fn_traits` stabilization and use AsyncFnMut::CallRefFuture<'s> here
+    type Future<'s>
+        = Pin<Box<dyn 's + Future<Output = Result<StreamEvents, E>>>>
+    where
+        Self: 's,
+        Ctx: 's;
+
+    fn dispatch_event<'s>(
+        &'s mut self,
+        ev: Event,
+        ctx: &'s mut Ctx,
+    ) -> Result<Self::Future<'s>, (Event, &'s mut Ctx)> {
+        match Ev::from_event(ev) {
+            Ok(ev) => Ok(Box::pin((self.f)(ev, ctx))),
+            Err(ev) => Err((ev, ctx)),
+        }
+    }
+}
+
+impl<Ctx, Ev, E, F, Fut> ConcurrentDispatchEvent<Ctx> for Match<Ev, F>
+where
+    Ctx: 'static + Send,
+    Ev: 'static + EventData,
+    E: 'static + Send,
+    // TODO: Wait for `async_fn_traits` stabilization and use AsyncFn with for<'a>
+    // CallRefFuture<'a>: 'static instead
+    F: Fn(Arc<Ev>, Ctx) -> Fut,
+    Fut: 'static + Send + Future<Output = Result<StreamEvents, E>>,
+{
+    type Error = E;
+    type Future = Fut;
+
+    fn concurrent_dispatch_event(&self, ev: Event, ctx: Ctx) -> Result<Self::Future, (Event, Ctx)> {
+        match Ev::from_event(ev) {
+            Ok(ev) => Ok((self.f)(ev, ctx)),
+            Err(ev) => Err((ev, ctx)),
+        }
+    }
+}
+
+/// A [`Match`] guarded by an [`EventPredicate`]. See [`Dispatcher::on_if`] and
+/// [`Dispatcher::seq_if`].
+pub struct MatchIf<Ev, Pr, F> {
+    pred: Pr,
+    f: F,
+    _phantom: std::marker::PhantomData<Ev>,
+}
+
+impl<Ctx, Ev, E, Pr, F> DispatchEvent<Ctx> for MatchIf<Ev, Pr, F>
+where
+    Ev: EventData,
+    Pr: EventPredicate,
+    F: AsyncFnMut(Arc<Ev>, &mut Ctx) -> Result<StreamEvents, E>,
+{
+    type Error = E;
+    // TODO: Wait for `async_fn_traits` stabilization and use AsyncFnMut::CallRefFuture<'s> here
+    type Future<'s>
+        = Pin<Box<dyn 's + Future<Output = Result<StreamEvents, E>>>>
+    where
+        Self: 's,
+        Ctx: 's;
+
+    fn dispatch_event<'s>(
+        &'s mut self,
+        ev: Event,
+        ctx: &'s mut Ctx,
+    ) -> Result<Self::Future<'s>, (Event, &'s mut Ctx)> {
+        if ev.kind() != Ev::KIND || !self.pred.matches(&ev) {
+            return Err((ev, ctx));
+        }
+
+        match Ev::from_event(ev) {
+            Ok(ev) => Ok(Box::pin((self.f)(ev, ctx))),
+            Err(ev) => Err((ev, ctx)),
+        }
+    }
+}
+
+impl<Ctx, Ev, E, Pr, F, Fut> ConcurrentDispatchEvent<Ctx> for MatchIf<Ev, Pr, F>
+where
+    Ctx: 'static + Send,
+    Ev: 'static + EventData,
+    E: 'static + Send,
+    Pr: EventPredicate,
+    // TODO: Wait for `async_fn_traits` stabilization and use AsyncFn with for<'a>
+    // CallRefFuture<'a>: 'static instead
+    F: Fn(Arc<Ev>, Ctx) -> Fut,
+    Fut: 'static + Send + Future<Output = Result<StreamEvents, E>>,
+{
+    type Error = E;
+    type Future = Fut;
+
+    fn concurrent_dispatch_event(&self, ev: Event, ctx: Ctx) -> Result<Self::Future, (Event, Ctx)> {
+        if ev.kind() != Ev::KIND || !self.pred.matches(&ev) {
+            return Err((ev, ctx));
+        }
+
+        match Ev::from_event(ev) {
+            Ok(ev) => Ok((self.f)(ev, ctx)),
+            Err(ev) => Err((ev, ctx)),
+        }
+    }
+}
+
+pub struct Intercept<D1, D2> {
+    d1: D1,
+    d2: D2,
+}
+
+impl<Ctx, D1, D2> DispatchEvent<Ctx> for Intercept<D1, D2>
+where
+    D1: DispatchEvent<Ctx>,
+    D2: DispatchEvent<Ctx, Error = D1::Error>,
+{
+    type Error = D1::Error;
+    type Future<'s>
+        = futures::future::Either<D1::Future<'s>, D2::Future<'s>>
+    where
+        Self: 's,
+        Ctx: 's;
+
+    fn dispatch_event<'s>(
+        &'s mut self,
+        ev: Event,
+        ctx: &'s mut Ctx,
+    ) -> Result<Self::Future<'s>, (Event, &'s mut Ctx)> {
+        self.d1
+            .dispatch_event(ev, ctx)
+            .map(futures::future::Either::Left)
+            .or_else(|(ev, ctx)| {
+                self.d2
+                    .dispatch_event(ev, ctx)
+                    .map(futures::future::Either::Right)
+            })
+    }
+}
+
+impl<Ctx, D1, D2> ConcurrentDispatchEvent<Ctx> for Intercept<D1, D2>
+where
+    Ctx: 'static + Send,
+    D1: ConcurrentDispatchEvent<Ctx>,
+    D2: ConcurrentDispatchEvent<Ctx, Error = D1::Error>,
+{
+    type Error = D1::Error;
+    type Future = futures::future::Either<D1::Future, D2::Future>;
+
+    fn concurrent_dispatch_event(&self, ev: Event, ctx: Ctx) -> Result<Self::Future, (Event, Ctx)> {
+        self.d1
+            .concurrent_dispatch_event(ev, ctx)
+            .map(futures::future::Either::Left)
+            .or_else(|(ev, ctx)| {
+                self.d2
+                    .concurrent_dispatch_event(ev, ctx)
+                    .map(futures::future::Either::Right)
+            })
+    }
+}
+
+/// Middleware wrapping the dispatch chain `D`. See [`Dispatcher::layer`] and
+/// [`Dispatcher::seq_layer`].
+pub struct Layer<D, F> {
+    kinds: [bool; EventKind::COUNT],
+    inner: D,
+    f: F,
+}
+
+impl<Ctx, D, F> DispatchEvent<Ctx> for Layer<D, F>
+where
+    D: DispatchEvent<Ctx>,
+    F: AsyncFnMut(Event, &mut Ctx, Next<'_, D>) -> Result<StreamEvents, D::Error>,
+{
+    type Error = D::Error;
+    // TODO: Wait for `async_fn_traits` stabilization and use AsyncFnMut::CallRefFuture<'s> here
+    type Future<'s>
+        = Pin<Box<dyn 's + Future<Output = Result<StreamEvents, D::Error>>>>
+    where
+        Self: 's,
+        Ctx: 's;
+
+    fn dispatch_event<'s>(
+        &'s mut self,
+        ev: Event,
+        ctx: &'s mut Ctx,
+    ) -> Result<Self::Future<'s>, (Event, &'s mut Ctx)> {
+        if !self.kinds[ev.kind().as_usize()] {
+            return Err((ev, ctx));
+        }
+
+        let next = Next {
+            chain: &mut self.inner,
+            ev: ev.clone(),
+        };
+
+        Ok(Box::pin((self.f)(ev, ctx, next)))
+    }
+}
+
+impl<Ctx, D, F, Fut> ConcurrentDispatchEvent<Ctx> for Layer<D, F>
+where
+    Ctx: 'static + Send + Clone,
+    D: ConcurrentDispatchEvent<Ctx>,
+    F: Fn(Event, Ctx, D::Future) -> Fut,
+    Fut: 'static + Send + Future<Output = Result<StreamEvents, D::Error>>,
+{
+    type Error = D::Error;
+    type Future = Fut;
+
+    fn concurrent_dispatch_event(&self, ev: Event, ctx: Ctx) -> Result<Self::Future, (Event, Ctx)> {
+        if !self.kinds[ev.kind().as_usize()] {
+            return Err((ev, ctx));
+        }
+
+        let handler = self
+            .inner
+            .concurrent_dispatch_event(ev.clone(), ctx.clone())?;
+
+        Ok((self.f)(ev, ctx, handler))
+    }
+}
+
+/// The rest of the sequential dispatch chain passed into [`Dispatcher::seq_layer`] middlewares
+pub struct Next<'a, D> {
+    chain: &'a mut D,
+    ev: Event,
+}
+
+impl<D> Next<'_, D> {
+    /// Run the handler
+    pub async fn run<Ctx>(self, ctx: &mut Ctx) -> Result<StreamEvents, D::Error>
+    where
+        D: DispatchEvent<Ctx>,
+    {
+        // Rejected by the guards of seq_if handlers
+        let Ok(handler) = self.chain.dispatch_event(self.ev, ctx) else {
+            return Ok(StreamEvents::Continue);
+        };
+
+        handler.await
+    }
+}
+
+/// A handler failure reported to [`Dispatcher::on_error`] and [`Dispatcher::seq_on_error`] error
+/// handlers
+pub enum HandlerError<E> {
+    /// The handler returned an error
+    Failed(E),
+    /// The handler panicked. Contains the panic payload
+    Panicked(Box<dyn Any + Send>),
+}
+
+impl<E> HandlerError<E> {
+    /// Returns the handler error or resumes the panic
+    pub fn into_error(self) -> E {
+        match self {
+            Self::Failed(e) => e,
+            Self::Panicked(payload) => std::panic::resume_unwind(payload),
+        }
+    }
+
+    /// The panic message if the handler panicked with a string payload
+    pub fn panic_message(&self) -> Option<&str> {
+        match self {
+            Self::Failed(_) => None,
+            Self::Panicked(payload) => payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str)),
+        }
+    }
+}
+
+impl<E: std::fmt::Debug> std::fmt::Debug for HandlerError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Failed(e) => f.debug_tuple("Failed").field(e).finish(),
+            Self::Panicked(_) => f
+                .debug_tuple("Panicked")
+                .field(&self.panic_message())
+                .finish(),
+        }
+    }
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for HandlerError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Failed(e) => e.fmt(f),
+            Self::Panicked(_) => match self.panic_message() {
+                Some(msg) => write!(f, "handler panicked: {msg}"),
+                None => f.write_str("handler panicked"),
+            },
+        }
+    }
+}
+
+/// Delays between handler retries. See [`Dispatcher::retry`] and [`Dispatcher::seq_retry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    /// Retry immediately
+    pub const NONE: Self = Self::fixed(Duration::ZERO);
+
+    /// Wait `delay` before each retry
+    pub const fn fixed(delay: Duration) -> Self {
+        Self {
+            initial: delay,
+            max: delay,
+        }
+    }
+
+    /// Wait `initial` before the first retry doubling the delay after each retry until it reaches
+    /// `max`
+    pub const fn exponential(initial: Duration, max: Duration) -> Self {
+        Self { initial, max }
+    }
+
+    /// The delay before the `retry`th retry(starting from 0)
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.initial
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max)
+    }
+}
+
+/// The error handler installed by [`Dispatcher::log_and_continue`]
+pub type LogAndContinue<E, Ctx> =
+    fn(HandlerError<E>, Event, Ctx) -> std::future::Ready<Result<StreamEvents, E>>;
+
+/// The error handler installed by [`Dispatcher::seq_log_and_continue`]
+pub type SeqLogAndContinue<E, Ctx> =
+    for<'a> fn(HandlerError<E>, Event, &'a mut Ctx) -> std::future::Ready<Result<StreamEvents, E>>;
+
+fn log_and_continue<E: std::fmt::Display, Ctx>(
+    err: HandlerError<E>,
+    ev: Event,
+    _: Ctx,
+) -> std::future::Ready<Result<StreamEvents, E>> {
+    log::error!("{:?} handler failed: {err}", ev.kind());
+    std::future::ready(Ok(StreamEvents::Continue))
+}
+
+fn seq_log_and_continue<E: std::fmt::Display, Ctx>(
+    err: HandlerError<E>,
+    ev: Event,
+    _: &mut Ctx,
+) -> std::future::Ready<Result<StreamEvents, E>> {
+    log::error!("{:?} handler failed: {err}", ev.kind());
+    std::future::ready(Ok(StreamEvents::Continue))
+}
+
+// Polls the future catching panics. The panicked future is dropped, but the state it shares
+// through `Ctx` (e.g. an `Arc<Mutex<_>>`) may be left half-updated and the following handlers,
+// retries and error handlers keep running against it. Recovering from panics opts into this
+// risk, hence `AssertUnwindSafe`.
+async fn catch_unwind<F: Future>(fut: F) -> Result<F::Output, Box<dyn Any + Send>> {
+    let mut fut = std::pin::pin!(fut);
+    std::future::poll_fn(|cx| {
+        match std::panic::catch_unwind(AssertUnwindSafe(|| fut.as_mut().poll(cx))) {
+            Ok(poll) => poll.map(Ok),
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    })
+    .await
+}
+
+/// Retries failed sequential handlers of the chain `D`. See [`Dispatcher::seq_retry`].
+pub struct SeqRetry<D> {
+    kinds: [bool; EventKind::COUNT],
+    inner: D,
+    attempts: u32,
+    backoff: Backoff,
+}
+
+impl<Ctx, D> DispatchEvent<Ctx> for SeqRetry<D>
+where
+    D: DispatchEvent<Ctx>,
+{
+    type Error = D::Error;
+    type Future<'s>
+        = Pin<Box<dyn 's + Future<Output = Result<StreamEvents, D::Error>>>>
+    where
+        Self: 's,
+        Ctx: 's;
+
+    fn dispatch_event<'s>(
+        &'s mut self,
+        ev: Event,
+        ctx: &'s mut Ctx,
+    ) -> Result<Self::Future<'s>, (Event, &'s mut Ctx)> {
+        if !self.kinds[ev.kind().as_usize()] {
+            return Err((ev, ctx));
+        }
+
+        let Self {
+            inner,
+            attempts,
+            backoff,
+            ..
+        } = self;
+
+        Ok(Box::pin(async move {
+            let mut retry = 0;
+            loop {
+                // Rejected by the guards of seq_if handlers
+                let Ok(handler) = inner.dispatch_event(ev.clone(), ctx) else {
+                    break Ok(StreamEvents::Continue);
+                };
+
+                match handler.await {
+                    Err(_) if retry < *attempts => {
+                        log::debug!("Retrying {:?} handler", ev.kind());
+                        tokio::time::sleep(backoff.delay(retry)).await;
+                        retry += 1;
+                    }
+                    result => break result,
+                }
+            }
+        }))
+    }
+}
+
+/// Retries failed concurrent handlers of the chain `D`. See [`Dispatcher::retry`].
+pub struct Retry<D> {
+    kinds: [bool; EventKind::COUNT],
+    inner: Arc<D>,
+    attempts: u32,
+    backoff: Backoff,
+}
+
+impl<Ctx, D> ConcurrentDispatchEvent<Ctx> for Retry<D>
+where
+    Ctx: 'static + Send + Clone,
+    D: 'static + Send + Sync + ConcurrentDispatchEvent<Ctx>,
+{
+    type Error = D::Error;
+    type Future = Pin<Box<dyn Send + Future<Output = Result<StreamEvents, D::Error>>>>;
+
+    fn concurrent_dispatch_event(&self, ev: Event, ctx: Ctx) -> Result<Self::Future, (Event, Ctx)> {
+        if !self.kinds[ev.kind().as_usize()] {
+            return Err((ev, ctx));
+        }
+
+        let mut handler = self
+            .inner
+            .concurrent_dispatch_event(ev.clone(), ctx.clone())?;
+        let inner = Arc::clone(&self.inner);
+        let attempts = self.attempts;
+        let backoff = self.backoff;
+
+        Ok(Box::pin(async move {
+            let mut retry = 0;
+            loop {
+                match handler.await {
+                    Err(_) if retry < attempts => {
+                        log::debug!("Retrying {:?} handler", ev.kind());
+                        tokio::time::sleep(backoff.delay(retry)).await;
+                        retry += 1;
+                    }
+                    result => break result,
+                }
+
+                handler = match inner.concurrent_dispatch_event(ev.clone(), ctx.clone()) {
+                    Ok(handler) => handler,
+                    // Rejected by the guards of on_if handlers
+                    Err(_) => break Ok(StreamEvents::Continue),
+                };
+            }
+        }))
+    }
+}
+
+/// Routes errors and panics of the sequential chain `D` to an error handler. See
+/// [`Dispatcher::seq_on_error`].
+pub struct SeqRecover<D, F> {
+    kinds: [bool; EventKind::COUNT],
+    inner: D,
+    f: F,
+}
+
+impl<Ctx, D, F> DispatchEvent<Ctx> for SeqRecover<D, F>
+where
+    D: DispatchEvent<Ctx>,
+    F: AsyncFnMut(HandlerError<D::Error>, Event, &mut Ctx) -> Result<StreamEvents, D::Error>,
+{
+    type Error = D::Error;
+    // TODO: Wait for `async_fn_traits` stabilization and use AsyncFnMut::CallRefFuture<'s> here
+    type Future<'s>
+        = Pin<Box<dyn 's + Future<Output = Result<StreamEvents, D::Error>>>>
+    where
+        Self: 's,
+        Ctx: 's;
+
+    fn dispatch_event<'s>(
+        &'s mut self,
+        ev: Event,
+        ctx: &'s mut Ctx,
+    ) -> Result<Self::Future<'s>, (Event, &'s mut Ctx)> {
+        if !self.kinds[ev.kind().as_usize()] {
+            return Err((ev, ctx));
+        }
+
+        let Self { inner, f, .. } = self;
+
+        Ok(Box::pin(async move {
+            // Rejected by the guards of seq_if handlers
+            let Ok(handler) = inner.dispatch_event(ev.clone(), ctx) else {
+                return Ok(StreamEvents::Continue);
+            };
+
+            let err = match catch_unwind(handler).await {
+                Ok(Ok(flow)) => return Ok(flow),
+                Ok(Err(e)) => HandlerError::Failed(e),
+                Err(payload) => HandlerError::Panicked(payload),
+            };
+
+            f(err, ev, ctx).await
+        }))
+    }
+}
+
+/// Routes errors and panics of the concurrent chain `D` to an error handler. See
+/// [`Dispatcher::on_error`].
+pub struct Recover<D, F> {
+    kinds: [bool; EventKind::COUNT],
+    inner: D,
+    f: Arc<F>,
+}
+
+impl<Ctx, D, F, Fut> ConcurrentDispatchEvent<Ctx> for Recover<D, F>
+where
+    Ctx: 'static + Send + Clone,
+    D: ConcurrentDispatchEvent<Ctx>,
+    F: 'static + Send + Sync + Fn(HandlerError<D::Error>, Event, Ctx) -> Fut,
+    Fut: 'static + Send + Future<Output = Result<StreamEvents, D::Error>>,
+{
+    type Error = D::Error;
+    type Future = Pin<Box<dyn Send + Future<Output = Result<StreamEvents, D::Error>>>>;
+
+    fn concurrent_dispatch_event(&self, ev: Event, ctx: Ctx) -> Result<Self::Future, (Event, Ctx)> {
+        if !self.kinds[ev.kind().as_usize()] {
+            return Err((ev, ctx));
+        }
+
+        let handler = self
+            .inner
+            .concurrent_dispatch_event(ev.clone(), ctx.clone())?;
+        let f = Arc::clone(&self.f);
+
+        Ok(Box::pin(async move {
+            let err = match catch_unwind(handler).await {
+                Ok(Ok(flow)) => return Ok(flow),
+                Ok(Err(e)) => HandlerError::Failed(e),
+                Err(payload) => HandlerError::Panicked(payload),
+            };
+
+            f(err, ev, ctx).await
+        }))
+    }
+}
+
+/// The timeout callback installed by [`Dispatcher::timeout`]
+pub type LogTimeout<E, Ctx> = fn(Event, Ctx) -> std::future::Ready<Result<StreamEvents, E>>;
+
+/// The timeout callback installed by [`Dispatcher::seq_timeout`]
+pub type SeqLogTimeout<E, Ctx> =
+    for<'a> fn(Event, &'a mut Ctx) -> std::future::Ready<Result<StreamEvents, E>>;
+
+fn log_timeout<E, Ctx>(ev: Event, _: Ctx) -> std::future::Ready<Result<StreamEvents, E>> {
+    log::warn!("{:?} handler timed out", ev.kind());
+    std::future::ready(Ok(StreamEvents::Continue))
+}
+
+fn seq_log_timeout<E, Ctx>(ev: Event, _: &mut Ctx) -> std::future::Ready<Result<StreamEvents, E>> {
+    log::warn!("{:?} handler timed out", ev.kind());
+    std::future::ready(Ok(StreamEvents::Continue))
+}
+
+thread_local! {
+    // Set by the timeout wrapper that accepted the event being dispatched
+    static TIMEOUT_CLAIMED: Cell<bool> = const { Cell::new(false) };
+}
+
+fn claim_timeout() {
+    TIMEOUT_CLAIMED.set(true);
+}
+
+// Dispatches the event into a wrapped chain reporting whether a nested timeout accepted it. The
+// nested timeouts are registered earlier and their limits take precedence.
+fn dispatch_unclaimed<T>(dispatch: impl FnOnce() -> T) -> (T, bool) {
+    let outer = TIMEOUT_CLAIMED.replace(false);
+    let result = dispatch();
+    let claimed = TIMEOUT_CLAIMED.replace(outer);
+
+    (result, claimed)
+}
+
+/// Cancels sequential handlers of the chain `D` running for too long. See
+/// [`Dispatcher::seq_timeout`].
+pub struct SeqTimeout<D, F> {
+    kinds: [bool; EventKind::COUNT],
+    inner: D,
+    duration: Duration,
+    f: F,
+}
+
+impl<Ctx, D, F> DispatchEvent<Ctx> for SeqTimeout<D, F>
+where
+    D: DispatchEvent<Ctx>,
+    F: AsyncFnMut(Event, &mut Ctx) -> Result<StreamEvents, D::Error>,
+{
+    type Error = D::Error;
+    // TODO: Wait for `async_fn_traits` stabilization and use AsyncFnMut::CallRefFuture<'s> here
+    type Future<'s>
+        = Pin<Box<dyn 's + Future<Output = Result<StreamEvents, D::Error>>>>
+    where
+        Self: 's,
+        Ctx: 's;
+
+    fn dispatch_event<'s>(
+        &'s mut self,
+        ev: Event,
+        ctx: &'s mut Ctx,
+    ) -> Result<Self::Future<'s>, (Event, &'s mut Ctx)> {
+        if !self.kinds[ev.kind().as_usize()] {
+            return Err((ev, ctx));
+        }
+
+        claim_timeout();
+        let Self {
+            inner, duration, f, ..
+        } = self;
+
+        Ok(Box::pin(async move {
+            let (handler, claimed) =
+                dispatch_unclaimed(|| inner.dispatch_event(ev.clone(), ctx).ok());
+
+            // Rejected by the guards of seq_if handlers. Moved out as a whole so the borrow of
+            // `ctx` ends with the handler
+            let Some(handler) = ({ handler }) else {
+                return Ok(StreamEvents::Continue);
+            };
+
+            if claimed {
+                return handler.await;
+            }
+
+            match tokio::time::timeout(*duration, handler).await {
+                Ok(result) => result,
+                Err(_) => f(ev, ctx).await,
+            }
+        }))
+    }
+}
+
+/// Cancels concurrent handlers of the chain `D` running for too long. See
+/// [`Dispatcher::timeout`].
+pub struct Timeout<D, F> {
+    kinds: [bool; EventKind::COUNT],
+    inner: D,
+    duration: Duration,
+    f: Arc<F>,
+}
+
+impl<Ctx, D, F, Fut> ConcurrentDispatchEvent<Ctx> for Timeout<D, F>
+where
+    Ctx: 'static + Send + Clone,
+    D: ConcurrentDispatchEvent<Ctx>,
+    F: 'static + Send + Sync + Fn(Event, Ctx) -> Fut,
+    Fut: 'static + Send + Future<Output = Result<StreamEvents, D::Error>>,
+{
+    type Error = D::Error;
+    type Future = Pin<Box<dyn Send + Future<Output = Result<StreamEvents, D::Error>>>>;
+
+    fn concurrent_dispatch_event(&self, ev: Event, ctx: Ctx) -> Result<Self::Future, (Event, Ctx)> {
+        if !self.kinds[ev.kind().as_usize()] {
+            return Err((ev, ctx));
+        }
+
+        let (handler, claimed) = dispatch_unclaimed(|| {
+            self.inner
+                .concurrent_dispatch_event(ev.clone(), ctx.clone())
+        });
+        let handler = handler?;
+        claim_timeout();
+
+        if claimed {
+            return Ok(Box::pin(handler));
+        }
+
+        let duration = self.duration;
+        let f = Arc::clone(&self.f);
+
+        Ok(Box::pin(async move {
+            match tokio::time::timeout(duration, handler).await {
+                Ok(result) => result,
+                Err(_) => f(ev, ctx).await,
+            }
+        }))
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::mock::{self, MockError};
+
+    use std::sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    #[tokio::test]
+    async fn retry_and_recover() {
+        let (_, events, sender) = mock::init();
+        push_numbered(&sender, 3);
+        sender.close();
+
+        let log = Log::default();
+
+        events
+            .into_dispatcher(log.clone())
+            .fallback(async |ev, log: Log| {
+                let n = number(&ev);
+
+                let attempt = {
+                    let mut log = log.lock().unwrap();
+                    log.push(format!("call {n}"));
+                    log.iter().filter(|s| **s == format!("call {n}")).count()
+                };
+
+                match (n, attempt) {
+                    (0, 1) | (1, _) => Err(MockError::Unexpected(format!("fail {n}"))),
+                    (2, _) => panic!("boom"),
+                    _ => Ok(StreamEvents::Continue),
+                }
+            })
+            .retry(1, Backoff::NONE)
+            .on_error(async |err, _, log: Log| {
+                log.lock().unwrap().push(err.to_string());
+                Ok(StreamEvents::Continue)
+            })
+            .dispatch()
+            .await
+            .unwrap();
+
+        let mut log = log.lock().unwrap().clone();
+        log.sort();
+
+        assert_eq!(
+            log,
+            [
+                "Unexpected mock command: fail 1",
+                "call 0",
+                "call 0",
+                "call 1",
+                "call 1",
+                "call 2",
+                "handler panicked: boom",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn max_in_flight() {
+        let (_, events, sender) = mock::init();
+        push_numbered(&sender, 10);
+        sender.close();
+
+        let in_flight = Arc::new((AtomicUsize::new(0), AtomicUsize::new(0)));
+
+        events
+            .into_dispatcher(in_flight.clone())
+            .fallback(async |_, in_flight: Arc<(AtomicUsize, AtomicUsize)>| {
+                let current = in_flight.0.fetch_add(1, Ordering::SeqCst) + 1;
+                in_flight.1.fetch_max(current, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                in_flight.0.fetch_sub(1, Ordering::SeqCst);
+                Ok::<_, MockError>(StreamEvents::Continue)
+            })
+            .max_in_flight(2)
+            .dispatch()
+            .await
+            .unwrap();
+
+        assert_eq!(in_flight.1.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn guarded_handlers() {
+        use simploxide_api_types::events::HostConnected;
+
+        fn host(name: &'static str) -> impl EventPredicate {
+            move |ev: &Event| matches!(ev, Event::HostConnected(ev) if ev.transport_host == name)
+        }
+
+        let (_, mut events, sender) = mock::init();
+        for name in ["a", "b", "c", "d"] {
+            sender
+                .push_json(serde_json::json!({
+                    "type": "hostConnected",
+                    "protocol": "smp",
+                    "transportHost": name,
+                }))
+                .unwrap();
+        }
+        sender.close();
+
+        events.set_predicate(host("d").not());
+        let log = Log::default();
+
+        events
+            .into_dispatcher(log.clone())
+            .on_if(host("a"), async |ev: Arc<HostConnected>, log: Log| {
+                log.lock()
+                    .unwrap()
+                    .push(format!("a: {}", ev.transport_host));
+                Ok::<_, MockError>(StreamEvents::Continue)
+            })
+            .on_if(
+                host("b").or(host("d")),
+                async |ev: Arc<HostConnected>, log| {
+                    log.lock()
+                        .unwrap()
+                        .push(format!("b: {}", ev.transport_host));
+                    Ok(StreamEvents::Continue)
+                },
+            )
+            // Overlaps with the first guard and takes priority as the latest one
+            .on_if(
+                host("a").or(host("c")),
+                async |ev: Arc<HostConnected>, log| {
+                    log.lock()
+                        .unwrap()
+                        .push(format!("a|c: {}", ev.transport_host));
+                    Ok(StreamEvents::Continue)
+                },
+            )
+            .dispatch()
+            .await
+            .unwrap();
+
+        let mut log = log.lock().unwrap().clone();
+        log.sort();
+
+        assert_eq!(log, ["a|c: a", "a|c: c", "b: b"]);
+    }
+
+    fn push_numbered(sender: &mock::EventSender, count: u64) {
+        for n in 0..count {
+            sender
+                .push_json(serde_json::json!({ "type": "testEvent", "n": n }))
+                .unwrap();
+        }
+    }
+
+    fn number(ev: &Event) -> u64 {
+        let Event::Undocumented(json) = ev else {
+            unreachable!()
+        };
+
+        json["n"].as_u64().unwrap()
+    }
+
+    fn push_hosts(sender: &mock::EventSender, hosts: &[(&str, &str)]) {
+        for (kind, name) in hosts {
+            sender
+                .push_json(serde_json::json!({
+                    "type": kind,
+                    "protocol": "smp",
+                    "transportHost": name,
+                }))
+                .unwrap();
+        }
+    }
+
+    fn host(ev: &Event) -> &str {
+        match ev {
+            Event::HostConnected(ev) => &ev.transport_host,
+            Event::HostDisconnected(ev) => &ev.transport_host,
+            _ => unreachable!(),
+        }
+    }
+
+    const LAYER_EVENTS: [(&str, &str); 3] = [
+        ("hostConnected", "a"),
+        ("hostConnected", "skip"),
+        ("hostDisconnected", "a"),
+    ];
+
+    const LAYER_LOG: [&str; 5] = [
+        "before a",
+        "connected a",
+        "after a",
+        "skipped",
+        "disconnected a",
+    ];
+
+    #[tokio::test]
+    async fn layers() {
+        use simploxide_api_types::events::{HostConnected, HostDisconnected};
+
+        let (_, events, sender) = mock::init();
+        push_hosts(&sender, &LAYER_EVENTS);
+        sender.close();
+
+        let log = Log::default();
+
+        events
+            .into_dispatcher(log.clone())
+            .on(async |ev: Arc<HostConnected>, log: Log| {
+                log.lock()
+                    .unwrap()
+                    .push(format!("connected {}", ev.transport_host));
+                Ok::<_, MockError>(StreamEvents::Continue)
+            })
+            // Wraps only the handler registered before it
+            .layer(async |ev, log: Log, next| {
+                if host(&ev) == "skip" {
+                    log.lock().unwrap().push("skipped".to_owned());
+                    return Ok(StreamEvents::Continue);
+                }
+
+                log.lock().unwrap().push(format!("before {}", host(&ev)));
+                let result = next.await;
+                log.lock().unwrap().push(format!("after {}", host(&ev)));
+                result
+            })
+            .on(async |ev: Arc<HostDisconnected>, log: Log| {
+                log.lock()
+                    .unwrap()
+                    .push(format!("disconnected {}", ev.transport_host));
+                Ok(StreamEvents::Continue)
+            })
+            .max_in_flight(1)
+            .dispatch()
+            .await
+            .unwrap();
+
+        assert_eq!(*log.lock().unwrap(), LAYER_LOG);
+    }
+
+    #[tokio::test]
+    async fn sequential_layers() {
+        use simploxide_api_types::events::{HostConnected, HostDisconnected};
+
+        let (_, events, sender) = mock::init();
+        push_hosts(&sender, &LAYER_EVENTS);
+        sender.close();
+
+        let (_, log) = events
+            .into_dispatcher(Vec::<String>::new())
+            .seq(async |ev: Arc<HostConnected>, log: &mut Vec<String>| {
+                log.push(format!("connected {}", ev.transport_host));
+                Ok::<_, MockError>(StreamEvents::Continue)
+            })
+            // Wraps only the handler registered before it
+            .seq_layer(async |ev, log, next| {
+                if host(&ev) == "skip" {
+                    log.push("skipped".to_owned());
+                    return Ok(StreamEvents::Continue);
+                }
+
+                log.push(format!("before {}", host(&ev)));
+                let result = next.run(log).await;
+                log.push(format!("after {}", host(&ev)));
+                result
+            })
+            .seq(async |ev: Arc<HostDisconnected>, log: &mut Vec<String>| {
+                log.push(format!("disconnected {}", ev.transport_host));
+                Ok(StreamEvents::Continue)
+            })
+            .sequential_dispatch()
+            .await
+            .unwrap();
+
+        assert_eq!(log, LAYER_LOG);
+    }
+
+    #[tokio::test]
+    async fn handler_timeouts() {
+        let (_, events, sender) = mock::init();
+        push_numbered(&sender, 2);
+        sender.close();
+
+        let log = Log::default();
+
+        events
+            .into_dispatcher(log.clone())
+            .fallback(async |ev, log: Log| {
+                if number(&ev) == 0 {
+                    std::future::pending::<()>().await;
+                }
+
+                log.lock().unwrap().push("done".to_owned());
+                Ok::<_, MockError>(StreamEvents::Continue)
+            })
+            .timeout_with(Duration::from_millis(20), async |ev, log: Log| {
+                log.lock().unwrap().push(format!("timeout {}", number(&ev)));
+                Ok(StreamEvents::Continue)
+            })
+            .dispatch()
+            .await
+            .unwrap();
+
+        let mut log = log.lock().unwrap().clone();
+        log.sort();
+
+        assert_eq!(log, ["done", "timeout 0"]);
+    }
+
+    const TIMEOUT_EVENTS: [(&str, &str); 2] = [("hostConnected", "a"), ("hostDisconnected", "a")];
+
+    #[tokio::test]
+    async fn nested_timeouts() {
+        use simploxide_api_types::events::{HostConnected, HostDisconnected};
+
+        let (_, events, sender) = mock::init();
+        push_hosts(&sender, &TIMEOUT_EVENTS);
+        sender.close();
+
+        let log = Log::default();
+
+        events
+            .into_dispatcher(log.clone())
+            .on(async |_: Arc<HostConnected>, log: Log| {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                log.lock().unwrap().push("connected".to_owned());
+                Ok::<_, MockError>(StreamEvents::Continue)
+            })
+            .timeout_with(Duration::from_secs(10), async |_, log: Log| {
+                log.lock().unwrap().push("timeout 10s".to_owned());
+                Ok(StreamEvents::Continue)
+            })
+            .on(async |_: Arc<HostDisconnected>, _| {
+                std::future::pending::<()>().await;
+                Ok(StreamEvents::Continue)
+            })
+            // Doesn't cut the earlier handler with the longer limit
+            .timeout_with(Duration::from_millis(20), async |_, log: Log| {
+                log.lock().unwrap().push("timeout 20ms".to_owned());
+                Ok(StreamEvents::Continue)
+            })
+            .dispatch()
+            .await
+            .unwrap();
+
+        let mut log = log.lock().unwrap().clone();
+        log.sort();
+
+        assert_eq!(log, ["connected", "timeout 20ms"]);
+    }
+
+    #[tokio::test]
+    async fn nested_sequential_timeouts() {
+        use simploxide_api_types::events::{HostConnected, HostDisconnected};
+
+        let (_, events, sender) = mock::init();
+        push_hosts(&sender, &TIMEOUT_EVENTS);
+        sender.close();
+
+        let (_, log) = events
+            .into_dispatcher(Vec::<String>::new())
+            .seq(async |_: Arc<HostConnected>, log: &mut Vec<String>| {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                log.push("connected".to_owned());
+                Ok::<_, MockError>(StreamEvents::Continue)
+            })
+            .seq_timeout_with(Duration::from_secs(10), async |_, log| {
+                log.push("timeout 10s".to_owned());
+                Ok(StreamEvents::Continue)
+            })
+            .seq(async |_: Arc<HostDisconnected>, _: &mut Vec<String>| {
+                std::future::pending::<()>().await;
+                Ok(StreamEvents::Continue)
+            })
+            .seq_timeout_with(Duration::from_millis(20), async |_, log| {
+                log.push("timeout 20ms".to_owned());
+                Ok(StreamEvents::Continue)
+            })
+            .sequential_dispatch()
+            .await
+            .unwrap();
+
+        assert_eq!(log, ["connected", "timeout 20ms"]);
+    }
+
+    #[cfg(feature = "cancellation")]
+    #[tokio::test]
+    async fn cancellation_and_shutdown_timeout() {
+        let (_, events, sender) = mock::init();
+        push_numbered(&sender, 2);
+
+        let log = Log::default();
+        let token = CancellationToken::new();
+
+        tokio::spawn({
+            let token = token.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                token.cancel();
+            }
+        });
+
+        events
+            .into_dispatcher(log.clone())
+            .fallback(async |ev, log: Log| {
+                if number(&ev) == 0 {
+                    handler_token().unwrap().cancelled().await;
+                    log.lock().unwrap().push("cancelled".to_owned());
+                } else {
+                    std::future::pending::<()>().await;
+                    log.lock().unwrap().push("unreachable".to_owned());
+                }
+
+                Ok::<_, MockError>(StreamEvents::Continue)
+            })
+            .shutdown_timeout(Duration::from_millis(20))
+            .dispatch_with_cancellation(token)
+            .await
+            .unwrap();
+
+        assert_eq!(*log.lock().unwrap(), ["cancelled"]);
+        assert!(handler_token().is_none());
+        drop(sender);
+    }
+
+    #[test]
+    fn backoff_delays() {
+        let backoff = Backoff::exponential(Duration::from_millis(100), Duration::from_millis(300));
+        assert_eq!(backoff.delay(0), Duration::from_millis(100));
+        assert_eq!(backoff.delay(1), Duration::from_millis(200));
+        assert_eq!(backoff.delay(2), Duration::from_millis(300));
+        assert_eq!(backoff.delay(40), Duration::from_millis(300));
+        assert_eq!(Backoff::NONE.delay(3), Duration::ZERO);
+    }
+}
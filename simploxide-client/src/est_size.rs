@@ -2,6 +2,14 @@ use simploxide_api_types::{
     GroupProfile, LinkContent, LinkOwnerSig, LinkPreview, MsgChatLink, MsgContent, Profile,
 };
 
+/// The text size in bytes fitting into a single message with room left for the message envelope
+pub const MAX_TEXT_SIZE: usize = 15_000;
+
+const FENCE: &str = "```";
+
+/// The smallest `max_size` accepted by [`split_text`]
+pub const MIN_SPLIT_SIZE: usize = 64;
+
 pub trait EstSize {
     fn est_size(&self) -> usize;
 }
@@ -100,3 +108,199 @@ impl EstSize for GroupProfile {
             + self.image.as_deref().map(str::len).unwrap_or(0)
     }
 }
+
+/// Splits the text into parts of at most `max_size` bytes.
+///
+/// The text is cut on paragraph boundaries when possible, then on line boundaries, then on
+/// whitespace outside of formatting spans like `*bold*` or `` `code` ``, and only as the last
+/// resort on arbitrary character boundaries. Code blocks cut across parts are closed at the end of
+/// one part and reopened in the next one.
+///
+/// # Panics
+///
+/// If `max_size` is less than [`MIN_SPLIT_SIZE`]
+pub fn split_text(text: &str, max_size: usize) -> Vec<String> {
+    assert!(
+        max_size >= MIN_SPLIT_SIZE,
+        "max_size must be at least {MIN_SPLIT_SIZE}"
+    );
+
+    let mut parts = Vec::new();
+    let mut rest = text.to_owned();
+
+    while rest.len() > max_size {
+        let cut = find_cut(&rest, max_size);
+        let mut part = rest[..cut.at].trim_end().to_owned();
+        let next = rest[cut.at + cut.skip..].trim_start_matches('\n');
+
+        rest = if cut.in_code {
+            part.push('\n');
+            part.push_str(FENCE);
+            format!("{FENCE}\n{next}")
+        } else {
+            next.to_owned()
+        };
+
+        if !part.is_empty() {
+            parts.push(part);
+        }
+    }
+
+    if !rest.is_empty() || parts.is_empty() {
+        parts.push(rest);
+    }
+
+    parts
+}
+
+struct Cut {
+    /// The part ends here
+    at: usize,
+    /// The number of bytes skipped before the next part
+    skip: usize,
+    /// The cut is inside a code block
+    in_code: bool,
+}
+
+impl Cut {
+    fn line(at: usize, in_code: bool) -> Self {
+        Self {
+            at,
+            skip: 0,
+            in_code,
+        }
+    }
+}
+
+fn find_cut(text: &str, max_size: usize) -> Cut {
+    // Room for closing a code block
+    let code_limit = max_size - FENCE.len() - 1;
+
+    let mut in_code = false;
+    let mut code_start = 0;
+    let mut paragraph = None;
+    let mut line = None;
+    let mut code_line = None;
+    let mut overflow = (0, false);
+    let mut pos = 0;
+
+    for text_line in text.split_inclusive('\n') {
+        let end = pos + text_line.len();
+        let line_in_code = in_code;
+
+        if text_line.trim_start().starts_with(FENCE) {
+            in_code = !in_code;
+            code_start = end;
+        }
+
+        if end > max_size {
+            overflow = (pos, line_in_code);
+            break;
+        }
+
+        if in_code {
+            if end > code_start && end <= code_limit {
+                code_line = Some(end);
+            }
+        } else {
+            if text_line.trim().is_empty() && pos > 0 {
+                paragraph = Some(end);
+            }
+
+            line = Some(end);
+        }
+
+        pos = end;
+    }
+
+    // Don't produce tiny parts just to end them on a paragraph
+    if let Some(at) = paragraph.filter(|at| *at >= max_size / 2) {
+        return Cut::line(at, false);
+    }
+
+    if let Some(at) = line.filter(|at| *at > 0) {
+        return Cut::line(at, false);
+    }
+
+    if let Some(at) = code_line {
+        return Cut::line(at, true);
+    }
+
+    // The line at `overflow` doesn't fit on its own
+    let (line_start, mut in_code) = overflow;
+    let limit = floor_char_boundary(text, if in_code { code_limit } else { max_size });
+
+    // Only possible with an enormous code block header
+    if in_code && code_start >= limit {
+        in_code = false;
+    }
+
+    let start = line_start
+        .max(if in_code { code_start } else { 0 })
+        .min(limit);
+
+    if let Some((at, ws)) = last_safe_whitespace(&text[start..limit], in_code) {
+        return Cut {
+            at: start + at,
+            skip: ws.len_utf8(),
+            in_code,
+        };
+    }
+
+    Cut {
+        at: limit,
+        skip: 0,
+        in_code,
+    }
+}
+
+/// Finds the last whitespace that isn't inside of a formatting span
+fn last_safe_whitespace(line: &str, in_code: bool) -> Option<(usize, char)> {
+    const MARKERS: [char; 5] = ['*', '_', '~', '`', '#'];
+
+    let mut open: Option<char> = None;
+    let mut last = None;
+    let mut prev: Option<char> = None;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|(_, c)| *c);
+        let at_word_start = prev.is_none_or(char::is_whitespace);
+
+        match open {
+            _ if in_code => {}
+            None if MARKERS.contains(&c)
+                && at_word_start
+                && next.is_some_and(|next| !next.is_whitespace()) =>
+            {
+                open = Some(c);
+            }
+            // Colored text: `!1 text!`
+            None if c == '!' && at_word_start && next.is_some_and(|next| next.is_ascii_digit()) => {
+                open = Some(c);
+            }
+            Some(marker) if c == marker && prev.is_some_and(|prev| !prev.is_whitespace()) => {
+                open = None;
+            }
+            _ => {}
+        }
+
+        if c.is_whitespace() && open.is_none() && i > 0 {
+            last = Some((i, c));
+        }
+
+        prev = Some(c);
+    }
+
+    last
+}
+
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+
+    index
+}
@@ -1,11 +1,11 @@
 //! Provides [`ClientApiExt`] with type-safe wrappers over raw [`ClientApi`]
 
-use futures::FutureExt as _;
+use futures::{FutureExt as _, Stream, StreamExt as _, TryStreamExt as _};
 use simploxide_api_types::{
-    AChatItem, AddressSettings, CIDeleteMode, CIFile, ChatDeleteMode, ChatItem, ChatListQuery,
-    Contact, CryptoFile, GroupInfo, GroupMember, GroupMemberRole, GroupProfile, JsonObject,
-    MsgContent, MsgReaction, NewUser, PaginationByTime, PlanResolveMode, Preferences, Profile,
-    UpdatedMessage, UserInfo,
+    AChatItem, AddressSettings, CIDeleteMode, CIFile, ChatDeleteMode, ChatInfo, ChatItem,
+    ChatListQuery, Contact, CryptoFile, GroupInfo, GroupMember, GroupMemberRole, GroupProfile,
+    JsonObject, MsgContent, MsgReaction, NewUser, PaginationByTime, PlanResolveMode, Preferences,
+    Profile, UpdatedMessage, UserInfo,
     client_api::{
         AllowUndocumentedResponses as _, BadResponseError, ClientApi, ClientApiError as _,
         ExtractResponse as _, UndocumentedResponse,
@@ -16,6 +16,7 @@ use simploxide_api_types::{
         ApiSetGroupCustomData, ApiSetProfileAddress, ApiSetUserAutoAcceptMemberContacts,
         ApiUpdateChatItem, Connect, ReceiveFile,
     },
+    events::Event,
     responses::{
         AcceptingContactRequestResponse, ActiveUserResponse, ApiAddGroupRelaysResponse,
         ApiChatsResponse, ApiDeleteChatResponse, ApiNewPublicGroupResponse,
@@ -25,14 +26,14 @@ use simploxide_api_types::{
         GroupCreatedResponse, GroupLinkCreatedResponse, GroupLinkDeletedResponse,
         GroupLinkResponse, GroupRelaysResponse, GroupUpdatedResponse, InvitationResponse,
         LeftMemberUserResponse, MemberAcceptedResponse, MembersBlockedForAllUserResponse,
-        MembersRoleUserResponse, ReceiveFileResponse, RelayGroupAllowedResponse,
-        SentGroupInvitationResponse, UserAcceptedGroupSentResponse, UserContactLinkCreatedResponse,
-        UserContactLinkDeletedResponse, UserContactLinkResponse, UserContactLinkUpdatedResponse,
-        UserDeletedMembersResponse, UserProfileUpdatedResponse,
+        MembersRoleUserResponse, NewChatItemsResponse, ReceiveFileResponse,
+        RelayGroupAllowedResponse, SentGroupInvitationResponse, UserAcceptedGroupSentResponse,
+        UserContactLinkCreatedResponse, UserContactLinkDeletedResponse, UserContactLinkResponse,
+        UserContactLinkUpdatedResponse, UserDeletedMembersResponse, UserProfileUpdatedResponse,
     },
 };
 
-use std::{pin::Pin, sync::Arc};
+use std::{collections::BTreeMap, pin::Pin, sync::Arc};
 
 use crate::{
     id::{
@@ -81,6 +82,7 @@ pub type DeleteMessageResponse<C> = Result<Arc<ChatItemsDeletedResponse>, <C as
 pub type UpdateMessageReactionsResponse<C> =
     Vec<Result<Arc<ChatItemReactionResponse>, <C as ClientApi>::Error>>;
 pub type UpdateMessageResponse<C> = Result<ApiUpdateChatItemResponse, <C as ClientApi>::Error>;
+pub type ForwardMessagesResponse<C> = Result<Arc<NewChatItemsResponse>, <C as ClientApi>::Error>;
 
 pub type NewUserResponse<C> = Result<Arc<ActiveUserResponse>, <C as ClientApi>::Error>;
 pub type UsersResponse<C> = Result<Vec<UserInfo>, <C as ClientApi>::Error>;
@@ -112,6 +114,8 @@ pub type AllowRelayGroupsResponse<C> =
 pub type DefaultRelaysResponse<C> = Result<Vec<RelayId>, <C as ClientApi>::Error>;
 
 pub type GetChatsResponse<C> = Result<Arc<ApiChatsResponse>, <C as ClientApi>::Error>;
+pub type GetChatItemsResponse<C> = Result<Vec<ChatItem>, <C as ClientApi>::Error>;
+pub type GetChatItemResponse<C> = Result<ChatItem, <C as ClientApi>::Error>;
 
 pub trait ClientApiExt: ClientApi {
     fn users(&self) -> impl Future<Output = UsersResponse<Self>>;
@@ -180,6 +184,30 @@ pub trait ClientApiExt: ClientApi {
         self.batch_delete_messages(chat_id, std::iter::once(message_id.into()), mode)
     }
 
+    /// Forward messages preserving their content, files and the "forwarded" attribution
+    fn forward_messages<FCID, I, TCID>(
+        &self,
+        from_chat: FCID,
+        message_ids: I,
+        to_chat: TCID,
+    ) -> impl Future<Output = ForwardMessagesResponse<Self>>
+    where
+        FCID: Into<ChatId>,
+        I: IntoIterator<Item = MessageId>,
+        TCID: Into<ChatId>;
+
+    /// Forward the same messages to multiple chats. Returns one result per chat.
+    fn multicast_forward<FCID, I, T>(
+        &self,
+        from_chat: FCID,
+        message_ids: I,
+        to_chats: T,
+    ) -> impl Future<Output = Vec<ForwardMessagesResponse<Self>>>
+    where
+        FCID: Into<ChatId>,
+        I: IntoIterator<Item = MessageId>,
+        T: IntoIterator<Item = ChatId>;
+
     fn batch_message_reactions<
         CID: Into<ChatId>,
         MID: Into<MessageId>,
@@ -478,6 +506,34 @@ pub trait ClientApiExt: ClientApi {
         pagination: PaginationByTime,
         query: ChatListQuery,
     ) -> impl Future<Output = GetChatsResponse<Self>>;
+
+    /// Get a page of chat items ordered from the oldest to the newest one. When `search` is set
+    /// only items containing the text are returned.
+    fn get_chat_items<CID: Into<ChatId>>(
+        &self,
+        chat_id: CID,
+        pagination: ChatPagination,
+        search: Option<&str>,
+    ) -> impl Future<Output = GetChatItemsResponse<Self>>;
+
+    fn get_chat_item<CID: Into<ChatId>, MID: Into<MessageId>>(
+        &self,
+        chat_id: CID,
+        message_id: MID,
+    ) -> impl Future<Output = GetChatItemResponse<Self>>;
+
+    /// Lazily page through the chat history from the newest to the oldest items. See [`History`]
+    fn chat_history<CID: Into<ChatId>>(&self, chat_id: CID) -> History<'_, Self> {
+        History {
+            client: self,
+            chat_id: chat_id.into(),
+            before: None,
+            limit: None,
+            page_size: History::<Self>::DEFAULT_PAGE_SIZE,
+            search: None,
+            stream: None,
+        }
+    }
 }
 
 impl<C> ClientApiExt for C
@@ -601,6 +657,46 @@ where
         )
     }
 
+    fn forward_messages<FCID, I, TCID>(
+        &self,
+        from_chat: FCID,
+        message_ids: I,
+        to_chat: TCID,
+    ) -> impl Future<Output = ForwardMessagesResponse<Self>>
+    where
+        FCID: Into<ChatId>,
+        I: IntoIterator<Item = MessageId>,
+        TCID: Into<ChatId>,
+    {
+        self.send(util::ForwardChatItems {
+            to_chat_ref: to_chat.into().into_chat_ref(),
+            from_chat_ref: from_chat.into().into_chat_ref(),
+            item_ids: message_ids.into_iter().collect(),
+        })
+        .map(|response| response.map(Arc::new))
+    }
+
+    fn multicast_forward<FCID, I, T>(
+        &self,
+        from_chat: FCID,
+        message_ids: I,
+        to_chats: T,
+    ) -> impl Future<Output = Vec<ForwardMessagesResponse<Self>>>
+    where
+        FCID: Into<ChatId>,
+        I: IntoIterator<Item = MessageId>,
+        T: IntoIterator<Item = ChatId>,
+    {
+        let from_chat = from_chat.into();
+        let message_ids: Vec<MessageId> = message_ids.into_iter().collect();
+
+        futures::future::join_all(
+            to_chats
+                .into_iter()
+                .map(|to_chat| self.forward_messages(from_chat, message_ids.clone(), to_chat)),
+        )
+    }
+
     fn batch_message_reactions<
         CID: Into<ChatId>,
         MID: Into<MessageId>,
@@ -1074,6 +1170,38 @@ where
 
         Ok(ids)
     }
+
+    async fn get_chat_items<CID: Into<ChatId>>(
+        &self,
+        chat_id: CID,
+        pagination: ChatPagination,
+        search: Option<&str>,
+    ) -> GetChatItemsResponse<Self> {
+        let response: util::ApiChatResp = self
+            .send(util::GetChat {
+                chat_ref: chat_id.into().into_chat_ref(),
+                pagination,
+                search: search.map(str::to_owned),
+            })
+            .await?;
+
+        Ok(response.chat.chat_items)
+    }
+
+    async fn get_chat_item<CID: Into<ChatId>, MID: Into<MessageId>>(
+        &self,
+        chat_id: CID,
+        message_id: MID,
+    ) -> GetChatItemResponse<Self> {
+        let response: util::ChatItemInfoResp = self
+            .send(util::GetChatItemInfo {
+                chat_ref: chat_id.into().into_chat_ref(),
+                item_id: message_id.into(),
+            })
+            .await?;
+
+        Ok(response.chat_item.chat_item)
+    }
 }
 
 pub trait FilterChatItems {
@@ -1179,6 +1307,156 @@ impl<'a, C: 'a + ?Sized + ClientApi> IntoFuture for AcceptFileBuilder<'a, C> {
     }
 }
 
+/// Selects a page of chat items for [`ClientApiExt::get_chat_items`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatPagination {
+    /// The last `n` items of the chat
+    Last(usize),
+    /// `n` items preceding the specified item
+    Before(MessageId, usize),
+    /// `n` items following the specified item
+    After(MessageId, usize),
+    /// `n` items around the specified item, including the item itself
+    Around(MessageId, usize),
+}
+
+impl std::fmt::Display for ChatPagination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Last(count) => write!(f, "count={count}"),
+            Self::Before(id, count) => write!(f, "before={id} count={count}"),
+            Self::After(id, count) => write!(f, "after={id} count={count}"),
+            Self::Around(id, count) => write!(f, "around={id} count={count}"),
+        }
+    }
+}
+
+type HistoryStream<'a, E> = Pin<Box<dyn 'a + Send + Stream<Item = Result<ChatItem, E>>>>;
+
+/// A [`Stream`] of chat items going from the newest to the oldest item. The history is requested
+/// lazily page by page, so dropping the stream early doesn't load the rest of the chat.
+///
+/// ```ignore
+/// let last_50: Vec<ChatItem> = bot.history(chat_id).limit(50).try_collect().await?;
+/// ```
+///
+/// Changing the settings after the stream was polled has no effect.
+pub struct History<'a, C: 'a + ?Sized + ClientApi> {
+    client: &'a C,
+    chat_id: ChatId,
+    before: Option<MessageId>,
+    limit: Option<usize>,
+    page_size: usize,
+    search: Option<String>,
+    stream: Option<HistoryStream<'a, C::Error>>,
+}
+
+impl<'a, C: 'a + ?Sized + ClientApi> History<'a, C> {
+    pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+    /// Start from the item preceding the specified one
+    pub fn before<MID: Into<MessageId>>(mut self, message_id: MID) -> Self {
+        self.before = Some(message_id.into());
+        self
+    }
+
+    /// Stop after yielding `n` items
+    pub fn limit(mut self, n: usize) -> Self {
+        self.limit = Some(n);
+        self
+    }
+
+    /// The number of items requested at once. Default: [`Self::DEFAULT_PAGE_SIZE`]
+    pub fn page_size(mut self, n: usize) -> Self {
+        self.page_size = n.max(1);
+        self
+    }
+
+    /// Only yield items containing the text
+    pub fn search(mut self, text: impl Into<String>) -> Self {
+        self.search = Some(text.into());
+        self
+    }
+}
+
+impl<'a, C> History<'a, C>
+where
+    C: 'a + ?Sized + ClientApi,
+    C::Error: 'static + Send,
+{
+    fn pages(&self) -> HistoryStream<'a, C::Error> {
+        let client = self.client;
+        let chat_id = self.chat_id;
+        let page_size = self.page_size;
+        let search = self.search.clone();
+        let state = Some((self.before, self.limit.unwrap_or(usize::MAX)));
+
+        let pages = futures::stream::try_unfold(state, move |state| {
+            let search = search.clone();
+
+            async move {
+                let Some((before, remaining)) = state.filter(|(_, remaining)| *remaining > 0)
+                else {
+                    return Ok(None);
+                };
+
+                let count = page_size.min(remaining);
+                let pagination = match before {
+                    Some(id) => ChatPagination::Before(id, count),
+                    None => ChatPagination::Last(count),
+                };
+
+                let response: util::ApiChatResp = client
+                    .send(util::GetChat {
+                        chat_ref: chat_id.into_chat_ref(),
+                        pagination,
+                        search,
+                    })
+                    .await?;
+
+                // Pages are ordered from the oldest to the newest item
+                let mut items = response.chat.chat_items;
+                items.drain(..items.len().saturating_sub(count));
+
+                let next = match items.first() {
+                    Some(oldest) if items.len() == count => {
+                        Some((Some(MessageId::from(oldest)), remaining - count))
+                    }
+                    _ => None,
+                };
+
+                items.reverse();
+                Ok::<_, C::Error>(Some((items, next)))
+            }
+        });
+
+        Box::pin(
+            pages
+                .map_ok(|items| futures::stream::iter(items.into_iter().map(Ok)))
+                .try_flatten(),
+        )
+    }
+}
+
+impl<'a, C> Stream for History<'a, C>
+where
+    C: 'a + ?Sized + ClientApi,
+    C::Error: 'static + Send,
+{
+    type Item = Result<ChatItem, C::Error>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        if self.stream.is_none() {
+            self.stream = Some(self.pages());
+        }
+
+        self.stream.as_mut().unwrap().poll_next_unpin(cx)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Reaction {
     Set(String),
@@ -1206,6 +1484,94 @@ impl FileSourceExt for simploxide_api_types::events::RcvFileComplete {
     }
 }
 
+/// Detects messages mentioning the user in groups. Replies to the user messages are not counted as
+/// mentions.
+///
+/// ```ignore
+/// async fn on_new_msgs(ev: Arc<NewChatItems>, bot: Bot) -> ClientResult<StreamEvents> {
+///     for item in ev.chat_items.iter().filter(|item| item.mentions_user()) {
+///         // ...
+///     }
+/// }
+/// ```
+pub trait MentionsExt {
+    fn mentions_user(&self) -> bool;
+}
+
+impl MentionsExt for AChatItem {
+    fn mentions_user(&self) -> bool {
+        match &self.chat_info {
+            ChatInfo::Group { group_info, .. } => self
+                .chat_item
+                .mentions
+                .values()
+                .any(|mention| mention.member_id == group_info.membership.member_id),
+            _ => false,
+        }
+    }
+}
+
+impl MentionsExt for simploxide_api_types::events::NewChatItems {
+    /// Whether any of the new items mentions the user
+    fn mentions_user(&self) -> bool {
+        self.chat_items.iter().any(AChatItem::mentions_user)
+    }
+}
+
+/// Tallies the emoji reactions on a message
+pub trait ReactionsExt {
+    /// The number of reactions per emoji
+    fn reaction_tally(&self) -> BTreeMap<String, usize>;
+
+    /// The number of `emoji` reactions
+    fn reaction_count(&self, emoji: &str) -> usize;
+
+    /// Whether the user has set the `emoji` reaction
+    fn user_reacted(&self, emoji: &str) -> bool;
+}
+
+impl ReactionsExt for ChatItem {
+    fn reaction_tally(&self) -> BTreeMap<String, usize> {
+        self.reactions
+            .iter()
+            .filter_map(|count| match &count.reaction {
+                MsgReaction::Emoji { emoji, .. } => {
+                    Some((emoji.clone(), count.total_reacted.max(0) as usize))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn reaction_count(&self, emoji: &str) -> usize {
+        self.reactions
+            .iter()
+            .find(|count| matches!(&count.reaction, MsgReaction::Emoji { emoji: e, .. } if e == emoji))
+            .map_or(0, |count| count.total_reacted.max(0) as usize)
+    }
+
+    fn user_reacted(&self, emoji: &str) -> bool {
+        self.reactions.iter().any(|count| {
+            count.user_reacted
+                && matches!(&count.reaction, MsgReaction::Emoji { emoji: e, .. } if e == emoji)
+        })
+    }
+}
+
+impl ReactionsExt for AChatItem {
+    fn reaction_tally(&self) -> BTreeMap<String, usize> {
+        self.chat_item.reaction_tally()
+    }
+
+    fn reaction_count(&self, emoji: &str) -> usize {
+        self.chat_item.reaction_count(emoji)
+    }
+
+    fn user_reacted(&self, emoji: &str) -> bool {
+        self.chat_item.user_reacted(emoji)
+    }
+}
+
 /// Convenience accessor for group links
 pub trait GroupLinkExt {
     fn link(&self) -> String;
@@ -1228,3 +1594,190 @@ impl GroupLinkExt for simploxide_api_types::PreparedGroup {
             .unwrap_or_else(|| self.conn_link_to_connect.conn_full_link.clone())
     }
 }
+
+/// Extracts the chat an event belongs to
+pub trait EventExt {
+    /// Returns `None` for events not related to a single chat, e.g. [`Event::HostConnected`]. For
+    /// [`Event::NewChatItems`] and other multi-item events the chat of the first item is returned.
+    fn chat_id(&self) -> Option<ChatId>;
+}
+
+impl EventExt for Event {
+    fn chat_id(&self) -> Option<ChatId> {
+        fn direct(contact: &Contact) -> Option<ChatId> {
+            Some(ChatId::Direct(ContactId::from(contact)))
+        }
+
+        fn group(group_info: &GroupInfo) -> Option<ChatId> {
+            Some(ChatId::Group {
+                id: GroupId::from(group_info),
+                scope: None,
+            })
+        }
+
+        fn item(item: &AChatItem) -> Option<ChatId> {
+            ChatId::from_chat_info(&item.chat_info)
+        }
+
+        match self {
+            Self::ContactConnected(ev) => direct(&ev.contact),
+            Self::ContactUpdated(ev) => direct(&ev.to_contact),
+            Self::ContactDeletedByContact(ev) => direct(&ev.contact),
+            Self::ReceivedContactRequest(ev) => ev
+                .chat
+                .as_ref()
+                .and_then(|chat| ChatId::from_chat_info(&chat.chat_info)),
+            Self::NewMemberContactReceivedInv(ev) => direct(&ev.contact),
+            Self::ContactSndReady(ev) => direct(&ev.contact),
+            Self::NewChatItems(ev) => ev.chat_items.first().and_then(item),
+            Self::ChatItemReaction(ev) => ChatId::from_chat_info(&ev.reaction.chat_info),
+            Self::ChatItemsDeleted(ev) => ev
+                .chat_item_deletions
+                .first()
+                .and_then(|deletion| item(&deletion.deleted_chat_item)),
+            Self::ChatItemUpdated(ev) => item(&ev.chat_item),
+            Self::GroupChatItemsDeleted(ev) => group(&ev.group_info),
+            Self::ChatItemsStatusesUpdated(ev) => ev.chat_items.first().and_then(item),
+            Self::ReceivedGroupInvitation(ev) => group(&ev.group_info),
+            Self::UserJoinedGroup(ev) => group(&ev.group_info),
+            Self::GroupUpdated(ev) => group(&ev.to_group),
+            Self::JoinedGroupMember(ev) => group(&ev.group_info),
+            Self::MemberRole(ev) => group(&ev.group_info),
+            Self::DeletedMember(ev) => group(&ev.group_info),
+            Self::LeftMember(ev) => group(&ev.group_info),
+            Self::DeletedMemberUser(ev) => group(&ev.group_info),
+            Self::GroupDeleted(ev) => group(&ev.group_info),
+            Self::ConnectedToGroupMember(ev) => group(&ev.group_info),
+            Self::MemberAcceptedByOther(ev) => group(&ev.group_info),
+            Self::MemberBlockedForAll(ev) => group(&ev.group_info),
+            Self::GroupMemberUpdated(ev) => group(&ev.group_info),
+            Self::GroupLinkDataUpdated(ev) => group(&ev.group_info),
+            Self::GroupRelayUpdated(ev) => group(&ev.group_info),
+            Self::RcvFileDescrReady(ev) => item(&ev.chat_item),
+            Self::RcvFileComplete(ev) => item(&ev.chat_item),
+            Self::SndFileCompleteXftp(ev) => item(&ev.chat_item),
+            Self::RcvFileStart(ev) => item(&ev.chat_item),
+            Self::RcvFileSndCancelled(ev) => item(&ev.chat_item),
+            Self::RcvFileAccepted(ev) => item(&ev.chat_item),
+            Self::RcvFileError(ev) => ev.chat_item.as_ref().and_then(item),
+            Self::RcvFileWarning(ev) => ev.chat_item.as_ref().and_then(item),
+            Self::SndFileError(ev) => ev.chat_item.as_ref().and_then(item),
+            Self::SndFileWarning(ev) => ev.chat_item.as_ref().and_then(item),
+            Self::AcceptingContactRequest(ev) => direct(&ev.contact),
+            Self::AcceptingBusinessRequest(ev) => group(&ev.group_info),
+            Self::ContactConnecting(ev) => direct(&ev.contact),
+            Self::BusinessLinkConnecting(ev) => group(&ev.group_info),
+            Self::JoinedGroupMemberConnecting(ev) => group(&ev.group_info),
+            Self::SentGroupInvitation(ev) => group(&ev.group_info),
+            Self::GroupLinkConnecting(ev) => group(&ev.group_info),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::{mock, test_utils};
+
+    /// Serves a chat with items `1..=total` paging it like the SimpleX chat does. `extra` older
+    /// items are added to each page
+    fn serve_chat(total: i64, extra: i64) -> impl Fn(&str) -> JsonObject {
+        move |cmd| {
+            let arg = |name: &str| {
+                cmd.split_whitespace()
+                    .find_map(|token| token.strip_prefix(name))
+                    .map(|value| value.parse::<i64>().unwrap())
+            };
+
+            let end = arg("before=").map_or(total, |id| id - 1);
+            let start = (end - arg("count=").unwrap() - extra + 1).max(1);
+
+            serde_json::json!({
+                "type": "apiChat",
+                "chat": {
+                    "chatInfo": { "type": "unknown" },
+                    "chatItems": (start..=end)
+                        .map(|id| test_utils::chat_item(id, &format!("msg {id}"), false, serde_json::json!([])))
+                        .collect::<Vec<_>>(),
+                    "chatStats": { "unreadCount": 0, "unreadMentions": 0, "reportsCount": 0, "minUnreadItemId": 0 },
+                },
+            })
+        }
+    }
+
+    fn ids(items: &[ChatItem]) -> Vec<i64> {
+        items.iter().map(|item| item.meta.item_id).collect()
+    }
+
+    #[tokio::test]
+    async fn history_paging() {
+        let (client, _, _) = mock::init();
+        client
+            .expect_raw("/_get chat @1 ")
+            .repeatedly()
+            .respond_fn(serve_chat(7, 0));
+
+        let items: Vec<_> = client
+            .chat_history(ContactId::from_raw(1))
+            .page_size(3)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(ids(&items), [7, 6, 5, 4, 3, 2, 1]);
+
+        let items: Vec<_> = client
+            .chat_history(ContactId::from_raw(1))
+            .before(MessageId::from_raw(6))
+            .page_size(2)
+            .limit(3)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(ids(&items), [5, 4, 3]);
+
+        let sent: Vec<_> = client.sent().into_iter().map(|cmd| cmd.command).collect();
+        assert_eq!(
+            sent,
+            [
+                "/_get chat @1 count=3",
+                "/_get chat @1 before=5 count=3",
+                "/_get chat @1 before=2 count=3",
+                "/_get chat @1 before=6 count=2",
+                "/_get chat @1 before=4 count=1",
+            ]
+        );
+
+        // The surplus of oversized pages is dropped from the oldest end
+        client
+            .expect_raw("/_get chat @2 ")
+            .repeatedly()
+            .respond_fn(serve_chat(7, 2));
+
+        let items: Vec<_> = client
+            .chat_history(ContactId::from_raw(2))
+            .page_size(3)
+            .limit(5)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(ids(&items), [7, 6, 5, 4, 3]);
+    }
+
+    #[tokio::test]
+    async fn forward_commands() {
+        let (client, _, _) = mock::init();
+        client
+            .expect_raw("/_forward ")
+            .repeatedly()
+            .respond_json(serde_json::json!({ "type": "cmdOk" }));
+
+        let from = ContactId::from_raw(1);
+        let ids = [MessageId::from_raw(10), MessageId::from_raw(11)];
+        let to = [GroupId::from_raw(2).into(), ContactId::from_raw(3).into()];
+        let _ = client.multicast_forward(from, ids, to).await;
+
+        let sent: Vec<_> = client.sent().into_iter().map(|cmd| cmd.command).collect();
+        assert_eq!(sent, ["/_forward #2 @1 10,11", "/_forward @3 @1 10,11"]);
+    }
+}
@@ -0,0 +1,517 @@
+//! Message templates with localization.
+//!
+//! A [`Catalog`] keeps the message templates per locale. Templates are trusted SimpleX markdown
+//! with `{name}` placeholders, the arguments are escaped the same way as the [`RichText`] text, so
+//! user-provided values never break the template formatting. Use `{{` and `}}` for literal braces.
+//!
+//! Catalogs are loaded from JSON objects mapping keys to templates, either one file per locale or
+//! one object with all locales:
+//!
+//! ```json
+//! {
+//!     "en": { "greeting": "Hi *{name}*, your code is `{code}`" },
+//!     "de": { "greeting": "Hallo *{name}*, dein Code ist `{code}`" }
+//! }
+//! ```
+//!
+//! ```ignore
+//! // Loads locales/en.json, locales/de.json, ...
+//! let catalog = Catalog::load_dir("en", "locales").await?;
+//!
+//! // The locale is stored in the contact custom data
+//! bot.set_contact_locale(&contact, "de").await?;
+//!
+//! // In handlers
+//! bot.send_msg(
+//!     &contact,
+//!     catalog
+//!         .message_for(&contact, "greeting")
+//!         .arg("name", &contact.profile.display_name)
+//!         .arg("code", code),
+//! )
+//! .await?;
+//! ```
+//!
+//! Locales are matched exactly first, then by the language subtag(`de-AT` uses `de`) and then the
+//! fallback locale of the catalog is used. Messages missing in the selected locale are taken from
+//! the fallback one, and if the key is missing there too, the key itself is sent.
+
+use simploxide_api_types::{Contact, JsonObject};
+
+use std::{collections::HashMap, path::Path};
+
+use crate::messages::{Markdown, MessageLike, RichText, Span, TextKind};
+
+/// The key of the contact custom data storing the contact locale
+pub const LOCALE_KEY: &str = "locale";
+
+/// Placeholders are replaced with characters from the supplementary private use area while parsing
+/// the template markdown
+const PLACEHOLDER_BASE: u32 = 0xF0000;
+const MAX_PLACEHOLDERS: usize = 0xFFFE;
+
+/// The locale stored in the contact custom data, see [`Bot::set_contact_locale`](crate::bot::Bot::set_contact_locale)
+pub fn contact_locale(contact: &Contact) -> Option<&str> {
+    contact.custom_data.as_ref()?.get(LOCALE_KEY)?.as_str()
+}
+
+/// Returns the custom data with the locale set, other keys are kept
+pub fn with_locale(custom_data: Option<JsonObject>, locale: &str) -> JsonObject {
+    let mut data = match custom_data {
+        Some(JsonObject::Object(data)) => data,
+        _ => Default::default(),
+    };
+
+    data.insert(LOCALE_KEY.to_owned(), locale.into());
+    JsonObject::Object(data)
+}
+
+/// Message templates of all locales
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    fallback: String,
+    locales: HashMap<String, HashMap<String, Template>>,
+}
+
+impl Catalog {
+    /// An empty catalog. The `fallback` locale is used when the requested one is unknown
+    pub fn new(fallback: impl Into<String>) -> Self {
+        Self {
+            fallback: fallback.into(),
+            locales: HashMap::new(),
+        }
+    }
+
+    /// Parse the `{ "locale": { "key": "template" } }` JSON object
+    pub fn from_json(fallback: impl Into<String>, json: &str) -> Result<Self, CatalogError> {
+        let locales: HashMap<String, HashMap<String, String>> = serde_json::from_str(json)?;
+        let mut catalog = Self::new(fallback);
+
+        for (locale, messages) in locales {
+            catalog.add_messages(&locale, messages)?;
+        }
+
+        Ok(catalog)
+    }
+
+    /// Load the `<locale>.json` files from the directory, each file must contain the
+    /// `{ "key": "template" }` JSON object
+    pub async fn load_dir(
+        fallback: impl Into<String>,
+        dir: impl AsRef<Path>,
+    ) -> Result<Self, CatalogError> {
+        let mut catalog = Self::new(fallback);
+        let mut entries = tokio::fs::read_dir(dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+
+            let Some(locale) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+
+            let json = tokio::fs::read_to_string(&path).await?;
+            catalog.load_json(locale, &json)?;
+        }
+
+        Ok(catalog)
+    }
+
+    /// Parse the `{ "key": "template" }` JSON object and add its messages to the locale
+    pub fn load_json(&mut self, locale: &str, json: &str) -> Result<(), CatalogError> {
+        let messages: HashMap<String, String> = serde_json::from_str(json)?;
+        self.add_messages(locale, messages)
+    }
+
+    /// Add or replace the messages of the locale
+    pub fn add_messages<K, V>(
+        &mut self,
+        locale: &str,
+        messages: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<(), CatalogError>
+    where
+        K: Into<String>,
+        V: AsRef<str>,
+    {
+        let mut templates = HashMap::new();
+
+        for (key, template) in messages {
+            let key = key.into();
+
+            match Template::parse(template.as_ref()) {
+                Ok(template) => {
+                    templates.insert(key, template);
+                }
+                Err(reason) => {
+                    return Err(CatalogError::Template {
+                        locale: locale.to_owned(),
+                        key,
+                        reason,
+                    });
+                }
+            }
+        }
+
+        self.locales
+            .entry(locale.to_owned())
+            .or_default()
+            .extend(templates);
+
+        Ok(())
+    }
+
+    pub fn fallback(&self) -> &str {
+        &self.fallback
+    }
+
+    pub fn locales(&self) -> impl Iterator<Item = &str> {
+        self.locales.keys().map(String::as_str)
+    }
+
+    /// The best matching locale of the catalog
+    pub fn negotiate<'a>(&'a self, locale: &str) -> &'a str {
+        if let Some((locale, _)) = self.locales.get_key_value(locale) {
+            return locale;
+        }
+
+        let language = locale.split(['-', '_']).next().unwrap_or_default();
+
+        self.locales
+            .keys()
+            .find(|known| known.split(['-', '_']).next() == Some(language))
+            .map(String::as_str)
+            .unwrap_or(&self.fallback)
+    }
+
+    /// The message in the locale best matching the requested one
+    pub fn message<'a>(&'a self, locale: &str, key: &'a str) -> Localized<'a> {
+        let locale = self.negotiate(locale);
+
+        let template = self
+            .locales
+            .get(locale)
+            .and_then(|messages| messages.get(key))
+            .or_else(|| self.locales.get(&self.fallback)?.get(key));
+
+        if template.is_none() {
+            log::warn!("Message {key:?} is missing in the {locale:?} locale");
+        }
+
+        Localized {
+            key,
+            template,
+            args: HashMap::new(),
+        }
+    }
+
+    /// The message in the locale of the contact, see [`contact_locale`]
+    pub fn message_for<'a>(&'a self, contact: &Contact, key: &'a str) -> Localized<'a> {
+        let locale = contact_locale(contact).unwrap_or(&self.fallback);
+        self.message(locale, key)
+    }
+}
+
+/// A localized message with arguments. Can be passed to the send message methods directly or
+/// converted into [`RichText`] to be combined with other text
+#[derive(Debug, Clone)]
+pub struct Localized<'a> {
+    key: &'a str,
+    template: Option<&'a Template>,
+    args: HashMap<String, RichText>,
+}
+
+impl Localized<'_> {
+    /// Set the `{name}` placeholder to the escaped text
+    pub fn arg(self, name: impl Into<String>, value: impl std::fmt::Display) -> Self {
+        self.arg_rich(name, value.to_string())
+    }
+
+    /// Set the `{name}` placeholder to the formatted text, e.g. a mention. Placeholders inside
+    /// inline code and links get the plain text of the value
+    pub fn arg_rich(mut self, name: impl Into<String>, value: impl Into<RichText>) -> Self {
+        self.args.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn to_rich_text(&self) -> RichText {
+        match self.template {
+            Some(template) => template.render(&self.args),
+            None => RichText::from(self.key),
+        }
+    }
+}
+
+impl From<Localized<'_>> for RichText {
+    fn from(localized: Localized<'_>) -> Self {
+        localized.to_rich_text()
+    }
+}
+
+impl std::fmt::Display for Localized<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_rich_text().fmt(f)
+    }
+}
+
+impl MessageLike for Localized<'_> {
+    type Kind = TextKind;
+
+    fn into_builder_parts(self) -> (simploxide_api_types::ComposedMessage, Self::Kind) {
+        self.to_rich_text().into_builder_parts()
+    }
+}
+
+/// The parsed template. Placeholders are kept as single private use characters inside the spans
+#[derive(Debug, Clone)]
+struct Template {
+    spans: Vec<Span>,
+    names: Vec<String>,
+}
+
+impl Template {
+    fn parse(template: &str) -> Result<Self, String> {
+        let mut markup = String::with_capacity(template.len());
+        let mut names: Vec<String> = Vec::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.next_if_eq(&'{').is_some() => markup.push('{'),
+                '}' if chars.next_if_eq(&'}').is_some() => markup.push('}'),
+                '{' => {
+                    let mut name = String::new();
+
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) if c.is_alphanumeric() || c == '_' || c == '-' => name.push(c),
+                            Some(c) => return Err(format!("unexpected {c:?} in placeholder")),
+                            None => return Err("unclosed placeholder".to_owned()),
+                        }
+                    }
+
+                    if name.is_empty() {
+                        return Err("empty placeholder".to_owned());
+                    }
+
+                    let index = match names.iter().position(|known| *known == name) {
+                        Some(index) => index,
+                        None if names.len() < MAX_PLACEHOLDERS => {
+                            names.push(name);
+                            names.len() - 1
+                        }
+                        None => return Err("too many placeholders".to_owned()),
+                    };
+
+                    markup.push(placeholder(index));
+                }
+                '}' => return Err("unmatched '}'".to_owned()),
+                c => markup.push(c),
+            }
+        }
+
+        Ok(Self {
+            spans: Markdown::parse(&markup).into_spans(),
+            names,
+        })
+    }
+
+    fn render(&self, args: &HashMap<String, RichText>) -> RichText {
+        self.render_spans(&self.spans, args)
+    }
+
+    fn render_spans(&self, spans: &[Span], args: &HashMap<String, RichText>) -> RichText {
+        let mut text = RichText::new();
+
+        for span in spans {
+            text = match span {
+                Span::Plain(plain) => {
+                    let mut literal = String::new();
+
+                    for c in plain.chars() {
+                        match self.placeholder_name(c) {
+                            Some(name) => {
+                                if !literal.is_empty() {
+                                    text = text.push(std::mem::take(&mut literal));
+                                }
+
+                                text = match args.get(name) {
+                                    Some(arg) => text.push(arg.clone()),
+                                    None => text.push(format!("{{{name}}}")),
+                                };
+                            }
+                            None => literal.push(c),
+                        }
+                    }
+
+                    if literal.is_empty() {
+                        text
+                    } else {
+                        text.push(literal)
+                    }
+                }
+                Span::Bold(inner) => text.bold(self.render_spans(inner, args)),
+                Span::Italic(inner) => text.italic(self.render_spans(inner, args)),
+                Span::Strike(inner) => text.strike(self.render_spans(inner, args)),
+                Span::Secret(inner) => text.secret(self.render_spans(inner, args)),
+                Span::Colored(color, inner) => text.colored(*color, self.render_spans(inner, args)),
+                Span::Monospace(code) => text.monospace(self.substitute_plain(code, args)),
+                Span::CodeBlock { lang, code } => text.code_block(
+                    lang.as_deref().unwrap_or_default(),
+                    self.substitute_plain(code, args),
+                ),
+                Span::Link(url) => text.link(self.substitute_plain(url, args)),
+                Span::Mention(name) => text.push(Markdown::from(vec![Span::Mention(
+                    self.substitute_plain(name, args),
+                )])),
+            };
+        }
+
+        text
+    }
+
+    /// Replace the placeholders with the plain text of the arguments
+    fn substitute_plain(&self, text: &str, args: &HashMap<String, RichText>) -> String {
+        let mut out = String::with_capacity(text.len());
+
+        for c in text.chars() {
+            match self.placeholder_name(c) {
+                Some(name) => match args.get(name) {
+                    Some(arg) => out.push_str(&arg.to_plain_text()),
+                    None => {
+                        out.push('{');
+                        out.push_str(name);
+                        out.push('}');
+                    }
+                },
+                None => out.push(c),
+            }
+        }
+
+        out
+    }
+
+    fn placeholder_name(&self, c: char) -> Option<&str> {
+        let index = (c as u32).checked_sub(PLACEHOLDER_BASE)?;
+        self.names.get(index as usize).map(String::as_str)
+    }
+}
+
+fn placeholder(index: usize) -> char {
+    char::from_u32(PLACEHOLDER_BASE + index as u32).unwrap()
+}
+
+#[derive(Debug)]
+pub enum CatalogError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Template {
+        locale: String,
+        key: String,
+        reason: String,
+    },
+}
+
+impl From<std::io::Error> for CatalogError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for CatalogError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+impl std::fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "Cannot read the catalog: {error}"),
+            Self::Json(error) => write!(f, "Cannot parse the catalog: {error}"),
+            Self::Template {
+                locale,
+                key,
+                reason,
+            } => write!(f, "Bad template {key:?} in the {locale:?} locale: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for CatalogError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Json(error) => Some(error),
+            Self::Template { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{id::MemberId, messages::Mention};
+
+    #[test]
+    fn localized_messages() {
+        let catalog = Catalog::from_json(
+            "en",
+            r#"{
+                "en": {
+                    "greeting": "Hi *{name}*, your code is `{code}`",
+                    "bye": "Bye {name}! {{ok}}"
+                },
+                "de": { "greeting": "Hallo *{name}*, dein Code ist `{code}`" }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(catalog.negotiate("de-AT"), "de");
+        assert_eq!(catalog.negotiate("fr"), "en");
+
+        let greeting = catalog
+            .message("de", "greeting")
+            .arg("name", "*Eve*")
+            .arg("code", 42);
+        // The markers of the argument are shown as is, the word starting with one can't be bold
+        assert_eq!(
+            greeting.to_string(),
+            "Hallo \u{2060}*Eve\u{2060}*, dein Code ist `42`"
+        );
+        assert_eq!(
+            greeting
+                .to_rich_text()
+                .to_plain_text()
+                .replace('\u{2060}', ""),
+            "Hallo *Eve*, dein Code ist 42"
+        );
+
+        // Falls back to the default locale, then to the key
+        let bye = catalog
+            .message("de", "bye")
+            .arg_rich("name", Mention::new("Bob", MemberId::from_raw(2)));
+        assert_eq!(bye.to_string(), "Bye @Bob! {ok}");
+
+        let (msg, _) = bye.into_builder_parts();
+        assert_eq!(msg.mentions["Bob"], 2);
+
+        assert_eq!(catalog.message("en", "missing").to_string(), "missing");
+        assert_eq!(
+            catalog.message("en", "greeting").to_string(),
+            "Hi *{name}*, your code is `{code}`"
+        );
+
+        assert!(matches!(
+            Catalog::from_json("en", r#"{ "en": { "bad": "Hi {name" } }"#),
+            Err(CatalogError::Template { .. })
+        ));
+
+        let data = with_locale(Some(serde_json::json!({ "plan": "pro" })), "de");
+        assert_eq!(data, serde_json::json!({ "plan": "pro", "locale": "de" }));
+    }
+}
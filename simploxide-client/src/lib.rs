@@ -105,7 +105,8 @@
 //! - **`multimedia`**: Image transcoding via the `image` crate. Enables
 //!   [`preview::transcoder::Transcoder`] and automatic thumbnail generation for [`messages::Image`].
 //!   [`preview::ImagePreview`] automatically tries to transcode its sources to JPEGs with this
-//!   feature on
+//!   feature on. Also enables the [`media`] module reading audio and video durations, cover
+//!   art and VP8 keyframes for [`messages::Voice::from_file`] and [`messages::Video::from_file`]
 //!
 //! - **`xftp`**: Enables [`xftp::XftpClient`], which streamlines file downloads via
 //!   `download_file` method.
@@ -119,6 +120,12 @@
 //!
 //! - **`farm`**: Enables bot farms that manage multiple bots on the same SimpleX instance.
 //!
+//! - **`mock`**: Enables [`mock::MockClient`], an in-memory [`ClientApi`] answering commands
+//!   with programmable expectations. Allows testing bots offline without a SimpleX-Chat instance.
+//!
+//! - **`record`**: Enables the [`record`] module that records WebSocket sessions into JSONL files
+//!   and replays them as a [`ClientApi`] + [`EventStream`] pair. Implies `websocket`.
+//!
 //! - **`fullcli`**: Convenience bundle: `cli` + `native_crypto` + `multimedia` + `xftp` +
 //!   `cancellation` + `farm`.
 //!
@@ -134,23 +141,39 @@
 pub mod crypto;
 #[cfg(feature = "ffi")]
 pub mod ffi;
+#[cfg(feature = "multimedia")]
+pub mod media;
+#[cfg(feature = "mock")]
+pub mod mock;
+#[cfg(feature = "record")]
+pub mod record;
 #[cfg(feature = "websocket")]
 pub mod ws;
 #[cfg(feature = "xftp")]
 pub mod xftp;
 
 pub mod bot;
+pub mod dialogue;
+pub mod directory;
 pub mod dispatcher;
 pub mod est_size;
 pub mod ext;
+pub mod i18n;
 pub mod id;
 pub mod messages;
+pub mod poll;
+pub mod predicate;
 pub mod prelude;
 pub mod preview;
+pub mod reactions;
 pub mod remote;
+pub mod scheduler;
 
 mod util;
 
+#[cfg(test)]
+mod test_utils;
+
 pub use simploxide_api_types::{
     self as types,
     client_api::{self, BadResponseError, ClientApi, ClientApiError},
@@ -173,7 +196,7 @@ use std::{
     task::{Context, Poll},
 };
 
-use crate::id::UserId;
+use crate::{id::UserId, predicate::EventPredicate};
 
 /// The high level event stream that embeds event filtering.
 ///
@@ -193,6 +216,7 @@ pub struct EventStream<P> {
     kind_filter: [bool; EventKind::COUNT],
     receiver: tokio::sync::mpsc::UnboundedReceiver<P>,
     hooks: Vec<Arc<dyn Hook>>,
+    predicate: Option<Arc<dyn EventPredicate>>,
 }
 
 impl<P> FromIterator<P> for EventStream<P> {
@@ -214,6 +238,7 @@ impl<P> From<tokio::sync::mpsc::UnboundedReceiver<P>> for EventStream<P> {
             kind_filter: [true; EventKind::COUNT],
             receiver,
             hooks: Vec::new(),
+            predicate: None,
         }
     }
 }
@@ -252,6 +277,26 @@ impl<P> EventStream<P> {
         (handle, self)
     }
 
+    /// Setting this hook routes the replies to the pending [`Bot::ask`](bot::Bot::ask) calls
+    ///
+    /// See [`dialogue::Replies`]
+    pub fn hook_replies(mut self) -> (Arc<dialogue::Replies>, Self) {
+        let replies = Arc::new(dialogue::Replies::default());
+        self.add_hook(Arc::new(dialogue::RepliesHook::new(replies.clone())));
+
+        (replies, self)
+    }
+
+    /// Setting this hook keeps the contact and group cache up to date
+    ///
+    /// See [`directory::Directory`]
+    pub fn hook_directory(mut self) -> (Arc<directory::Directory>, Self) {
+        let directory = Arc::new(directory::Directory::default());
+        self.add_hook(directory.clone());
+
+        (directory, self)
+    }
+
     /// Set stream owner. Events with different UserIds will be filtered out
     pub fn set_owner(&mut self, id: id::UserId) -> &mut Self {
         self.user_filter = Some(UserFilter::Include(id));
@@ -273,6 +318,22 @@ impl<P> EventStream<P> {
         self
     }
 
+    /// Drop events not matching the predicate. Unlike [`Self::set_filter`] predicates are checked
+    /// after parsing, [`Hook`]s receive all events regardless of the predicate.
+    ///
+    /// Only a single predicate can be set, combine predicates with [`EventPredicate::and`] and
+    /// [`EventPredicate::or`]. See [`predicate`] for available predicates.
+    pub fn set_predicate<F: 'static + EventPredicate>(&mut self, predicate: F) -> &mut Self {
+        self.predicate = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Remove the predicate set by [`Self::set_predicate`]
+    pub fn unset_predicate(&mut self) -> &mut Self {
+        self.predicate = None;
+        self
+    }
+
     pub fn set_filter<I: IntoIterator<Item = EventKind>>(&mut self, f: Filter<I>) -> &mut Self {
         match f {
             Filter::Accept(kinds) => {
@@ -467,14 +528,31 @@ impl<P: EventParser> Stream for EventStream<P> {
                     }
 
                     match raw_event.parse_event() {
-                        Ok(event) => {
+                        Ok(mut event) => {
+                            let mut captured = false;
+                            let mut replacement = None;
+
                             for hook in self.hooks.iter_mut() {
                                 if hook.should_intercept(kind) {
-                                    hook.intercept_event(event.clone());
+                                    match hook.capture_event(event.clone()) {
+                                        Capture::Pass => (),
+                                        Capture::Take => captured = true,
+                                        Capture::Replace(ev) => replacement = Some(ev),
+                                    }
                                 }
                             }
 
-                            if self.kind_filter[kind.as_usize()] {
+                            if let Some(replacement) = replacement {
+                                event = replacement;
+                            }
+
+                            if !captured
+                                && self.kind_filter[kind.as_usize()]
+                                && self
+                                    .predicate
+                                    .as_ref()
+                                    .is_none_or(|predicate| predicate.matches(&event))
+                            {
                                 break Poll::Ready(Some(Ok(event)));
                             }
                         }
@@ -530,6 +608,23 @@ pub trait Hook: 'static + Send + Sync {
     /// Hooks must not block the event stream; this method should be a cheap synchronous call.
     /// Delegate heavy work to another thread or spawn async tasks internally.
     fn intercept_event(&self, event: Event);
+
+    /// Like [`Self::intercept_event`] but allows the hook to take the event or its part out of the
+    /// stream, see [`Capture`]. Other hooks still receive the original event.
+    fn capture_event(&self, event: Event) -> Capture {
+        self.intercept_event(event);
+        Capture::Pass
+    }
+}
+
+/// Returned from [`Hook::capture_event`]
+pub enum Capture {
+    /// The event continues down the stream
+    Pass,
+    /// The event is taken out of the stream
+    Take,
+    /// The event is replaced with a different one, e.g. with some items removed
+    Replace(Event),
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
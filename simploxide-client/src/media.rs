@@ -0,0 +1,997 @@
+//! Audio and video metadata.
+//!
+//! Reads the durations from the container headers without decoding the streams. Supported
+//! containers are OGG(Opus and Vorbis), MP4/M4A, WebM/Matroska and WAV.
+//!
+//! ```ignore
+//! let duration = media::duration("reply.ogg").await?;
+//! bot.send_msg(chat, Voice::new("reply.ogg", duration)).await?;
+//!
+//! // Same as above
+//! bot.send_msg(chat, Voice::from_file("reply.ogg").await?).await?;
+//!
+//! // Duration and the thumbnail
+//! bot.send_msg(chat, Video::from_file("clip.webm").await?).await?;
+//! ```
+//!
+//! [`probe`] reads two kinds of thumbnails:
+//!
+//! - The cover art embedded into the container: the `covr` tag of MP4 files or the image
+//!   attachment of WebM/Matroska files(the one named `cover.*` if there are several).
+//! - The first keyframe of WebM/Matroska videos encoded with VP8. A VP8 keyframe is a complete
+//!   lossy WebP image, so it's extracted without decoding and rewrapped into the WebP container.
+//!
+//! Frames of the other codecs(H.264, HEVC, VP9, AV1) can't be decoded without the native codec
+//! libraries. Such videos get only the cover art if they have one, otherwise they are sent with
+//! the default placeholder preview unless a preview is set explicitly, e.g. a frame extracted by
+//! an external `ffmpeg`.
+
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    time::Duration,
+};
+
+/// How far from the end to look for the last OGG page
+const OGG_TAIL_SIZE: u64 = 64 * 1024;
+
+const OGG_PAGE_HEADER_SIZE: usize = 27;
+
+/// Cover images larger than this are ignored
+const MAX_COVER_SIZE: u64 = crate::preview::MAX_FILE_SIZE as u64;
+
+/// Attachment names, MIME types and codec IDs larger than this are ignored
+const MAX_STRING_SIZE: u64 = 4 * 1024;
+
+/// The media metadata, see [`probe`]
+#[derive(Debug, Clone, Default)]
+pub struct MediaInfo {
+    pub duration: Duration,
+    /// The embedded cover image(usually JPEG or PNG), not a video frame
+    pub cover: Option<Vec<u8>>,
+    /// The first keyframe of the VP8 video track as a WebP image, WebM/Matroska only
+    pub keyframe: Option<Vec<u8>>,
+}
+
+/// Detect the container by the file header and read the media duration from it
+pub async fn duration(path: impl AsRef<Path>) -> Result<Duration, MediaError> {
+    let path = path.as_ref().to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(path)?;
+        read_duration(std::io::BufReader::new(file))
+    })
+    .await?
+}
+
+/// Sync version of [`duration`] reading from any seekable source
+pub fn read_duration<R: Read + Seek>(reader: R) -> Result<Duration, MediaError> {
+    read(reader, false).map(|info| info.duration)
+}
+
+/// Like [`duration`] but also extracts the embedded cover image and the VP8 keyframe
+pub async fn probe(path: impl AsRef<Path>) -> Result<MediaInfo, MediaError> {
+    let path = path.as_ref().to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(path)?;
+        read_info(std::io::BufReader::new(file))
+    })
+    .await?
+}
+
+/// Sync version of [`probe`] reading from any seekable source
+pub fn read_info<R: Read + Seek>(reader: R) -> Result<MediaInfo, MediaError> {
+    read(reader, true)
+}
+
+fn read<R: Read + Seek>(mut reader: R, with_preview: bool) -> Result<MediaInfo, MediaError> {
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    let audio = |duration| MediaInfo {
+        duration,
+        cover: None,
+        keyframe: None,
+    };
+
+    match header {
+        [b'O', b'g', b'g', b'S', ..] => ogg_duration(reader).map(audio),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E'] => {
+            wav_duration(reader).map(audio)
+        }
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => mp4_info(reader, with_preview),
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => matroska_info(reader, with_preview),
+        _ => Err(MediaError::Unsupported),
+    }
+}
+
+fn mp4_info<R: Read + Seek>(mut reader: R, with_preview: bool) -> Result<MediaInfo, MediaError> {
+    let moov =
+        find_box(&mut reader, *b"moov", None)?.ok_or(MediaError::Malformed("no moov box"))?;
+    let moov_start = reader.stream_position()?;
+
+    let duration = mp4_duration(&mut reader, moov)?;
+
+    let cover = if with_preview {
+        reader.seek(SeekFrom::Start(moov_start))?;
+        mp4_cover(&mut reader, moov)?
+    } else {
+        None
+    };
+
+    Ok(MediaInfo {
+        duration,
+        cover,
+        keyframe: None,
+    })
+}
+
+/// Reads the `mvhd` box of the `moov` box
+fn mp4_duration<R: Read + Seek>(reader: &mut R, moov: u64) -> Result<Duration, MediaError> {
+    let mvhd =
+        find_box(reader, *b"mvhd", Some(moov))?.ok_or(MediaError::Malformed("no mvhd box"))?;
+
+    if mvhd < 20 {
+        return Err(MediaError::Malformed("truncated mvhd box"));
+    }
+
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version)?;
+
+    let (timescale, duration) = if version[0] == 1 {
+        // Skip creation and modification times
+        reader.seek(SeekFrom::Current(16))?;
+        (read_u32_be(reader)?, read_u64_be(reader)?)
+    } else {
+        reader.seek(SeekFrom::Current(8))?;
+        (read_u32_be(reader)?, u64::from(read_u32_be(reader)?))
+    };
+
+    if timescale == 0 {
+        return Err(MediaError::Malformed("zero mvhd timescale"));
+    }
+
+    Ok(ticks_to_duration(duration, u64::from(timescale)))
+}
+
+/// Reads the `moov/udta/meta/ilst/covr/data` box
+fn mp4_cover<R: Read + Seek>(reader: &mut R, moov: u64) -> Result<Option<Vec<u8>>, MediaError> {
+    let Some(udta) = find_box(reader, *b"udta", Some(moov))? else {
+        return Ok(None);
+    };
+
+    let Some(mut meta) = find_box(reader, *b"meta", Some(udta))? else {
+        return Ok(None);
+    };
+
+    // iTunes `meta` is a full box with version and flags, QuickTime one starts with the children
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version)?;
+
+    if version == [0; 4] {
+        meta = meta.saturating_sub(4);
+    } else {
+        reader.seek(SeekFrom::Current(-4))?;
+    }
+
+    let Some(ilst) = find_box(reader, *b"ilst", Some(meta))? else {
+        return Ok(None);
+    };
+
+    let Some(covr) = find_box(reader, *b"covr", Some(ilst))? else {
+        return Ok(None);
+    };
+
+    let Some(data) = find_box(reader, *b"data", Some(covr))? else {
+        return Ok(None);
+    };
+
+    // Skip the type indicator and the locale
+    if data <= 8 || data - 8 > MAX_COVER_SIZE {
+        return Ok(None);
+    }
+
+    reader.seek(SeekFrom::Current(8))?;
+    read_bytes(reader, data - 8).map(Some)
+}
+
+/// Finds the box of the `kind` among the boxes starting at the current position and ending after
+/// `within` bytes(or at the end of the file). Returns the box content size and leaves the reader at
+/// the start of the content.
+fn find_box<R: Read + Seek>(
+    reader: &mut R,
+    kind: [u8; 4],
+    within: Option<u64>,
+) -> Result<Option<u64>, MediaError> {
+    let mut remaining = within.unwrap_or(u64::MAX);
+
+    while remaining >= 8 {
+        let mut header = [0u8; 8];
+        match reader.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut header_size = 8;
+        let mut size = u64::from(u32::from_be_bytes(header[..4].try_into().unwrap()));
+
+        if size == 1 {
+            size = read_u64_be(reader)?;
+            header_size += 8;
+        } else if size == 0 {
+            // The box extends to the end of the file
+            let pos = reader.stream_position()?;
+            let end = reader.seek(SeekFrom::End(0))?;
+            reader.seek(SeekFrom::Start(pos))?;
+            size = end - pos + header_size;
+        }
+
+        if size < header_size || size > remaining {
+            return Err(MediaError::Malformed("bad box size"));
+        }
+
+        if header[4..] == kind {
+            return Ok(Some(size - header_size));
+        }
+
+        reader.seek(SeekFrom::Current((size - header_size) as i64))?;
+        remaining -= size;
+    }
+
+    Ok(None)
+}
+
+/// The sample rate comes from the codec header in the first page, the sample count from the
+/// granule position of the last page
+fn ogg_duration<R: Read + Seek>(mut reader: R) -> Result<Duration, MediaError> {
+    let mut head = vec![0u8; 4096];
+    let len = read_up_to(&mut reader, &mut head)?;
+    head.truncate(len);
+
+    let segments = *head
+        .get(OGG_PAGE_HEADER_SIZE - 1)
+        .ok_or(MediaError::Malformed("truncated OGG page"))? as usize;
+    let packet = head
+        .get(OGG_PAGE_HEADER_SIZE + segments..)
+        .ok_or(MediaError::Malformed("truncated OGG page"))?;
+
+    let (sample_rate, pre_skip) = if let Some(opus) = packet.strip_prefix(b"OpusHead") {
+        // Opus granule positions are always in 48kHz samples
+        let pre_skip = opus
+            .get(2..4)
+            .ok_or(MediaError::Malformed("truncated OpusHead"))?;
+        (
+            48000,
+            u64::from(u16::from_le_bytes([pre_skip[0], pre_skip[1]])),
+        )
+    } else if let Some(vorbis) = packet.strip_prefix(b"\x01vorbis") {
+        let rate = vorbis
+            .get(5..9)
+            .ok_or(MediaError::Malformed("truncated Vorbis header"))?;
+        (u64::from(u32::from_le_bytes(rate.try_into().unwrap())), 0)
+    } else {
+        return Err(MediaError::Unsupported);
+    };
+
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(end.saturating_sub(OGG_TAIL_SIZE)))?;
+
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail)?;
+
+    let granule = tail
+        .windows(4)
+        .enumerate()
+        .rev()
+        .filter(|(_, window)| *window == b"OggS")
+        .filter_map(|(pos, _)| tail.get(pos + 6..pos + 14))
+        .map(|granule| u64::from_le_bytes(granule.try_into().unwrap()))
+        // -1 marks pages without finished packets
+        .find(|granule| *granule != u64::MAX)
+        .ok_or(MediaError::Malformed("no OGG granule position"))?;
+
+    if sample_rate == 0 {
+        return Err(MediaError::Malformed("zero sample rate"));
+    }
+
+    Ok(ticks_to_duration(
+        granule.saturating_sub(pre_skip),
+        sample_rate,
+    ))
+}
+
+/// The `data` chunk size divided by the byte rate of the `fmt ` chunk
+fn wav_duration<R: Read + Seek>(mut reader: R) -> Result<Duration, MediaError> {
+    reader.seek(SeekFrom::Start(12))?;
+    let mut byte_rate = None;
+
+    loop {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+
+        let size = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+        match &header[..4] {
+            b"fmt " => {
+                let mut fmt = [0u8; 12];
+                reader.read_exact(&mut fmt)?;
+                byte_rate = Some(u32::from_le_bytes(fmt[8..].try_into().unwrap()));
+                reader.seek(SeekFrom::Current(
+                    i64::from(size) - 12 + i64::from(size % 2),
+                ))?;
+            }
+            b"data" => {
+                let byte_rate = byte_rate
+                    .filter(|rate| *rate > 0)
+                    .ok_or(MediaError::Malformed("no fmt chunk before data"))?;
+
+                return Ok(ticks_to_duration(u64::from(size), u64::from(byte_rate)));
+            }
+            // Chunks are padded to the even size
+            _ => {
+                reader.seek(SeekFrom::Current(i64::from(size) + i64::from(size % 2)))?;
+            }
+        }
+    }
+}
+
+const SEGMENT: u32 = 0x18538067;
+const CLUSTER: u32 = 0x1F43B675;
+const INFO: u32 = 0x1549A966;
+const TIMECODE_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const ATTACHMENTS: u32 = 0x1941A469;
+const ATTACHED_FILE: u32 = 0x61A7;
+const FILE_NAME: u32 = 0x466E;
+const FILE_MIME_TYPE: u32 = 0x4660;
+const FILE_DATA: u32 = 0x465C;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+/// Reads the `Info`, `Tracks` and `Attachments` elements of the segment and the keyframe of the
+/// first cluster
+fn matroska_info<R: Read + Seek>(
+    mut reader: R,
+    with_preview: bool,
+) -> Result<MediaInfo, MediaError> {
+    let mut timecode_scale = 1_000_000;
+    let mut duration = None;
+    let mut cover: Option<(bool, Vec<u8>)> = None;
+    let mut vp8_track = None;
+    let mut keyframe = None;
+
+    while let Some((id, size)) = read_element(&mut reader)? {
+        // Tracks precede the clusters and a cluster starts with a keyframe, only the first one is
+        // checked
+        if id == CLUSTER
+            && let Some(track) = vp8_track.take()
+        {
+            keyframe = read_keyframe(&mut reader, size, track)?;
+
+            match size {
+                Some(_) => continue,
+                None => break,
+            }
+        }
+
+        match (id, size) {
+            // Look into the segment children
+            (SEGMENT, _) => continue,
+            (INFO, Some(size)) => {
+                let end = reader.stream_position()? + size;
+
+                while reader.stream_position()? < end {
+                    let Some((id, Some(size))) = read_element(&mut reader)? else {
+                        return Err(MediaError::Malformed("bad Info element"));
+                    };
+
+                    match id {
+                        TIMECODE_SCALE => timecode_scale = read_uint(&mut reader, size)?,
+                        DURATION => duration = Some(read_float(&mut reader, size)?),
+                        _ => skip(&mut reader, size)?,
+                    }
+                }
+            }
+            (ATTACHMENTS, Some(size)) if with_preview => {
+                let end = reader.stream_position()? + size;
+
+                while reader.stream_position()? < end {
+                    let Some((id, Some(size))) = read_element(&mut reader)? else {
+                        return Err(MediaError::Malformed("bad Attachments element"));
+                    };
+
+                    if id != ATTACHED_FILE {
+                        skip(&mut reader, size)?;
+                        continue;
+                    }
+
+                    let attachment = read_attachment(&mut reader, size)?;
+
+                    // Prefer the image named `cover`
+                    if let Some((is_cover, image)) = attachment
+                        && cover.as_ref().is_none_or(|(found, _)| !found)
+                        && (is_cover || cover.is_none())
+                    {
+                        cover = Some((is_cover, image));
+                    }
+                }
+            }
+            (TRACKS, Some(size)) if with_preview => {
+                let end = reader.stream_position()? + size;
+
+                while reader.stream_position()? < end {
+                    let Some((id, Some(size))) = read_element(&mut reader)? else {
+                        return Err(MediaError::Malformed("bad Tracks element"));
+                    };
+
+                    if id != TRACK_ENTRY {
+                        skip(&mut reader, size)?;
+                        continue;
+                    }
+
+                    let track = read_vp8_track(&mut reader, size)?;
+                    vp8_track = vp8_track.or(track);
+                }
+            }
+            // Clusters of the live streams have unknown sizes and can't be skipped
+            (CLUSTER, None) => break,
+            (_, Some(size)) => skip(&mut reader, size)?,
+            (_, None) => return Err(MediaError::Malformed("unknown element size")),
+        }
+    }
+
+    let duration = duration.ok_or(MediaError::Malformed("no segment duration"))?;
+    let nanos = duration * timecode_scale as f64;
+
+    if !nanos.is_finite() || nanos < 0.0 {
+        return Err(MediaError::Malformed("bad segment duration"));
+    }
+
+    Ok(MediaInfo {
+        duration: Duration::from_nanos(nanos as u64),
+        cover: cover.map(|(_, image)| image),
+        keyframe,
+    })
+}
+
+/// Returns the track number if the track entry is a VP8 video track
+fn read_vp8_track<R: Read + Seek>(reader: &mut R, size: u64) -> Result<Option<u64>, MediaError> {
+    let end = reader.stream_position()? + size;
+    let mut number = None;
+    let mut is_video = false;
+    let mut is_vp8 = false;
+
+    while reader.stream_position()? < end {
+        let Some((id, Some(size))) = read_element(reader)? else {
+            return Err(MediaError::Malformed("bad TrackEntry element"));
+        };
+
+        match id {
+            TRACK_NUMBER => number = Some(read_uint(reader, size)?),
+            TRACK_TYPE => is_video = read_uint(reader, size)? == 1,
+            CODEC_ID if size <= MAX_STRING_SIZE => is_vp8 = read_bytes(reader, size)? == b"V_VP8",
+            _ => skip(reader, size)?,
+        }
+    }
+
+    Ok(number.filter(|_| is_video && is_vp8))
+}
+
+/// Looks for the first keyframe of the `track` in the cluster. The reader ends up at the end of
+/// the cluster if its size is known or right after the keyframe block otherwise
+fn read_keyframe<R: Read + Seek>(
+    reader: &mut R,
+    size: Option<u64>,
+    track: u64,
+) -> Result<Option<Vec<u8>>, MediaError> {
+    let end = match size {
+        Some(size) => Some(reader.stream_position()? + size),
+        None => None,
+    };
+
+    let mut keyframe = None;
+
+    while keyframe.is_none() {
+        if let Some(end) = end
+            && reader.stream_position()? >= end
+        {
+            break;
+        }
+
+        let Some((id, Some(size))) = read_element(reader)? else {
+            break;
+        };
+
+        match id {
+            // Look into the group children
+            BLOCK_GROUP => continue,
+            SIMPLE_BLOCK | BLOCK => keyframe = read_block_keyframe(reader, size, track)?,
+            _ => skip(reader, size)?,
+        }
+    }
+
+    if let Some(end) = end {
+        reader.seek(SeekFrom::Start(end))?;
+    }
+
+    Ok(keyframe)
+}
+
+/// Returns the block frame as a WebP image if it's a VP8 keyframe of the `track`
+fn read_block_keyframe<R: Read + Seek>(
+    reader: &mut R,
+    size: u64,
+    track: u64,
+) -> Result<Option<Vec<u8>>, MediaError> {
+    let end = reader.stream_position()? + size;
+
+    let mut first = [0u8];
+    reader.read_exact(&mut first)?;
+    let (number, len) = read_vint(reader, first[0])?;
+
+    // 2 byte timecode and flags
+    let mut header = [0u8; 3];
+    reader.read_exact(&mut header)?;
+
+    let frame_size = size
+        .checked_sub(u64::from(len) + 3)
+        .ok_or(MediaError::Malformed("bad Block element"))?;
+
+    // Laced blocks hold several frames
+    if number != track || header[2] & 0x06 != 0 || frame_size > MAX_COVER_SIZE {
+        reader.seek(SeekFrom::Start(end))?;
+        return Ok(None);
+    }
+
+    let frame = read_bytes(reader, frame_size)?;
+
+    // The keyframe bit of the frame tag is 0 and the start code follows the 3 byte tag
+    if frame.len() < 10 || frame[0] & 1 != 0 || frame[3..6] != [0x9D, 0x01, 0x2A] {
+        return Ok(None);
+    }
+
+    Ok(Some(vp8_to_webp(frame)))
+}
+
+/// A lossy WebP image is a VP8 keyframe in the RIFF container
+fn vp8_to_webp(frame: Vec<u8>) -> Vec<u8> {
+    let padding = frame.len() % 2;
+    let mut webp = Vec::with_capacity(20 + frame.len() + padding);
+
+    webp.extend(b"RIFF");
+    webp.extend(((12 + frame.len() + padding) as u32).to_le_bytes());
+    webp.extend(b"WEBPVP8 ");
+    webp.extend((frame.len() as u32).to_le_bytes());
+    webp.extend(frame);
+
+    // Chunks are padded to the even size
+    if padding == 1 {
+        webp.push(0);
+    }
+
+    webp
+}
+
+/// Returns the image data and whether the file is named `cover`
+fn read_attachment<R: Read + Seek>(
+    reader: &mut R,
+    size: u64,
+) -> Result<Option<(bool, Vec<u8>)>, MediaError> {
+    let end = reader.stream_position()? + size;
+    let mut is_cover = false;
+    let mut is_image = false;
+    let mut data = None;
+
+    while reader.stream_position()? < end {
+        let Some((id, Some(size))) = read_element(reader)? else {
+            return Err(MediaError::Malformed("bad AttachedFile element"));
+        };
+
+        match id {
+            FILE_NAME if size <= MAX_STRING_SIZE => {
+                let name = read_bytes(reader, size)?;
+                is_cover = name.starts_with(b"cover.");
+            }
+            FILE_MIME_TYPE if size <= MAX_STRING_SIZE => {
+                let mime = read_bytes(reader, size)?;
+                is_image = mime.starts_with(b"image/");
+            }
+            // The mime type may come after the data, remember the position
+            FILE_DATA if size <= MAX_COVER_SIZE => {
+                data = Some((reader.stream_position()?, size));
+                skip(reader, size)?;
+            }
+            _ => skip(reader, size)?,
+        }
+    }
+
+    let Some((pos, size)) = data.filter(|_| is_image) else {
+        return Ok(None);
+    };
+
+    reader.seek(SeekFrom::Start(pos))?;
+    let image = read_bytes(reader, size)?;
+    reader.seek(SeekFrom::Start(end))?;
+
+    Ok(Some((is_cover, image)))
+}
+
+/// Reads the EBML element ID and size. The size is `None` if unknown. Returns `None` on EOF
+fn read_element<R: Read>(reader: &mut R) -> Result<Option<(u32, Option<u64>)>, MediaError> {
+    let mut first = [0u8];
+    if reader.read(&mut first)? == 0 {
+        return Ok(None);
+    }
+
+    let (id, len) = read_vint(reader, first[0])?;
+    if len > 4 {
+        return Err(MediaError::Malformed("bad EBML element ID"));
+    }
+
+    // IDs keep the length marker
+    let id = (id | 1 << (7 * len)) as u32;
+
+    reader.read_exact(&mut first)?;
+    let (size, len) = read_vint(reader, first[0])?;
+    let unknown = size == (1 << (7 * len)) - 1;
+
+    Ok(Some((id, (!unknown).then_some(size))))
+}
+
+/// Reads the variable size integer starting with the `first` byte. Returns the value without the
+/// length marker and the length
+fn read_vint<R: Read>(reader: &mut R, first: u8) -> Result<(u64, u32), MediaError> {
+    let len = first.leading_zeros() + 1;
+    if len > 8 {
+        return Err(MediaError::Malformed("bad EBML integer"));
+    }
+
+    let mut value = u64::from(first) & (0xFF >> len);
+
+    for _ in 1..len {
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte)?;
+        value = value << 8 | u64::from(byte[0]);
+    }
+
+    Ok((value, len))
+}
+
+fn read_uint<R: Read>(reader: &mut R, size: u64) -> Result<u64, MediaError> {
+    if size > 8 {
+        return Err(MediaError::Malformed("bad EBML unsigned integer"));
+    }
+
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf[8 - size as usize..])?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+fn read_float<R: Read>(reader: &mut R, size: u64) -> Result<f64, MediaError> {
+    match size {
+        4 => {
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf)?;
+            Ok(f64::from(f32::from_be_bytes(buf)))
+        }
+        8 => {
+            let mut buf = [0u8; 8];
+            reader.read_exact(&mut buf)?;
+            Ok(f64::from_be_bytes(buf))
+        }
+        _ => Err(MediaError::Malformed("bad EBML float")),
+    }
+}
+
+fn skip<R: Seek>(reader: &mut R, size: u64) -> std::io::Result<()> {
+    let offset = i64::try_from(size).map_err(crate::util::file_is_too_large)?;
+    reader.seek(SeekFrom::Current(offset))?;
+    Ok(())
+}
+
+fn read_bytes<R: Read>(reader: &mut R, size: u64) -> Result<Vec<u8>, MediaError> {
+    let mut buf = vec![0; crate::util::cast_file_size(size)?];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn ticks_to_duration(ticks: u64, per_second: u64) -> Duration {
+    let secs = ticks / per_second;
+    let nanos = (ticks % per_second) * 1_000_000_000 / per_second;
+    Duration::new(secs, nanos as u32)
+}
+
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut len = 0;
+
+    while len < buf.len() {
+        match reader.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+
+    Ok(len)
+}
+
+fn read_u32_be<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64_be<R: Read>(reader: &mut R) -> std::io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+#[derive(Debug)]
+pub enum MediaError {
+    /// The container or codec isn't supported
+    Unsupported,
+    Malformed(&'static str),
+    Io(std::io::Error),
+    Tokio(tokio::task::JoinError),
+}
+
+impl From<std::io::Error> for MediaError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<tokio::task::JoinError> for MediaError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        Self::Tokio(err)
+    }
+}
+
+impl std::fmt::Display for MediaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsupported => write!(f, "unsupported media format"),
+            Self::Malformed(reason) => write!(f, "malformed media file: {reason}"),
+            Self::Io(error) => write!(f, "Cannot read media file: {error}"),
+            Self::Tokio(error) => write!(f, "Failed to join the media task: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for MediaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Unsupported | Self::Malformed(_) => None,
+            Self::Io(error) => Some(error),
+            Self::Tokio(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn ogg_page(granule: u64, packet: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS\0\0".to_vec();
+        page.extend(granule.to_le_bytes());
+        page.extend([0; 12]);
+        page.push(1);
+        page.push(packet.len() as u8);
+        page.extend(packet);
+        page
+    }
+
+    fn mp4_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut b = (content.len() as u32 + 8).to_be_bytes().to_vec();
+        b.extend(kind);
+        b.extend(content);
+        b
+    }
+
+    fn ebml(id: u32, content: &[u8]) -> Vec<u8> {
+        let mut element: Vec<u8> = id
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|byte| *byte == 0)
+            .collect();
+        // 8 byte size
+        element.push(0x01);
+        element.extend(&(content.len() as u64).to_be_bytes()[1..]);
+        element.extend(content);
+        element
+    }
+
+    #[test]
+    fn video_info() {
+        let mut mvhd = vec![1, 0, 0, 0];
+        mvhd.extend([0; 16]);
+        mvhd.extend(600u32.to_be_bytes());
+        mvhd.extend(6000u64.to_be_bytes());
+
+        let mut data = vec![0, 0, 0, 13, 0, 0, 0, 0];
+        data.extend(b"JPEG");
+
+        let mut meta = vec![0; 4];
+        meta.extend(mp4_box(b"hdlr", &[0; 25]));
+        meta.extend(mp4_box(
+            b"ilst",
+            &mp4_box(b"covr", &mp4_box(b"data", &data)),
+        ));
+
+        let mut moov = mp4_box(b"mvhd", &mvhd);
+        moov.extend(mp4_box(b"udta", &mp4_box(b"meta", &meta)));
+
+        let mut mp4 = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        mp4.extend(mp4_box(b"moov", &moov));
+
+        let info = read_info(Cursor::new(mp4)).unwrap();
+        assert_eq!(info.duration, Duration::from_secs(10));
+        assert_eq!(info.cover.as_deref(), Some(&b"JPEG"[..]));
+
+        let attachment = |name: &[u8], mime: &[u8], data: &[u8]| {
+            let mut file = ebml(FILE_NAME, name);
+            file.extend(ebml(FILE_DATA, data));
+            file.extend(ebml(FILE_MIME_TYPE, mime));
+            ebml(ATTACHED_FILE, &file)
+        };
+
+        let mut info = ebml(TIMECODE_SCALE, &1_000_000u32.to_be_bytes());
+        info.extend(ebml(DURATION, &2500f32.to_be_bytes()));
+
+        let mut attachments = attachment(b"font.ttf", b"font/ttf", b"TTF");
+        attachments.extend(attachment(b"thumb.png", b"image/png", b"PNG"));
+        attachments.extend(attachment(b"cover.jpg", b"image/jpeg", b"JPEG"));
+
+        let mut segment = ebml(0x114D9B74, &[0; 4]);
+        segment.extend(ebml(INFO, &info));
+        segment.extend(ebml(CLUSTER, &[0; 32]));
+        segment.extend(ebml(ATTACHMENTS, &attachments));
+
+        let mut webm = ebml(0x1A45DFA3, &ebml(0x4282, b"webm"));
+        webm.extend(ebml(SEGMENT, &segment));
+
+        let info = read_info(Cursor::new(&webm)).unwrap();
+        assert_eq!(info.duration, Duration::from_millis(2500));
+        assert_eq!(info.cover.as_deref(), Some(&b"JPEG"[..]));
+
+        assert_eq!(
+            read_duration(Cursor::new(webm)).unwrap(),
+            Duration::from_millis(2500)
+        );
+
+        // Oversized names are skipped instead of being allocated
+        let mut file = FILE_NAME.to_be_bytes()[2..].to_vec();
+        file.extend([0x01, 0x04, 0, 0, 0, 0, 0, 0]);
+        file.extend(b"cover.jpg");
+        let len = file.len() as u64;
+        assert!(matches!(
+            read_attachment(&mut Cursor::new(file), len),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn vp8_keyframe() {
+        let track = |number: u8, kind: u8, codec: &[u8]| {
+            let mut entry = ebml(TRACK_NUMBER, &[number]);
+            entry.extend(ebml(TRACK_TYPE, &[kind]));
+            entry.extend(ebml(CODEC_ID, codec));
+            ebml(TRACK_ENTRY, &entry)
+        };
+
+        // Track number, timecode and flags
+        let block = |id, number: u8, frame: &[u8]| {
+            let mut block = vec![0x80 | number, 0, 0, 0x80];
+            block.extend(frame);
+            ebml(id, &block)
+        };
+
+        let webm = |codec: &[u8], cluster: &[u8]| {
+            let mut tracks = track(1, 2, b"A_OPUS");
+            tracks.extend(track(2, 1, codec));
+
+            let mut segment = ebml(INFO, &ebml(DURATION, &1000f32.to_be_bytes()));
+            segment.extend(ebml(TRACKS, &tracks));
+            segment.extend(cluster);
+
+            let mut webm = ebml(0x1A45DFA3, &ebml(0x4282, b"webm"));
+            webm.extend(ebml(SEGMENT, &segment));
+            webm
+        };
+
+        // Keyframe tag, start code and 16x16 dimensions, odd length to get padded
+        let mut frame = vec![0x90, 0x02, 0x00, 0x9D, 0x01, 0x2A, 0x10, 0x00, 0x10, 0x00];
+        frame.extend([0; 5]);
+
+        let mut interframe = frame.clone();
+        interframe[0] |= 1;
+
+        let mut blocks = ebml(0xE7, &[0]);
+        blocks.extend(block(SIMPLE_BLOCK, 1, &[0; 16]));
+        blocks.extend(ebml(BLOCK_GROUP, &block(BLOCK, 2, &interframe)));
+        blocks.extend(ebml(BLOCK_GROUP, &block(BLOCK, 2, &frame)));
+
+        let keyframe = read_info(Cursor::new(webm(b"V_VP8", &ebml(CLUSTER, &blocks))))
+            .unwrap()
+            .keyframe
+            .unwrap();
+
+        assert_eq!(keyframe.len(), 20 + frame.len() + 1);
+
+        let image = image::ImageReader::new(Cursor::new(keyframe))
+            .with_guessed_format()
+            .unwrap();
+        assert_eq!(image.format(), Some(image::ImageFormat::WebP));
+        assert_eq!(image.into_dimensions().unwrap(), (16, 16));
+
+        // Clusters of unknown size are read up to the keyframe
+        let mut cluster = CLUSTER.to_be_bytes().to_vec();
+        cluster.extend([0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        cluster.extend(block(SIMPLE_BLOCK, 2, &frame));
+        cluster.extend([0xFF; 8]);
+
+        let info = read_info(Cursor::new(webm(b"V_VP8", &cluster))).unwrap();
+        assert_eq!(info.duration, Duration::from_secs(1));
+        assert!(info.keyframe.is_some());
+
+        // Other codecs can't be decoded
+        let info = read_info(Cursor::new(webm(b"V_VP9", &ebml(CLUSTER, &blocks)))).unwrap();
+        assert!(info.keyframe.is_none());
+
+        let mut vp8_blocks = ebml(0xE7, &[0]);
+        vp8_blocks.extend(block(SIMPLE_BLOCK, 2, &interframe));
+        let info = read_info(Cursor::new(webm(b"V_VP8", &ebml(CLUSTER, &vp8_blocks)))).unwrap();
+        assert!(info.keyframe.is_none());
+    }
+
+    #[test]
+    fn media_durations() {
+        // 48000 samples + 312 pre-skip
+        let mut opus = ogg_page(0, b"OpusHead\x01\x01\x38\x01\x80\xbb\0\0\0\0\0");
+        opus.extend(ogg_page(u64::MAX, b"OpusTags"));
+        opus.extend(ogg_page(48312 + 24000, &[0; 16]));
+        assert_eq!(
+            read_duration(Cursor::new(opus)).unwrap(),
+            Duration::from_millis(1500)
+        );
+
+        // 16kHz mono 16bit = 32000 bytes per second
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        wav.extend(b"LIST\x03\0\0\0abc\0");
+        wav.extend(b"fmt \x10\0\0\0\x01\0\x01\0\x80\x3e\0\0\x00\x7d\0\0\x02\0\x10\0");
+        wav.extend(b"data\x00\xfa\0\0");
+        assert_eq!(
+            read_duration(Cursor::new(wav)).unwrap(),
+            Duration::from_secs(2)
+        );
+
+        let mut mvhd = vec![0; 12];
+        mvhd.extend(1000u32.to_be_bytes());
+        mvhd.extend(3250u32.to_be_bytes());
+        mvhd.extend([0; 80]);
+
+        let mut mp4 = mp4_box(b"ftyp", b"M4A \0\0\0\0");
+        mp4.extend(mp4_box(b"mdat", &[0; 32]));
+        mp4.extend(mp4_box(b"moov", &mp4_box(b"mvhd", &mvhd)));
+        assert_eq!(
+            read_duration(Cursor::new(mp4)).unwrap(),
+            Duration::from_millis(3250)
+        );
+
+        assert!(matches!(
+            read_duration(Cursor::new(b"ID3\x04\0\0\0\0\0\0\0\0")),
+            Err(MediaError::Unsupported)
+        ));
+    }
+}
@@ -0,0 +1,608 @@
+//! SimpleX markdown parser.
+//!
+//! Turns the received message text into a tree of [`Span`]s which can be rendered back as plain
+//! text, HTML or ANSI terminal sequences:
+//!
+//! ```ignore
+//! let md = Markdown::parse("*Hello*, @alice! See https://simplex.chat");
+//!
+//! assert_eq!(md.to_plain_text(), "Hello, @alice! See https://simplex.chat");
+//! assert_eq!(md.to_html(), "<b>Hello</b>, <span class=\"mention\">@alice</span>! See <a href=\"https://simplex.chat\">https://simplex.chat</a>");
+//! ```
+//!
+//! The parser is lenient: markup that doesn't form a valid span, e.g. an unclosed `*`, is kept as
+//! plain text. Inline spans don't cross line boundaries and markers only open at the start of a
+//! word, so `snake_case_name` or `2*3*4` stay plain.
+
+use simploxide_api_types::Color;
+
+use std::fmt::Write as _;
+
+/// A parsed markdown span. Formatting spans contain nested spans, SimpleX clients don't render
+/// nested formatting but other renderers may.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Span {
+    Plain(String),
+    Bold(Vec<Span>),
+    Italic(Vec<Span>),
+    Strike(Vec<Span>),
+    Secret(Vec<Span>),
+    Colored(Color, Vec<Span>),
+    /// Inline `` `code` ``
+    Monospace(String),
+    /// A fenced code block
+    CodeBlock {
+        lang: Option<String>,
+        code: String,
+    },
+    Link(String),
+    /// A member mention, the name without `@` and quotes
+    Mention(String),
+}
+
+/// Parses the color markup like `1`, `r` or `red`
+fn color_from_markup(markup: &str) -> Option<Color> {
+    let color = match markup {
+        "1" | "r" | "red" => Color::Red,
+        "2" | "g" | "green" => Color::Green,
+        "3" | "b" | "blue" => Color::Blue,
+        "4" | "y" | "yellow" => Color::Yellow,
+        "5" | "c" | "cyan" => Color::Cyan,
+        "6" | "m" | "magenta" => Color::Magenta,
+        _ => return None,
+    };
+
+    Some(color)
+}
+
+fn color_name(color: Color) -> &'static str {
+    match color {
+        Color::Red => "red",
+        Color::Green => "green",
+        Color::Yellow => "yellow",
+        Color::Blue => "blue",
+        Color::Magenta => "magenta",
+        Color::Cyan => "cyan",
+        _ => "black",
+    }
+}
+
+fn color_ansi(color: Color) -> &'static str {
+    match color {
+        Color::Red => "31",
+        Color::Green => "32",
+        Color::Yellow => "33",
+        Color::Blue => "34",
+        Color::Magenta => "35",
+        Color::Cyan => "36",
+        _ => "30",
+    }
+}
+
+/// The parsed message text
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Markdown {
+    spans: Vec<Span>,
+}
+
+impl Markdown {
+    pub fn parse(text: &str) -> Self {
+        let mut spans = Vec::new();
+        let mut inline = String::new();
+        let mut lines = text.split_inclusive('\n');
+
+        while let Some(line) = lines.next() {
+            // A fence without the closing one, or with the code on the same line, is a plain text
+            let Some(lang) = line
+                .trim()
+                .strip_prefix(FENCE)
+                .filter(|lang| !lang.contains('`'))
+                .filter(|_| lines.clone().any(|line| line.trim() == FENCE))
+            else {
+                inline.push_str(line);
+                continue;
+            };
+
+            parse_inline(&inline, &mut spans);
+            inline.clear();
+
+            let mut code = String::new();
+            for line in lines.by_ref() {
+                if line.trim() == FENCE {
+                    // Keep the line break after the block
+                    if line.ends_with('\n') {
+                        inline.push('\n');
+                    }
+                    break;
+                }
+
+                code.push_str(line);
+            }
+
+            if code.ends_with('\n') {
+                code.pop();
+            }
+
+            let lang = lang.trim();
+            spans.push(Span::CodeBlock {
+                lang: (!lang.is_empty()).then(|| lang.to_owned()),
+                code,
+            });
+        }
+
+        parse_inline(&inline, &mut spans);
+        Self { spans }
+    }
+
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    pub fn into_spans(self) -> Vec<Span> {
+        self.spans
+    }
+
+    /// The text with all formatting removed
+    pub fn to_plain_text(&self) -> String {
+        let mut out = String::new();
+        render_plain(&self.spans, &mut out);
+        out
+    }
+
+    /// HTML markup. Line breaks are rendered as `<br>`, secrets as `<span class="secret">` and
+    /// mentions as `<span class="mention">`
+    pub fn to_html(&self) -> String {
+        let mut out = String::new();
+        render_html(&self.spans, &mut out);
+        out
+    }
+
+    /// Text with ANSI escape sequences for terminals. Secrets are rendered in reverse video, control
+    /// characters of the text except line breaks and tabs are dropped
+    pub fn to_ansi(&self) -> String {
+        let mut out = String::new();
+        render_ansi(&self.spans, &mut Vec::new(), &mut out);
+        out
+    }
+}
+
+impl From<Vec<Span>> for Markdown {
+    fn from(spans: Vec<Span>) -> Self {
+        Self { spans }
+    }
+}
+
+impl std::str::FromStr for Markdown {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::parse(s))
+    }
+}
+
+const FENCE: &str = "```";
+
+/// Trailing characters not included into links and mentions
+const TRAILING_PUNCTUATION: [char; 9] = ['.', ',', ';', ':', '!', '?', ')', '\'', '"'];
+
+const LINK_SCHEMES: [&str; 3] = ["https://", "http://", "simplex:"];
+
+/// Markup starts after a boundary: a whitespace, an ASCII punctuation or the text start. Unlike
+/// other symbols, the word joiner (U+2060) isn't a boundary, it escapes the markers
+pub(super) fn is_boundary(c: Option<char>) -> bool {
+    c.is_none_or(|c| c.is_whitespace() || c.is_ascii_punctuation())
+}
+
+fn parse_inline(text: &str, spans: &mut Vec<Span>) {
+    let mut plain = String::new();
+    let mut i = 0;
+
+    while i < text.len() {
+        let prev = text[..i].chars().next_back();
+        let rest = &text[i..];
+        let c = rest.chars().next().unwrap();
+
+        let parsed = if !is_boundary(prev) {
+            None
+        } else {
+            match c {
+                '*' | '_' | '~' | '#' => parse_format(rest, c),
+                '`' => parse_monospace(rest),
+                '!' => parse_colored(rest),
+                '@' => parse_mention(rest),
+                _ => parse_link(rest),
+            }
+        };
+
+        match parsed {
+            Some((span, len)) => {
+                if !plain.is_empty() {
+                    spans.push(Span::Plain(std::mem::take(&mut plain)));
+                }
+
+                spans.push(span);
+                i += len;
+            }
+            None => {
+                plain.push(c);
+                i += c.len_utf8();
+            }
+        }
+    }
+
+    if !plain.is_empty() {
+        spans.push(Span::Plain(plain));
+    }
+}
+
+/// Finds the closing `marker` for the content starting at `text[start..]`. Returns the content
+/// length
+fn find_closing(text: &str, start: usize, marker: char) -> Option<usize> {
+    let content = &text[start..];
+
+    if content.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    for (i, c) in content.char_indices() {
+        if c == '\n' {
+            return None;
+        }
+
+        if c != marker || i == 0 {
+            continue;
+        }
+
+        let before = content[..i].chars().next_back();
+        let after = content[i + c.len_utf8()..].chars().next();
+
+        if before.is_some_and(|c| !c.is_whitespace()) && after.is_none_or(|c| !c.is_alphanumeric())
+        {
+            return Some(i);
+        }
+    }
+
+    None
+}
+
+fn parse_format(text: &str, marker: char) -> Option<(Span, usize)> {
+    let len = find_closing(text, 1, marker)?;
+
+    let mut inner = Vec::new();
+    parse_inline(&text[1..1 + len], &mut inner);
+
+    let span = match marker {
+        '*' => Span::Bold(inner),
+        '_' => Span::Italic(inner),
+        '~' => Span::Strike(inner),
+        _ => Span::Secret(inner),
+    };
+
+    Some((span, len + 2))
+}
+
+fn parse_monospace(text: &str) -> Option<(Span, usize)> {
+    let len = find_closing(text, 1, '`')?;
+    Some((Span::Monospace(text[1..1 + len].to_owned()), len + 2))
+}
+
+/// `!1 text!`, `!r text!` or `!red text!`
+fn parse_colored(text: &str) -> Option<(Span, usize)> {
+    let space = text.find(' ')?;
+    let color = color_from_markup(&text[1..space])?;
+    let start = space + 1;
+    let len = find_closing(text, start, '!')?;
+
+    let mut inner = Vec::new();
+    parse_inline(&text[start..start + len], &mut inner);
+
+    Some((Span::Colored(color, inner), start + len + 1))
+}
+
+/// `@name` or `@'name with spaces'`
+fn parse_mention(text: &str) -> Option<(Span, usize)> {
+    if let Some(quoted) = text[1..].strip_prefix('\'') {
+        let end = quoted.find(['\'', '\n'])?;
+        let name = &quoted[..end];
+
+        if name.trim().is_empty() || !quoted[end..].starts_with('\'') {
+            return None;
+        }
+
+        return Some((Span::Mention(name.to_owned()), end + 3));
+    }
+
+    let name = text[1..]
+        .split(char::is_whitespace)
+        .next()?
+        .trim_end_matches(TRAILING_PUNCTUATION);
+
+    if name.is_empty() {
+        return None;
+    }
+
+    Some((Span::Mention(name.to_owned()), name.len() + 1))
+}
+
+fn parse_link(text: &str) -> Option<(Span, usize)> {
+    let scheme = LINK_SCHEMES
+        .iter()
+        .find(|scheme| text.starts_with(**scheme))?;
+
+    let link = text
+        .split(char::is_whitespace)
+        .next()?
+        .trim_end_matches(TRAILING_PUNCTUATION);
+
+    if link.len() <= scheme.len() {
+        return None;
+    }
+
+    Some((Span::Link(link.to_owned()), link.len()))
+}
+
+fn render_plain(spans: &[Span], out: &mut String) {
+    for span in spans {
+        match span {
+            Span::Plain(text) | Span::Monospace(text) | Span::Link(text) => out.push_str(text),
+            Span::CodeBlock { code, .. } => out.push_str(code),
+            Span::Mention(name) => {
+                out.push('@');
+                out.push_str(name);
+            }
+            Span::Bold(inner)
+            | Span::Italic(inner)
+            | Span::Strike(inner)
+            | Span::Secret(inner)
+            | Span::Colored(_, inner) => render_plain(inner, out),
+        }
+    }
+}
+
+fn render_html(spans: &[Span], out: &mut String) {
+    fn tagged(tag: &str, attrs: &str, inner: &[Span], out: &mut String) {
+        let _ = write!(out, "<{tag}{attrs}>");
+        render_html(inner, out);
+        let _ = write!(out, "</{tag}>");
+    }
+
+    for span in spans {
+        match span {
+            Span::Plain(text) => {
+                for (i, line) in text.split('\n').enumerate() {
+                    if i > 0 {
+                        out.push_str("<br>");
+                    }
+
+                    escape_html(line, out);
+                }
+            }
+            Span::Bold(inner) => tagged("b", "", inner, out),
+            Span::Italic(inner) => tagged("i", "", inner, out),
+            Span::Strike(inner) => tagged("s", "", inner, out),
+            Span::Secret(inner) => tagged("span", " class=\"secret\"", inner, out),
+            Span::Colored(color, inner) => {
+                let style = format!(" style=\"color: {}\"", color_name(*color));
+                tagged("span", &style, inner, out);
+            }
+            Span::Monospace(code) => {
+                out.push_str("<code>");
+                escape_html(code, out);
+                out.push_str("</code>");
+            }
+            Span::CodeBlock { lang, code } => {
+                match lang {
+                    Some(lang) => {
+                        out.push_str("<pre><code class=\"language-");
+                        escape_html(lang, out);
+                        out.push_str("\">");
+                    }
+                    None => out.push_str("<pre><code>"),
+                }
+
+                escape_html(code, out);
+                out.push_str("</code></pre>");
+            }
+            Span::Link(link) => {
+                out.push_str("<a href=\"");
+                escape_html(link, out);
+                out.push_str("\">");
+                escape_html(link, out);
+                out.push_str("</a>");
+            }
+            Span::Mention(name) => {
+                out.push_str("<span class=\"mention\">@");
+                escape_html(name, out);
+                out.push_str("</span>");
+            }
+        }
+    }
+}
+
+fn escape_html(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// `styles` is the stack of the enclosing spans SGR codes, restored after each nested span
+fn render_ansi(spans: &[Span], styles: &mut Vec<&'static str>, out: &mut String) {
+    fn styled(code: &'static str, text: &str, styles: &[&'static str], out: &mut String) {
+        let _ = write!(out, "\x1b[{code}m");
+        escape_ansi(text, out);
+        restore_ansi(styles, out);
+    }
+
+    for span in spans {
+        match span {
+            Span::Plain(text) => escape_ansi(text, out),
+            Span::Bold(inner) => nested_ansi("1", inner, styles, out),
+            Span::Italic(inner) => nested_ansi("3", inner, styles, out),
+            Span::Strike(inner) => nested_ansi("9", inner, styles, out),
+            Span::Secret(inner) => nested_ansi("7", inner, styles, out),
+            Span::Colored(color, inner) => nested_ansi(color_ansi(*color), inner, styles, out),
+            Span::Monospace(code) => styled("2", code, styles, out),
+            Span::CodeBlock { code, .. } => styled("2", code, styles, out),
+            Span::Link(link) => styled("4", link, styles, out),
+            Span::Mention(name) => styled("1", &format!("@{name}"), styles, out),
+        }
+    }
+}
+
+fn nested_ansi(
+    code: &'static str,
+    inner: &[Span],
+    styles: &mut Vec<&'static str>,
+    out: &mut String,
+) {
+    let _ = write!(out, "\x1b[{code}m");
+    styles.push(code);
+    render_ansi(inner, styles, out);
+    styles.pop();
+    restore_ansi(styles, out);
+}
+
+/// Drops the control characters except line breaks and tabs, so the received text can't inject
+/// its own escape sequences
+fn escape_ansi(text: &str, out: &mut String) {
+    out.extend(
+        text.chars()
+            .filter(|c| !c.is_control() || matches!(c, '\n' | '\t')),
+    );
+}
+
+fn restore_ansi(styles: &[&'static str], out: &mut String) {
+    out.push_str("\x1b[0m");
+
+    for code in styles {
+        let _ = write!(out, "\x1b[{code}m");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(text: &str) -> Span {
+        Span::Plain(text.to_owned())
+    }
+
+    #[test]
+    fn parse_spans() {
+        let md = Markdown::parse(
+            "*Hi* @alice and @'Bob Smith', _see_ https://simplex.chat.\n\
+             !1 red *bold*! ~x~ #secret# `a*b*c` snake_case_name 2*3*4",
+        );
+
+        assert_eq!(
+            md.spans(),
+            [
+                Span::Bold(vec![plain("Hi")]),
+                plain(" "),
+                Span::Mention("alice".to_owned()),
+                plain(" and "),
+                Span::Mention("Bob Smith".to_owned()),
+                plain(", "),
+                Span::Italic(vec![plain("see")]),
+                plain(" "),
+                Span::Link("https://simplex.chat".to_owned()),
+                plain(".\n"),
+                Span::Colored(
+                    Color::Red,
+                    vec![plain("red "), Span::Bold(vec![plain("bold")])]
+                ),
+                plain(" "),
+                Span::Strike(vec![plain("x")]),
+                plain(" "),
+                Span::Secret(vec![plain("secret")]),
+                plain(" "),
+                Span::Monospace("a*b*c".to_owned()),
+                plain(" snake_case_name 2*3*4"),
+            ]
+        );
+
+        assert_eq!(
+            md.to_plain_text(),
+            "Hi @alice and @Bob Smith, see https://simplex.chat.\nred bold x secret a*b*c snake_case_name 2*3*4"
+        );
+
+        // Markers close before any non-alphanumeric character but don't open after the word joiner
+        assert_eq!(
+            Markdown::parse("*bold*— *bold*😀 \u{2060}*x* é*y*").spans(),
+            [
+                Span::Bold(vec![plain("bold")]),
+                plain("— "),
+                Span::Bold(vec![plain("bold")]),
+                plain("😀 \u{2060}*x* é*y*"),
+            ]
+        );
+
+        // Unclosed and whitespace-padded markup stays plain
+        assert_eq!(
+            Markdown::parse("* not bold * and *open").spans(),
+            [plain("* not bold * and *open")]
+        );
+    }
+
+    #[test]
+    fn code_blocks() {
+        let md = Markdown::parse("Run:\n```sh\necho *hi*\nls\n```\nDone");
+
+        assert_eq!(
+            md.spans(),
+            [
+                plain("Run:\n"),
+                Span::CodeBlock {
+                    lang: Some("sh".to_owned()),
+                    code: "echo *hi*\nls".to_owned(),
+                },
+                plain("\nDone"),
+            ]
+        );
+        assert_eq!(md.to_plain_text(), "Run:\necho *hi*\nls\nDone");
+        assert_eq!(
+            md.to_html(),
+            "Run:<br><pre><code class=\"language-sh\">echo *hi*\nls</code></pre><br>Done"
+        );
+
+        // Unclosed and one-line fences don't swallow the rest of the message
+        assert_eq!(
+            Markdown::parse("```sh\n*bold*").spans(),
+            [plain("```sh\n"), Span::Bold(vec![plain("bold")])]
+        );
+        assert_eq!(
+            Markdown::parse("```x```\n*bold*\n").spans(),
+            [
+                Span::Monospace("``x".to_owned()),
+                plain("``\n"),
+                Span::Bold(vec![plain("bold")]),
+                plain("\n")
+            ]
+        );
+    }
+
+    #[test]
+    fn renderers() {
+        let md = Markdown::parse("*a <b>* !2 _c_! @bob");
+
+        assert_eq!(
+            md.to_html(),
+            "<b>a &lt;b&gt;</b> <span style=\"color: green\"><i>c</i></span> <span class=\"mention\">@bob</span>"
+        );
+        assert_eq!(
+            md.to_ansi(),
+            "\x1b[1ma <b>\x1b[0m \x1b[32m\x1b[3mc\x1b[0m\x1b[32m\x1b[0m \x1b[1m@bob\x1b[0m"
+        );
+
+        // Control characters in the received text are dropped
+        let md = Markdown::parse("a\x1b[2J\tb\n`c\u{9b}31m` @d\x07");
+        assert_eq!(md.to_ansi(), "a[2J\tb\n\x1b[2mc31m\x1b[0m \x1b[1m@d\x1b[0m");
+    }
+}
@@ -30,6 +30,58 @@
 //! ).await?;
 //! ```
 //!
+//! ### Rich text
+//!
+//! ```ignore
+//! // Nested styles, the user provided text is escaped
+//! let text = RichText::new()
+//!     .push("Hi ")
+//!     .bold(&user_name)
+//!     .push(", your code is ")
+//!     .monospace(code)
+//!     .newline()
+//!     .italic(RichText::new().push("Expires ").red("in 5 minutes"));
+//!
+//! bot.send_msg(chat, text).await?;
+//! ```
+//!
+//! ### Mentions
+//!
+//! ```ignore
+//! // "Welcome, @alice"
+//! bot.send_msg(group, "Welcome,").mention(&member).await?;
+//!
+//! // Mention markup inside formatted text
+//! let mention = Mention::from(&member);
+//! bot.send_msg(group, format!("{} {mention}", "Attention".bold()))
+//!     .with_mentions([mention])
+//!     .await?;
+//! ```
+//!
+//! ### Live messages
+//!
+//! ```ignore
+//! // The text is updated in place as it gets generated
+//! let mut live = bot.send_msg(chat, "Thinking...").live().await?;
+//! live.set_text("").await?;
+//!
+//! while let Some(token) = tokens.next().await {
+//!     live.append(&token).await?;
+//! }
+//!
+//! live.finish().await?;
+//! ```
+//!
+//! ### Long texts
+//!
+//! ```ignore
+//! // Sent as several messages if the report doesn't fit into one
+//! bot.send_msg(chat, long_report)
+//!     .reply_to(request_id)
+//!     .split_oversized()
+//!     .await?;
+//! ```
+//!
 //! ### Simple files
 //!
 //! ```ignore
@@ -51,6 +103,18 @@
 //!    .await?;
 //! ```
 //!
+//! ### Voice messages
+//!
+//! ```ignore
+//! // Duration is known
+//! bot.send_msg(chat, Voice::new("reply.ogg", Duration::from_secs(7))).await?;
+//!
+//! // Duration is read from the OGG/M4A/WAV file(requires `multimedia` feature)
+//! bot.send_msg(chat, "Listen to this")
+//!     .with_voice(Voice::from_file("reply.ogg").await?)
+//!     .await?;
+//! ```
+//!
 //! ### Images
 //!
 //! ```ignore
@@ -94,8 +158,9 @@
 //!
 //! ### Video
 //!
-//! Automatic preview generation from video files is currently unsupported. A custom preview can be
-//! provided, or the message sends with the default placeholder preview.
+//! Video frames can't be decoded without external codecs. A custom preview can be provided, or the
+//! message sends with the embedded cover art(requires `multimedia`) or the default placeholder
+//! preview.
 //!
 //! ```ignore
 //! // Default placeholder preview
@@ -113,6 +178,9 @@
 //!             .with_transcoder(Transcoder::thumbnail().with_size(255, 255))
 //!     )
 //!     .await?;
+//!
+//! // Duration and the cover art thumbnail are read from the MP4/WebM file(requires `multimedia`)
+//! bot.send_msg(chat, Video::from_file("vid.mp4").await?).await?;
 //! ```
 //!
 //! ### Link
@@ -243,23 +311,47 @@
 //!     .with_preview(ImagePreview::from_bytes(thumb_bytes))
 //!     .await;
 //! ```
+//!
+//! ### Parsing received text
+//!
+//! ```ignore
+//! // See the markdown module for the span tree
+//! let md = Markdown::parse(&text);
+//! println!("{}", md.to_ansi());
+//! ```
+
+pub mod markdown;
+pub mod rich_text;
 
-use serde::Serialize;
+pub use markdown::{Markdown, Span};
+pub use rich_text::RichText;
+
+use serde::{Deserialize, Serialize};
 use simploxide_api_types::{
-    ComposedMessage, CryptoFile, CryptoFileArgs, JsonObject, LinkContent, LinkOwnerSig,
-    LinkPreview, MsgChatLink, MsgContent, ReportReason, client_api::ClientApi,
-    commands::ApiSendMessages, responses::NewChatItemsResponse,
+    ComposedMessage, CryptoFile, CryptoFileArgs, GroupMember, JsonObject, LinkContent,
+    LinkOwnerSig, LinkPreview, MsgChatLink, MsgContent, ReportReason, UpdatedMessage,
+    client_api::{BadResponseError, ClientApi, ClientApiError},
+    commands::{ApiSendMessages, ApiUpdateChatItem},
+    responses::{ApiUpdateChatItemResponse, NewChatItemsResponse},
 };
 
 #[cfg(feature = "multimedia")]
 use crate::preview;
 use crate::{
-    id::{ChatId, MessageId},
+    est_size::{EstSize as _, MAX_TEXT_SIZE, MIN_SPLIT_SIZE, split_text},
+    id::{ChatId, MemberId, MessageId},
     preferences,
     preview::{ImagePreview, PreviewKind},
+    scheduler::{JobHandle, Schedule, Scheduler},
 };
 
-use std::{path::Path, pin::Pin, sync::Arc, time::Duration};
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 /// A kind for simple text messsages
 pub struct TextKind;
@@ -286,7 +378,23 @@ impl sealed::SimplySendable for RawKind {}
 /// re-encoding at send time.
 pub struct PreviewableKind(ImagePreview);
 
-impl sealed::MessageKind for PreviewableKind {}
+/// Builder kind for [`MessageBuilder::live`]. Awaiting the builder returns a [`LiveMessage`]
+/// handle.
+pub struct LiveKind;
+
+impl sealed::MessageKind for LiveKind {}
+
+/// Builder kind for [`MessageBuilder::split_oversized`]. Holds the max text size of a single
+/// message.
+pub struct SplitKind(usize);
+
+impl sealed::MessageKind for SplitKind {}
+
+impl sealed::MessageKind for PreviewableKind {
+    fn into_preview(self) -> Option<ImagePreview> {
+        Some(self.0)
+    }
+}
 
 pub trait MessageLike {
     type Kind: sealed::MessageKind;
@@ -441,6 +549,66 @@ impl MessageLike for Text<'_> {
     }
 }
 
+/// A group member mention rendered as the `@name` markup. Use [`MessageBuilder::mention`] to append
+/// a mention to the message text or embed it into formatted text manually and register it with
+/// [`MessageBuilder::with_mentions`]:
+///
+/// ```ignore
+/// let mention = Mention::from(&member);
+/// bot.send_msg(group, format!("{}, welcome to the group!", mention))
+///     .with_mentions([mention])
+///     .await?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mention {
+    name: String,
+    member_id: MemberId,
+}
+
+impl Mention {
+    /// The `name` must be the member display name as it appears in the text
+    pub fn new(name: impl Into<String>, member_id: impl Into<MemberId>) -> Self {
+        Self {
+            name: name.into(),
+            member_id: member_id.into(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn member_id(&self) -> MemberId {
+        self.member_id
+    }
+}
+
+impl From<&GroupMember> for Mention {
+    fn from(member: &GroupMember) -> Self {
+        Self::new(member.member_profile.display_name.clone(), member)
+    }
+}
+
+impl std::fmt::Display for Mention {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.name.contains(char::is_whitespace) {
+            write!(f, "@'{}'", self.name)
+        } else {
+            write!(f, "@{}", self.name)
+        }
+    }
+}
+
+impl MessageLike for Mention {
+    type Kind = TextKind;
+
+    fn into_builder_parts(self) -> (ComposedMessage, Self::Kind) {
+        let (mut msg, kind) = self.to_string().into_builder_parts();
+        msg.mentions.insert(self.name, self.member_id.raw());
+        (msg, kind)
+    }
+}
+
 impl MessageLike for CryptoFile {
     type Kind = RichKind;
     fn into_builder_parts(self) -> (ComposedMessage, RichKind) {
@@ -547,9 +715,10 @@ fn make_image_preview(_: &CryptoFile) -> ImagePreview {
     ImagePreview::default()
 }
 
-/// Video message type. Automatic preview generation from video files is unsupported; set a preview
+/// Video message type. Video frames can't be decoded without external codecs; set a preview
 /// explicitly or the default placeholder is used. Your app can generate video previews by calling
-/// the external `ffmpeg` process or similar.
+/// the external `ffmpeg` process or similar. With the `multimedia` feature [`Video::from_file`]
+/// reads the duration and the embedded cover art of MP4 and WebM files, see [`crate::media`].
 #[derive(Debug, Clone)]
 pub struct Video {
     source: CryptoFile,
@@ -572,6 +741,21 @@ impl Video {
         }
     }
 
+    /// Video message with the duration and the preview detected from the MP4 or WebM file. The
+    /// embedded cover art or the first VP8 keyframe is transcoded into the thumbnail on resolve,
+    /// the default placeholder is used if there are none, see [`crate::media`]. Only unencrypted
+    /// files are supported, set the crypto args after the detection.
+    #[cfg(feature = "multimedia")]
+    pub async fn from_file(path: impl AsRef<Path>) -> Result<Self, crate::media::MediaError> {
+        let info = crate::media::probe(path.as_ref()).await?;
+        let video = Self::new(path, info.duration);
+
+        Ok(match info.cover.or(info.keyframe) {
+            Some(thumbnail) => video.with_preview(ImagePreview::from_bytes(thumbnail)),
+            None => video,
+        })
+    }
+
     pub fn with_caption(mut self, caption: impl Into<String>) -> Self {
         self.text = caption.into();
         self
@@ -619,6 +803,84 @@ impl MessageLike for Video {
     }
 }
 
+/// Voice message type. The duration is shown in the player before the file is downloaded, with the
+/// `multimedia` feature [`Voice::from_file`] reads it from OGG, M4A or WAV files.
+#[derive(Debug, Clone)]
+pub struct Voice {
+    source: CryptoFile,
+    text: String,
+    duration: Duration,
+}
+
+impl Voice {
+    pub fn new(path: impl AsRef<Path>, duration: Duration) -> Self {
+        Self {
+            source: CryptoFile {
+                file_path: path.as_ref().display().to_string(),
+                crypto_args: None,
+                undocumented: Default::default(),
+            },
+            text: String::new(),
+            duration,
+        }
+    }
+
+    /// Voice message with the duration detected from the audio file, see [`crate::media`]. Only
+    /// unencrypted files are supported, set the crypto args after the detection.
+    #[cfg(feature = "multimedia")]
+    pub async fn from_file(path: impl AsRef<Path>) -> Result<Self, crate::media::MediaError> {
+        let duration = crate::media::duration(path.as_ref()).await?;
+        Ok(Self::new(path, duration))
+    }
+
+    pub fn with_caption(mut self, caption: impl Into<String>) -> Self {
+        self.text = caption.into();
+        self
+    }
+
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    pub fn with_crypto_args(mut self, args: CryptoFileArgs) -> Self {
+        self.source.crypto_args = Some(args);
+        self
+    }
+}
+
+impl From<CryptoFile> for Voice {
+    fn from(source: CryptoFile) -> Self {
+        Self {
+            source,
+            text: String::new(),
+            duration: Duration::ZERO,
+        }
+    }
+}
+
+impl MessageLike for Voice {
+    type Kind = RichKind;
+    fn into_builder_parts(self) -> (ComposedMessage, RichKind) {
+        (
+            ComposedMessage {
+                file_source: Some(self.source),
+                msg_content: MsgContent::make_voice(self.text, voice_duration(self.duration)),
+                quoted_item_id: None,
+                mentions: Default::default(),
+                undocumented: Default::default(),
+            },
+            RichKind,
+        )
+    }
+}
+
+/// Whole seconds rounded up, so the short clips aren't shown as empty
+fn voice_duration(duration: Duration) -> i32 {
+    let secs = duration.as_secs() + u64::from(duration.subsec_nanos() > 0);
+    secs.try_into().unwrap_or(i32::MAX)
+}
+
 /// Link preview message. Use `with_title`, `with_description`, and `with_image` to populate
 /// the Open Graph-style card shown to the recipient.
 #[derive(Debug, Clone)]
@@ -882,6 +1144,156 @@ impl MessageLike for Custom {
     }
 }
 
+/// A poll sent as a [`Custom`] message tagged with [`Poll::TAG`]. Clients not aware of the tag
+/// display the numbered options as text. Use [`crate::poll::PollCollector`] to collect the votes.
+///
+/// ```ignore
+/// let poll = Poll::new("Where do we go?", ["Cinema", "Bowling", "Stay home"]);
+/// let response = bot.send_msg(chat, poll.clone()).await?;
+/// polls.track(chat, MessageId::from(&response.chat_items[0]), poll, Some(Duration::from_secs(3600)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Poll {
+    question: String,
+    options: Vec<String>,
+    #[serde(default)]
+    multiple: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    reactions: Vec<String>,
+}
+
+impl Poll {
+    pub const TAG: &str = "poll";
+
+    pub fn new<I>(question: impl Into<String>, options: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Self {
+            question: question.into(),
+            options: options.into_iter().map(Into::into).collect(),
+            multiple: false,
+            reactions: Vec::new(),
+        }
+    }
+
+    /// A "Yes"/"No" poll that can also be answered with 👍/👎 reactions
+    pub fn yes_no(question: impl Into<String>) -> Self {
+        Self::new(question, ["Yes", "No"]).with_reactions(["👍", "👎"])
+    }
+
+    /// Allow voting for several options, e.g. by replying `1, 3`
+    pub fn allow_multiple(mut self, multiple: bool) -> Self {
+        self.multiple = multiple;
+        self
+    }
+
+    /// Emoji reactions voting for the options in order. Reactions are limited to the emojis
+    /// supported by SimpleX clients
+    pub fn with_reactions<I>(mut self, reactions: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.reactions = reactions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Parse the poll from the received message content
+    pub fn from_content(content: &MsgContent) -> Option<Self> {
+        match content {
+            MsgContent::Unknown { tag, json, .. } if tag == Self::TAG => {
+                serde_json::from_value(json.clone()).ok()
+            }
+            _ => None,
+        }
+    }
+
+    pub fn question(&self) -> &str {
+        &self.question
+    }
+
+    pub fn options(&self) -> &[String] {
+        &self.options
+    }
+
+    pub fn is_multiple(&self) -> bool {
+        self.multiple
+    }
+
+    /// The option index the reaction votes for
+    pub fn reaction_option(&self, emoji: &str) -> Option<usize> {
+        self.reactions
+            .iter()
+            .take(self.options.len())
+            .position(|reaction| reaction == emoji)
+    }
+
+    /// Parse the option indices from a text reply like `2` or `1, 3`. Returns `None` if the text
+    /// isn't a valid vote for this poll
+    pub fn parse_reply(&self, text: &str) -> Option<Vec<usize>> {
+        let mut choices = Vec::new();
+
+        for number in text
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+        {
+            let number: usize = number.trim_end_matches('.').parse().ok()?;
+            if number == 0 || number > self.options.len() {
+                return None;
+            }
+
+            if !choices.contains(&(number - 1)) {
+                choices.push(number - 1);
+            }
+        }
+
+        if choices.is_empty() || (!self.multiple && choices.len() > 1) {
+            return None;
+        }
+
+        Some(choices)
+    }
+}
+
+/// The text fallback. The question and the options are escaped
+impl std::fmt::Display for Poll {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut text = RichText::new().bold(&self.question).newline();
+
+        for (i, option) in self.options.iter().enumerate() {
+            text = text.push(format!("{}. ", i + 1));
+
+            if let Some(reaction) = self.reactions.get(i) {
+                text = text.push(format!("{reaction} "));
+            }
+
+            text = text.push(option).newline();
+        }
+
+        let hint = if self.multiple {
+            "Reply with the option numbers, e.g. 1, 2"
+        } else if self.reactions.is_empty() {
+            "Reply with the option number"
+        } else {
+            "Reply with the option number or react"
+        };
+
+        write!(f, "{}", text.newline().italic(hint))
+    }
+}
+
+impl MessageLike for Poll {
+    type Kind = RichKind;
+    fn into_builder_parts(self) -> (ComposedMessage, RichKind) {
+        let text = self.to_string();
+        Custom::new(Self::TAG, &self)
+            .with_text(text)
+            .into_builder_parts()
+    }
+}
+
 /// An awaitable message builder(await sends the message)
 pub struct MessageBuilder<'a, C: 'a + ?Sized, M = TextKind> {
     pub(crate) client: &'a C,
@@ -894,6 +1306,8 @@ pub struct MessageBuilder<'a, C: 'a + ?Sized, M = TextKind> {
 }
 
 impl<'a, C, M> MessageBuilder<'a, C, M> {
+    /// Send the message with the live flag set. Use [`MessageBuilder::live`] on text messages to
+    /// get a [`LiveMessage`] handle updating the sent message.
     pub fn live_message(mut self) -> Self {
         self.live = true;
         self
@@ -919,6 +1333,37 @@ impl<'a, C, M> MessageBuilder<'a, C, M> {
         self
     }
 
+    /// Append the mention markup to the message text and mention the member. Calling
+    /// [`Self::set_text`] afterwards overwrites the markup.
+    pub fn mention(mut self, mention: impl Into<Mention>) -> Self {
+        let mention = mention.into();
+
+        if let Some(text) = self.msg.msg_content.text_part_mut() {
+            if !text.is_empty() && !text.ends_with(char::is_whitespace) {
+                text.push(' ');
+            }
+
+            text.push_str(&mention.to_string());
+        }
+
+        self.with_mentions([mention])
+    }
+
+    /// Mention members whose markup is already present in the message text
+    pub fn with_mentions<I>(mut self, mentions: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Mention>,
+    {
+        self.msg
+            .mentions
+            .extend(mentions.into_iter().map(|mention| {
+                let mention = mention.into();
+                (mention.name, mention.member_id.raw())
+            }));
+        self
+    }
+
     /// A syntactic sugar to avoid double awaits(`.await.await` -> `.await.deliver().await`) in
     /// certain use-cases
     pub fn deliver(self) -> <Self as IntoFuture>::IntoFuture
@@ -927,9 +1372,72 @@ impl<'a, C, M> MessageBuilder<'a, C, M> {
     {
         self.into_future()
     }
+
+    /// Deliver the message later with the `scheduler` instead of sending it now. The preview is
+    /// resolved immediately, live messages are delivered as regular ones. See [`crate::scheduler`]
+    pub async fn schedule(
+        self,
+        scheduler: &Scheduler,
+        schedule: Schedule,
+    ) -> std::io::Result<JobHandle>
+    where
+        M: sealed::MessageKind,
+    {
+        let mut msg = self.msg;
+        if let Some(preview) = self.kind.into_preview() {
+            msg.msg_content.set_preview(preview.resolve().await);
+        }
+
+        scheduler
+            .add(self.chat_id, schedule, self.sign, self.ttl, msg)
+            .await
+    }
 }
 
 impl<'a, C> MessageBuilder<'a, C, TextKind> {
+    /// Send a live message which can be updated as its text gets generated, see [`LiveMessage`]
+    pub fn live(self) -> MessageBuilder<'a, C, LiveKind> {
+        MessageBuilder {
+            client: self.client,
+            chat_id: self.chat_id,
+            live: true,
+            sign: self.sign,
+            ttl: self.ttl,
+            msg: self.msg,
+            kind: LiveKind,
+        }
+    }
+
+    /// Send the text exceeding [`MAX_TEXT_SIZE`] as several sequential messages instead of
+    /// failing. See [`Self::split_oversized_at`]
+    pub fn split_oversized(self) -> MessageBuilder<'a, C, SplitKind> {
+        self.split_oversized_at(MAX_TEXT_SIZE)
+    }
+
+    /// Send the text exceeding `max_size` bytes as several sequential messages. The text is split
+    /// on paragraph and line boundaries when possible and never inside formatting spans or code
+    /// blocks, see [`split_text`]. The reply quote is kept on the first message only.
+    ///
+    /// # Panics
+    ///
+    /// If `max_size` is less than [`MIN_SPLIT_SIZE`]
+    pub fn split_oversized_at(self, max_size: usize) -> MessageBuilder<'a, C, SplitKind> {
+        assert!(
+            max_size >= MIN_SPLIT_SIZE,
+            "max_size must be at least {MIN_SPLIT_SIZE}"
+        );
+
+        MessageBuilder {
+            client: self.client,
+            chat_id: self.chat_id,
+            live: self.live,
+            sign: self.sign,
+            ttl: self.ttl,
+            msg: self.msg,
+            kind: SplitKind(max_size),
+        }
+    }
+
     pub fn with_image(self, img: Image) -> MessageBuilder<'a, C, PreviewableKind> {
         let (msg, kind) = fuse_messages(self.msg, img);
 
@@ -958,6 +1466,20 @@ impl<'a, C> MessageBuilder<'a, C, TextKind> {
         }
     }
 
+    pub fn with_voice(self, voice: Voice) -> MessageBuilder<'a, C, RichKind> {
+        let (msg, kind) = fuse_messages(self.msg, voice);
+
+        MessageBuilder {
+            client: self.client,
+            chat_id: self.chat_id,
+            live: self.live,
+            sign: self.sign,
+            ttl: self.ttl,
+            msg,
+            kind,
+        }
+    }
+
     pub fn with_link(self, link: Link) -> MessageBuilder<'a, C, PreviewableKind> {
         let (msg, kind) = fuse_messages(self.msg, link);
 
@@ -1040,7 +1562,15 @@ impl<'a, C> MessageBuilder<'a, C, PreviewableKind> {
 mod sealed {
     pub trait SimplySendable {}
 
-    pub trait MessageKind {}
+    pub trait MessageKind {
+        /// A preview to resolve before sending
+        fn into_preview(self) -> Option<super::ImagePreview>
+        where
+            Self: Sized,
+        {
+            None
+        }
+    }
 }
 
 impl<'a, C, M> IntoFuture for MessageBuilder<'a, C, M>
@@ -1090,6 +1620,246 @@ where
     }
 }
 
+impl<'a, C> IntoFuture for MessageBuilder<'a, C, LiveKind>
+where
+    C: 'static + ClientApi,
+    C::Error: 'static + Send,
+{
+    type Output = Result<LiveMessage<'a, C>, C::Error>;
+    type IntoFuture = Pin<Box<dyn 'a + Send + Future<Output = Self::Output>>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            let text = self
+                .msg
+                .msg_content
+                .text_part()
+                .unwrap_or_default()
+                .to_owned();
+            let mentions = self.msg.mentions.clone();
+
+            let response = self
+                .client
+                .api_send_messages(ApiSendMessages {
+                    send_ref: self.chat_id.into_chat_ref(),
+                    live_message: true,
+                    sign_messages: self.sign,
+                    ttl: self.ttl.map(preferences::timed_messages::ttl_to_secs),
+                    composed_messages: vec![self.msg],
+                })
+                .await?;
+
+            let Some(item) = response.chat_items.first() else {
+                return Err(BadResponseError::Undocumented(serde_json::json!({
+                    "type": "newChatItems",
+                    "error": "the sent live message is missing in the response",
+                }))
+                .into());
+            };
+
+            Ok(LiveMessage {
+                client: self.client,
+                chat_id: self.chat_id,
+                message_id: MessageId::from(item),
+                text,
+                mentions,
+                pending: false,
+                deleted: false,
+                throttle: LiveMessage::<C>::DEFAULT_THROTTLE,
+                last_update: Instant::now(),
+            })
+        })
+    }
+}
+
+impl<'a, C> IntoFuture for MessageBuilder<'a, C, SplitKind>
+where
+    C: 'static + ClientApi,
+    C::Error: 'static + Send,
+{
+    type Output = Result<Arc<NewChatItemsResponse>, C::Error>;
+    type IntoFuture = Pin<Box<dyn 'a + Send + Future<Output = Self::Output>>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.client.api_send_messages(ApiSendMessages {
+            send_ref: self.chat_id.into_chat_ref(),
+            live_message: false,
+            sign_messages: self.sign,
+            ttl: self.ttl.map(preferences::timed_messages::ttl_to_secs),
+            composed_messages: split_message(self.msg, self.kind.0),
+        }))
+    }
+}
+
+/// A live message updated as its text gets generated, e.g. from the LLM output:
+///
+/// ```ignore
+/// let mut live = bot.send_msg(chat, "").live().await?;
+///
+/// while let Some(token) = tokens.next().await {
+///     live.append(&token).await?;
+/// }
+///
+/// live.finish().await?;
+/// ```
+///
+/// Updates are throttled: [`Self::append`] and [`Self::set_text`] send the accumulated text at most
+/// once per [`Self::with_throttle`] interval, the changes made in between are coalesced into the
+/// next update. Use [`Self::flush`] to send the pending changes immediately.
+///
+/// Dropping the handle without calling [`Self::finish`] leaves the message in the live state with
+/// the last sent text until the live message times out in the clients.
+pub struct LiveMessage<'a, C: 'a + ?Sized> {
+    client: &'a C,
+    chat_id: ChatId,
+    message_id: MessageId,
+    text: String,
+    mentions: BTreeMap<String, i64>,
+    pending: bool,
+    deleted: bool,
+    throttle: Duration,
+    last_update: Instant,
+}
+
+impl<'a, C: 'a + ?Sized + ClientApi> LiveMessage<'a, C> {
+    pub const DEFAULT_THROTTLE: Duration = Duration::from_secs(1);
+
+    /// The minimal interval between updates. Default: [`Self::DEFAULT_THROTTLE`]
+    pub fn with_throttle(mut self, throttle: Duration) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    pub fn chat_id(&self) -> ChatId {
+        self.chat_id
+    }
+
+    pub fn message_id(&self) -> MessageId {
+        self.message_id
+    }
+
+    /// The current text including the changes not sent yet
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Whether the message was deleted, all updates fail with [`LiveError::Deleted`] afterwards
+    pub fn is_deleted(&self) -> bool {
+        self.deleted
+    }
+
+    pub async fn append(&mut self, text: &str) -> Result<(), LiveError<C::Error>> {
+        self.text.push_str(text);
+        self.pending = true;
+        self.update_throttled().await
+    }
+
+    pub async fn set_text(&mut self, text: impl Into<String>) -> Result<(), LiveError<C::Error>> {
+        self.text = text.into();
+        self.pending = true;
+        self.update_throttled().await
+    }
+
+    /// Send the pending changes ignoring the throttle
+    pub async fn flush(&mut self) -> Result<(), LiveError<C::Error>> {
+        if self.pending {
+            self.update(true).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Send the final text and end the live message
+    pub async fn finish(mut self) -> Result<ApiUpdateChatItemResponse, LiveError<C::Error>> {
+        self.update(false).await
+    }
+
+    async fn update_throttled(&mut self) -> Result<(), LiveError<C::Error>> {
+        if self.last_update.elapsed() >= self.throttle {
+            self.flush().await
+        } else if self.deleted {
+            Err(LiveError::Deleted)
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn update(
+        &mut self,
+        live: bool,
+    ) -> Result<ApiUpdateChatItemResponse, LiveError<C::Error>> {
+        if self.deleted {
+            return Err(LiveError::Deleted);
+        }
+
+        let result = self
+            .client
+            .api_update_chat_item(ApiUpdateChatItem {
+                chat_ref: self.chat_id.into_chat_ref(),
+                chat_item_id: self.message_id.raw(),
+                live_message: live,
+                updated_message: UpdatedMessage {
+                    msg_content: MsgContent::make_text(self.text.clone()),
+                    mentions: self.mentions.clone(),
+                    undocumented: Default::default(),
+                },
+            })
+            .await;
+
+        self.last_update = Instant::now();
+
+        match result {
+            Ok(response) => {
+                self.pending = false;
+                Ok(response)
+            }
+            Err(e) if is_deleted_item_error(&e) => {
+                self.deleted = true;
+                Err(LiveError::Deleted)
+            }
+            Err(e) => Err(LiveError::Api(e)),
+        }
+    }
+}
+
+/// Updates of the deleted items fail either because the item is gone or because it's marked as
+/// deleted
+fn is_deleted_item_error<E: ClientApiError>(e: &E) -> bool {
+    e.bad_response()
+        .and_then(|e| e.chat_error())
+        .is_some_and(|e| {
+            e.error().is_some_and(|e| e.is_invalid_chat_item_update())
+                || e.error_store()
+                    .is_some_and(|e| e.chat_item_not_found().is_some())
+        })
+}
+
+#[derive(Debug)]
+pub enum LiveError<E> {
+    /// The message was deleted, e.g. by the peer or a moderator
+    Deleted,
+    /// The API call failed
+    Api(E),
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for LiveError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Deleted => write!(f, "live message was deleted"),
+            Self::Api(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl<E: 'static + std::error::Error> std::error::Error for LiveError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Deleted => None,
+            Self::Api(e) => Some(e),
+        }
+    }
+}
+
 pub struct MulticastBuilder<'a, I, C: 'a + ?Sized, M = TextKind> {
     pub(crate) client: &'a C,
     pub(crate) chat_ids: I,
@@ -1126,6 +1896,28 @@ impl<'a, I, C, M> MulticastBuilder<'a, I, C, M> {
 }
 
 impl<'a, I, C> MulticastBuilder<'a, I, C, TextKind> {
+    /// See [`MessageBuilder::split_oversized`]
+    pub fn split_oversized(self) -> MulticastBuilder<'a, I, C, SplitKind> {
+        self.split_oversized_at(MAX_TEXT_SIZE)
+    }
+
+    /// See [`MessageBuilder::split_oversized_at`]
+    pub fn split_oversized_at(self, max_size: usize) -> MulticastBuilder<'a, I, C, SplitKind> {
+        assert!(
+            max_size >= MIN_SPLIT_SIZE,
+            "max_size must be at least {MIN_SPLIT_SIZE}"
+        );
+
+        MulticastBuilder {
+            client: self.client,
+            chat_ids: self.chat_ids,
+            ttl: self.ttl,
+            sign: self.sign,
+            msg: self.msg,
+            kind: SplitKind(max_size),
+        }
+    }
+
     pub fn with_image(self, img: Image) -> MulticastBuilder<'a, I, C, PreviewableKind> {
         let (msg, kind) = fuse_messages(self.msg, img);
 
@@ -1152,6 +1944,19 @@ impl<'a, I, C> MulticastBuilder<'a, I, C, TextKind> {
         }
     }
 
+    pub fn with_voice(self, voice: Voice) -> MulticastBuilder<'a, I, C, RichKind> {
+        let (msg, kind) = fuse_messages(self.msg, voice);
+
+        MulticastBuilder {
+            client: self.client,
+            chat_ids: self.chat_ids,
+            ttl: self.ttl,
+            sign: self.sign,
+            msg,
+            kind,
+        }
+    }
+
     pub fn with_link(self, link: Link) -> MulticastBuilder<'a, I, C, PreviewableKind> {
         let (msg, kind) = fuse_messages(self.msg, link);
 
@@ -1308,6 +2113,95 @@ where
     }
 }
 
+impl<'a, I, C> IntoFuture for MulticastBuilder<'a, I, C, SplitKind>
+where
+    I: IntoIterator<Item = ChatId>,
+    C: 'static + ClientApi,
+    C::Error: 'static + Send,
+{
+    type Output = Vec<Result<Arc<NewChatItemsResponse>, C::Error>>;
+    type IntoFuture = Pin<Box<dyn 'a + Send + Future<Output = Self::Output>>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        let Self {
+            client,
+            chat_ids,
+            ttl,
+            sign,
+            msg,
+            kind,
+        } = self;
+
+        let messages = split_message(msg, kind.0);
+        let iter = chat_ids.into_iter().map(move |id| {
+            let messages = messages.clone();
+            async move {
+                let command = ApiSendMessages {
+                    send_ref: id.into_chat_ref(),
+                    live_message: false,
+                    sign_messages: sign,
+                    ttl: ttl.map(preferences::timed_messages::ttl_to_secs),
+                    composed_messages: messages,
+                };
+
+                client.api_send_messages(command).await
+            }
+        });
+
+        Box::pin(futures::future::join_all(iter))
+    }
+}
+
+/// Splits the oversized text message into several ones. The reply quote stays on the first part,
+/// each part mentions only the members whose markup it contains.
+fn split_message(msg: ComposedMessage, max_size: usize) -> Vec<ComposedMessage> {
+    let text = match &msg.msg_content {
+        MsgContent::Text { text, .. } if msg.msg_content.est_size() > max_size => text,
+        _ => return vec![msg],
+    };
+
+    split_text(text, max_size)
+        .into_iter()
+        .enumerate()
+        .map(|(i, part)| {
+            let mentions = msg
+                .mentions
+                .iter()
+                .filter(|(name, id)| {
+                    let mention = Mention::new(name.as_str(), MemberId::from_raw(**id));
+                    contains_mention(&part, &mention.to_string())
+                })
+                .map(|(name, id)| (name.clone(), *id))
+                .collect();
+
+            let mut msg_content = msg.msg_content.clone();
+            msg_content.set_text_part(part);
+
+            ComposedMessage {
+                file_source: None,
+                quoted_item_id: msg.quoted_item_id.filter(|_| i == 0),
+                msg_content,
+                mentions,
+                undocumented: msg.undocumented.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Whether `text` contains the mention `markup` as a whole word, e.g. `@alice` doesn't match
+/// `@alice_bot`. Like in SimpleX, a mention name ends at a whitespace and trailing punctuation
+/// doesn't belong to it.
+fn contains_mention(text: &str, markup: &str) -> bool {
+    text.match_indices(markup).any(|(i, _)| {
+        let before = text[..i].chars().next_back();
+        let after = &text[i + markup.len()..];
+        let word_end = after.split(char::is_whitespace).next().unwrap_or_default();
+
+        before.is_none_or(|c| !c.is_alphanumeric())
+            && (markup.ends_with('\'') || word_end.chars().all(|c| c.is_ascii_punctuation()))
+    })
+}
+
 fn fuse_messages<M: MessageLike>(old: ComposedMessage, new: M) -> (ComposedMessage, M::Kind) {
     let (mut new, kind) = new.into_builder_parts();
     new.quoted_item_id = old.quoted_item_id;
@@ -1319,6 +2213,7 @@ fn fuse_messages<M: MessageLike>(old: ComposedMessage, new: M) -> (ComposedMessa
                 .map(|s| s.to_owned())
                 .unwrap_or_default(),
         );
+        new.mentions = old.mentions;
     }
 
     (new, kind)
@@ -1411,3 +2306,187 @@ impl MsgContentExt for MsgContent {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder<M: MessageLike>(msg: M) -> MessageBuilder<'static, (), M::Kind> {
+        let (msg, kind) = msg.into_builder_parts();
+        MessageBuilder {
+            client: &(),
+            chat_id: ChatId::Group {
+                id: crate::id::GroupId::from_raw(1),
+                scope: None,
+            },
+            live: false,
+            sign: false,
+            ttl: None,
+            msg,
+            kind,
+        }
+    }
+
+    #[test]
+    fn mentions() {
+        let alice = Mention::new("alice", MemberId::from_raw(2));
+        let bob = Mention::new("Bob Smith", MemberId::from_raw(3));
+
+        let msg = builder("Welcome,")
+            .mention(alice.clone())
+            .mention(bob.clone())
+            .msg;
+        assert_eq!(
+            msg.msg_content.text_part(),
+            Some("Welcome, @alice @'Bob Smith'")
+        );
+        assert_eq!(
+            msg.mentions.into_iter().collect::<Vec<_>>(),
+            [("Bob Smith".to_owned(), 3), ("alice".to_owned(), 2)]
+        );
+
+        let msg = builder(format!("{alice}, see the file"))
+            .with_mentions([alice])
+            .attach(CryptoFile {
+                file_path: "doc.pdf".to_owned(),
+                crypto_args: None,
+                undocumented: Default::default(),
+            })
+            .msg;
+        assert_eq!(msg.msg_content.text_part(), Some("@alice, see the file"));
+        assert_eq!(msg.mentions.get("alice"), Some(&2));
+    }
+
+    #[test]
+    fn voice_durations() {
+        let duration = |millis| {
+            let (msg, _) = Voice::new("a.ogg", Duration::from_millis(millis)).into_builder_parts();
+            let MsgContent::Voice { duration, .. } = msg.msg_content else {
+                unreachable!()
+            };
+            duration
+        };
+
+        assert_eq!(duration(0), 0);
+        assert_eq!(duration(900), 1);
+        assert_eq!(duration(2000), 2);
+        assert_eq!(duration(2001), 3);
+    }
+
+    #[test]
+    fn split_oversized() {
+        let alice = Mention::new("alice", MemberId::from_raw(2));
+        let para = "word ".repeat(12);
+        let code = format!("```\n{}```", "let x = 1;\n".repeat(8));
+        let text = format!("{para}\n\n{alice} {para}*bold span* end\n{code}");
+
+        let parts = split_text(&text, MIN_SPLIT_SIZE);
+        assert!(parts.iter().all(|part| part.len() <= MIN_SPLIT_SIZE));
+        assert_eq!(parts[0], para.trim_end());
+        assert!(parts.iter().any(|part| part.contains("*bold span*")));
+        assert!(parts.iter().all(|part| part.matches('*').count() % 2 == 0));
+        assert!(
+            parts
+                .iter()
+                .all(|part| part.matches("```").count() % 2 == 0)
+        );
+
+        let msg = builder(text)
+            .with_mentions([alice])
+            .reply_to(MessageId::from_raw(7))
+            .split_oversized_at(MIN_SPLIT_SIZE)
+            .msg;
+        let messages = split_message(msg, MIN_SPLIT_SIZE);
+        assert_eq!(messages.len(), parts.len());
+        assert_eq!(messages[0].quoted_item_id, Some(7));
+        assert!(messages[1..].iter().all(|m| m.quoted_item_id.is_none()));
+        assert!(messages[0].mentions.is_empty());
+        assert_eq!(messages[1].mentions.get("alice"), Some(&2));
+
+        assert!(contains_mention("hi @alice, see", "@alice"));
+        assert!(contains_mention("hi @alice_bot and @alice.", "@alice"));
+        assert!(!contains_mention("hi @alicex and @alice_bot", "@alice"));
+        assert!(!contains_mention("mail@alice", "@alice"));
+        assert!(contains_mention("hi @'Bob Smith'!", "@'Bob Smith'"));
+
+        let short = builder("short").split_oversized().msg;
+        assert_eq!(split_message(short, MAX_TEXT_SIZE).len(), 1);
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn live_message_updates() {
+        use crate::{id::ContactId, mock, test_utils};
+
+        let (client, _, _) = mock::init();
+        let live_message = |client| LiveMessage {
+            client,
+            chat_id: ContactId::from_raw(1).into(),
+            message_id: MessageId::from_raw(5),
+            text: String::new(),
+            mentions: Default::default(),
+            pending: false,
+            deleted: false,
+            throttle: Duration::from_secs(3600),
+            last_update: Instant::now(),
+        };
+
+        client
+            .expect::<ApiUpdateChatItem>()
+            .times(2)
+            .respond_json(serde_json::json!({
+                "type": "chatItemUpdated",
+                "user": test_utils::user(),
+                "chatItem": {
+                    "chatInfo": test_utils::local_chat(),
+                    "chatItem": test_utils::chat_item(5, "Hello, world!", false, serde_json::json!([])),
+                },
+            }));
+
+        let mut live = live_message(&client);
+
+        // Coalesced into a single update
+        live.append("Hello").await.unwrap();
+        live.append(", world").await.unwrap();
+        assert!(client.sent().is_empty());
+
+        live.flush().await.unwrap();
+        let sent = client.sent_of::<ApiUpdateChatItem>();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].command.starts_with("/_update item @1 5 live=on"));
+        assert!(sent[0].command.contains("Hello, world"));
+
+        // Nothing pending
+        live.flush().await.unwrap();
+        assert_eq!(client.sent().len(), 1);
+
+        // Throttled until the interval passes
+        live.append("!").await.unwrap();
+        assert_eq!(client.sent().len(), 1);
+        assert_eq!(live.text(), "Hello, world!");
+
+        let response = live.finish().await.unwrap();
+        assert!(response.chat_item_updated().is_some());
+        let sent = client.sent_of::<ApiUpdateChatItem>();
+        assert_eq!(sent.len(), 2);
+        assert!(sent[1].command.starts_with("/_update item @1 5 json"));
+        assert!(sent[1].command.contains("Hello, world!"));
+        client.assert_satisfied();
+
+        client.expect::<ApiUpdateChatItem>().respond_error(
+            serde_json::from_value(serde_json::json!({
+                "type": "errorStore",
+                "storeError": { "type": "chatItemNotFound", "itemId": 5 },
+            }))
+            .unwrap(),
+        );
+
+        let mut live = live_message(&client);
+        live.set_text("Bye").await.unwrap();
+        assert!(matches!(live.flush().await, Err(LiveError::Deleted)));
+        assert!(live.is_deleted());
+        assert!(matches!(live.append("!").await, Err(LiveError::Deleted)));
+        assert!(matches!(live.finish().await, Err(LiveError::Deleted)));
+        assert_eq!(client.sent().len(), 3);
+    }
+}
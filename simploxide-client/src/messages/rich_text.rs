@@ -0,0 +1,593 @@
+//! Composable formatted text.
+//!
+//! Unlike [`Text`](super::Text), [`RichText`] can nest styles, mix them within a line and escape
+//! untrusted input:
+//!
+//! ```ignore
+//! let text = RichText::new()
+//!     .push("Hi ")
+//!     .bold(user_name)
+//!     .push(", your code is ")
+//!     .monospace(code)
+//!     .newline()
+//!     .italic(RichText::new().push("Expires ").red("in 5 minutes"));
+//!
+//! bot.send_msg(chat, text).await?;
+//! ```
+//!
+//! All text passed into the builder is escaped: markup characters that would start a span are
+//! prefixed with an invisible word joiner (U+2060) and the ones that would end a styled span are
+//! moved out of it, so the text is shown exactly as given. SimpleX can't style the rest of the word
+//! after such a marker, e.g. only `a` is bold in `.bold("a*b")`. Use [`RichText::push_markdown`] to
+//! insert trusted markup as is.
+//!
+//! Styles apply per line, the multi-line styled text is wrapped into the markers on every
+//! non-empty line. Spans of the same style nested into each other are merged. Note that markers
+//! open and close only on word boundaries, so a styled span glued to a word, e.g.
+//! `.push("re").bold("do")`, is rendered as plain text.
+
+use simploxide_api_types::{Color, ComposedMessage};
+
+use std::collections::BTreeMap;
+
+use super::{
+    Mention, MessageLike, TextKind,
+    markdown::{Markdown, Span, is_boundary},
+};
+use crate::id::MemberId;
+
+/// Inserted to break the markup in the escaped text. Renders as nothing
+const ESCAPE: char = '\u{2060}';
+
+/// Characters opening the markup spans
+const MARKERS: [char; 6] = ['*', '_', '~', '#', '`', '@'];
+
+const FENCE: &str = "```";
+
+/// A formatted text builder, see the [module docs](self)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RichText {
+    spans: Vec<Span>,
+    mentions: BTreeMap<String, MemberId>,
+}
+
+impl RichText {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append text or another [`RichText`]
+    pub fn push(mut self, text: impl Into<RichText>) -> Self {
+        let text = text.into();
+        self.spans.extend(text.spans);
+        self.mentions.extend(text.mentions);
+        self
+    }
+
+    /// Append trusted markup without escaping
+    pub fn push_markdown(self, markup: &str) -> Self {
+        self.push(Markdown::parse(markup))
+    }
+
+    pub fn newline(self) -> Self {
+        self.push("\n")
+    }
+
+    pub fn bold(self, text: impl Into<RichText>) -> Self {
+        self.wrap(text, Span::Bold)
+    }
+
+    pub fn italic(self, text: impl Into<RichText>) -> Self {
+        self.wrap(text, Span::Italic)
+    }
+
+    pub fn strike(self, text: impl Into<RichText>) -> Self {
+        self.wrap(text, Span::Strike)
+    }
+
+    pub fn secret(self, text: impl Into<RichText>) -> Self {
+        self.wrap(text, Span::Secret)
+    }
+
+    pub fn colored(self, color: Color, text: impl Into<RichText>) -> Self {
+        self.wrap(text, |spans| Span::Colored(color, spans))
+    }
+
+    pub fn red(self, text: impl Into<RichText>) -> Self {
+        self.colored(Color::Red, text)
+    }
+
+    pub fn green(self, text: impl Into<RichText>) -> Self {
+        self.colored(Color::Green, text)
+    }
+
+    pub fn blue(self, text: impl Into<RichText>) -> Self {
+        self.colored(Color::Blue, text)
+    }
+
+    pub fn yellow(self, text: impl Into<RichText>) -> Self {
+        self.colored(Color::Yellow, text)
+    }
+
+    pub fn cyan(self, text: impl Into<RichText>) -> Self {
+        self.colored(Color::Cyan, text)
+    }
+
+    pub fn magenta(self, text: impl Into<RichText>) -> Self {
+        self.colored(Color::Magenta, text)
+    }
+
+    /// Inline code, can't contain other styles
+    pub fn monospace(mut self, code: impl Into<String>) -> Self {
+        self.spans.push(Span::Monospace(code.into()));
+        self
+    }
+
+    /// A fenced code block on its own lines. The `lang` may be empty
+    pub fn code_block(mut self, lang: &str, code: impl Into<String>) -> Self {
+        self.spans.push(Span::CodeBlock {
+            lang: (!lang.is_empty()).then(|| lang.to_owned()),
+            code: code.into(),
+        });
+        self
+    }
+
+    pub fn link(mut self, url: impl Into<String>) -> Self {
+        self.spans.push(Span::Link(url.into()));
+        self
+    }
+
+    /// Append the mention and register it in the message, see [`Mention`]
+    pub fn mention(self, mention: impl Into<Mention>) -> Self {
+        self.push(mention.into())
+    }
+
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    pub fn mentions(&self) -> &BTreeMap<String, MemberId> {
+        &self.mentions
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// The text with all formatting removed
+    pub fn to_plain_text(&self) -> String {
+        Markdown::from(self.spans.clone()).to_plain_text()
+    }
+
+    fn wrap(mut self, text: impl Into<RichText>, style: impl FnOnce(Vec<Span>) -> Span) -> Self {
+        let text = text.into();
+        self.mentions.extend(text.mentions);
+        self.spans.push(style(text.spans));
+        self
+    }
+}
+
+impl From<&str> for RichText {
+    fn from(text: &str) -> Self {
+        Self::from(text.to_owned())
+    }
+}
+
+impl From<&String> for RichText {
+    fn from(text: &String) -> Self {
+        Self::from(text.as_str())
+    }
+}
+
+impl From<String> for RichText {
+    fn from(text: String) -> Self {
+        Self {
+            spans: vec![Span::Plain(text)],
+            mentions: BTreeMap::new(),
+        }
+    }
+}
+
+impl From<Mention> for RichText {
+    fn from(mention: Mention) -> Self {
+        Self {
+            spans: vec![Span::Mention(mention.name().to_owned())],
+            mentions: BTreeMap::from([(mention.name().to_owned(), mention.member_id())]),
+        }
+    }
+}
+
+/// Mentions in the parsed text aren't registered, their member IDs are unknown
+impl From<Markdown> for RichText {
+    fn from(markdown: Markdown) -> Self {
+        Self {
+            spans: markdown.into_spans(),
+            mentions: BTreeMap::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for RichText {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = String::new();
+        render(&self.spans, &mut Vec::new(), &mut out);
+        f.write_str(&out)
+    }
+}
+
+impl MessageLike for RichText {
+    type Kind = TextKind;
+
+    fn into_builder_parts(self) -> (ComposedMessage, Self::Kind) {
+        let (mut msg, kind) = self.to_string().into_builder_parts();
+
+        msg.mentions.extend(
+            self.mentions
+                .into_iter()
+                .map(|(name, member_id)| (name, member_id.raw())),
+        );
+
+        (msg, kind)
+    }
+}
+
+/// `closing` holds the closing markers of the enclosing spans
+fn render(spans: &[Span], closing: &mut Vec<char>, out: &mut String) {
+    for (i, span) in spans.iter().enumerate() {
+        match span {
+            Span::Plain(text) => escape(text, closing, out),
+            Span::Bold(inner) => styled("*", '*', inner, closing, out),
+            Span::Italic(inner) => styled("_", '_', inner, closing, out),
+            Span::Strike(inner) => styled("~", '~', inner, closing, out),
+            Span::Secret(inner) => styled("#", '#', inner, closing, out),
+            Span::Colored(color, inner) => match color_markup(*color) {
+                Some(markup) => styled(markup, '!', inner, closing, out),
+                None => render(inner, closing, out),
+            },
+            Span::Monospace(code) => wrap_lines("`", '`', code, out),
+            Span::CodeBlock { lang, code } => {
+                if !out.is_empty() && !out.ends_with('\n') {
+                    out.push('\n');
+                }
+
+                out.push_str(FENCE);
+                out.push_str(lang.as_deref().unwrap_or_default());
+                out.push('\n');
+
+                for line in code.lines() {
+                    if line.trim_start().starts_with(FENCE) {
+                        out.push(ESCAPE);
+                    }
+
+                    out.push_str(line);
+                    out.push('\n');
+                }
+
+                out.push_str(FENCE);
+
+                let followed_by_newline = match spans.get(i + 1) {
+                    Some(Span::Plain(text)) => text.starts_with('\n'),
+                    Some(_) => false,
+                    None => true,
+                };
+
+                if !followed_by_newline {
+                    out.push('\n');
+                }
+            }
+            Span::Link(url) => out.push_str(url),
+            Span::Mention(name) => {
+                if name.contains(char::is_whitespace) {
+                    out.push_str("@'");
+                    out.push_str(name);
+                    out.push('\'');
+                } else {
+                    out.push('@');
+                    out.push_str(name);
+                }
+            }
+        }
+    }
+}
+
+fn styled(open: &str, close: char, inner: &[Span], closing: &mut Vec<char>, out: &mut String) {
+    // The nested span of the same style would close the outer one
+    if closing.contains(&close) {
+        render(inner, closing, out);
+        return;
+    }
+
+    let mut text = String::new();
+
+    closing.push(close);
+    render(inner, closing, &mut text);
+    closing.pop();
+
+    wrap_lines(open, close, &text, out);
+}
+
+/// Wraps every non-empty line into the markers. SimpleX ends the span at the first `close`
+/// marker, so the literal ones are moved out of the span with a leading word joiner. The rest of
+/// the word after such a marker can't be styled and is kept plain.
+fn wrap_lines(open: &str, close: char, text: &str, out: &mut String) {
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+
+        let mut rest = line;
+
+        while let Some(pos) = rest.find(close) {
+            wrap_segment(open, close, &rest[..pos], out);
+            out.push(ESCAPE);
+            out.push(close);
+
+            let after = &rest[pos + close.len_utf8()..];
+            let word_end = after.find(' ').unwrap_or(after.len());
+
+            for c in after[..word_end].chars() {
+                if c == close {
+                    out.push(ESCAPE);
+                }
+
+                out.push(c);
+            }
+
+            rest = &after[word_end..];
+        }
+
+        wrap_segment(open, close, rest, out);
+    }
+}
+
+/// Wraps the text without the surrounding whitespace, the markers don't open or close next to it
+fn wrap_segment(open: &str, close: char, text: &str, out: &mut String) {
+    let trimmed = text.trim();
+
+    if trimmed.is_empty() {
+        out.push_str(text);
+        return;
+    }
+
+    let start = text.len() - text.trim_start().len();
+
+    out.push_str(&text[..start]);
+    out.push_str(open);
+    out.push_str(trimmed);
+    out.push(close);
+    out.push_str(&text[start + trimmed.len()..]);
+}
+
+/// Breaks the markers that could open a span. The text start is assumed to be a boundary unless it
+/// follows other text in `out`. The markers closing the enclosing spans are left to [`wrap_lines`]
+fn escape(text: &str, closing: &[char], out: &mut String) {
+    let mut prev = out.chars().next_back();
+
+    for (i, c) in text.char_indices() {
+        let next = text[i + c.len_utf8()..].chars().next();
+
+        let opens = match c {
+            c if closing.contains(&c) => false,
+            // Colors are opened by `!` followed by the color name
+            '!' => next.is_some_and(char::is_alphanumeric),
+            c if MARKERS.contains(&c) => next.is_none_or(|c| !c.is_whitespace()),
+            _ => false,
+        };
+
+        if opens && is_boundary(prev) {
+            out.push(ESCAPE);
+        }
+
+        out.push(c);
+        prev = Some(c);
+    }
+}
+
+fn color_markup(color: Color) -> Option<&'static str> {
+    let markup = match color {
+        Color::Red => "!1 ",
+        Color::Green => "!2 ",
+        Color::Blue => "!3 ",
+        Color::Yellow => "!4 ",
+        Color::Cyan => "!5 ",
+        Color::Magenta => "!6 ",
+        _ => return None,
+    };
+
+    Some(markup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rich_text() {
+        let text = RichText::new()
+            .push("Hi ")
+            .bold("Alice")
+            .push(", your code is ")
+            .monospace("a` b")
+            .newline()
+            .italic(RichText::new().push("Expires ").red("in 5 min"))
+            .mention(Mention::new("Bob Smith", MemberId::from_raw(3)));
+
+        assert_eq!(
+            text.to_string(),
+            "Hi *Alice*, your code is `a`\u{2060}` `b`\n_Expires !1 in 5 min!_@'Bob Smith'"
+        );
+        assert_eq!(text.mentions()["Bob Smith"], MemberId::from_raw(3));
+
+        // Multi-line styles and merging of the nested same styles
+        let text = RichText::new().bold(RichText::new().push(" a\n\nb ").bold("c"));
+        assert_eq!(text.to_string(), " *a*\n\n*b c*");
+
+        let (msg, _) = text.into_builder_parts();
+        assert_eq!(msg.mentions.len(), 0);
+    }
+
+    /// Splits the text the way SimpleX clients do (`Simplex.Chat.Markdown`): every line is a
+    /// sequence of space runs, formatted spans and words. A span opens only at the fragment start,
+    /// ends at the first same marker and its content isn't parsed. Returns the span markers with
+    /// the shown text, the word joiners are removed and adjacent plain fragments merged.
+    fn simplex_fragments(text: &str) -> Vec<(Option<char>, String)> {
+        fn word(text: &str) -> usize {
+            text.find(' ').unwrap_or(text.len())
+        }
+
+        fn colored(text: &str) -> Option<(usize, usize)> {
+            let space = text.find(' ')?;
+            color_from_name(&text[1..space])?;
+
+            let content = &text[space + 1..];
+            let end = content.find('!')?;
+
+            if content.starts_with([' ', '!']) || content[..end].ends_with(' ') {
+                return None;
+            }
+
+            Some((space + 1, end))
+        }
+
+        fn color_from_name(name: &str) -> Option<()> {
+            [
+                "1", "2", "3", "4", "5", "6", "red", "green", "blue", "yellow", "cyan", "magenta",
+            ]
+            .contains(&name)
+            .then_some(())
+        }
+
+        let mut fragments: Vec<(Option<char>, String)> = Vec::new();
+        let mut push = |marker: Option<char>, text: &str| {
+            let text = text.replace(ESCAPE, "");
+
+            match fragments.last_mut() {
+                Some((None, last)) if marker.is_none() => last.push_str(&text),
+                _ => fragments.push((marker, text)),
+            }
+        };
+
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                push(None, "\n");
+            }
+
+            let mut rest = line;
+
+            while let Some(c) = rest.chars().next() {
+                let len = match c {
+                    ' ' => {
+                        let len = rest.len() - rest.trim_start_matches(' ').len();
+                        push(None, &rest[..len]);
+                        len
+                    }
+                    '*' | '_' | '~' | '#' | '`' => match rest[1..].find(c) {
+                        Some(end) => {
+                            let content = &rest[1..1 + end];
+
+                            if content.is_empty()
+                                || content.starts_with(char::is_whitespace)
+                                || content.ends_with(char::is_whitespace)
+                            {
+                                push(None, &rest[..end + 2]);
+                            } else {
+                                push(Some(c), content);
+                            }
+
+                            end + 2
+                        }
+                        None => {
+                            push(None, rest);
+                            rest.len()
+                        }
+                    },
+                    '!' => match colored(rest) {
+                        Some((start, end)) => {
+                            push(Some('!'), &rest[start..start + end]);
+                            start + end + 1
+                        }
+                        None => {
+                            let len = word(rest);
+                            push(None, &rest[..len]);
+                            len
+                        }
+                    },
+                    _ => {
+                        let len = word(rest);
+                        push(None, &rest[..len]);
+                        len
+                    }
+                };
+
+                rest = &rest[len..];
+            }
+        }
+
+        fragments
+    }
+
+    fn plain(text: &str) -> (Option<char>, String) {
+        (None, text.to_owned())
+    }
+
+    fn styled(marker: char, text: &str) -> (Option<char>, String) {
+        (Some(marker), text.to_owned())
+    }
+
+    #[test]
+    fn escaping() {
+        let untrusted = "*not bold* _x_ #y# ~z~ `w` !1 red! @alice ```";
+        let text = RichText::new()
+            .push(untrusted)
+            .newline()
+            .bold("a* b *c")
+            .newline()
+            .monospace("a` b")
+            .newline()
+            .red("x! y")
+            .push(" ")
+            .secret("#k#");
+
+        assert_eq!(
+            simplex_fragments(&text.to_string()),
+            [
+                plain(&format!("{untrusted}\n")),
+                styled('*', "a"),
+                plain("* "),
+                styled('*', "b"),
+                plain(" *c\n"),
+                styled('`', "a"),
+                plain("` "),
+                styled('`', "b"),
+                plain("\n"),
+                styled('!', "x"),
+                plain("! "),
+                styled('!', "y"),
+                plain(" #k#"),
+            ]
+        );
+
+        // Only `a` can be bold, SimpleX doesn't style the rest of the word after the marker
+        assert_eq!(
+            simplex_fragments(&RichText::new().bold("a*b*c d").to_string()),
+            [styled('*', "a"), plain("*b*c "), styled('*', "d")]
+        );
+
+        // The own parser shows the same text
+        assert_eq!(
+            Markdown::parse(&text.to_string())
+                .to_plain_text()
+                .replace(ESCAPE, ""),
+            format!("{untrusted}\na* b *c\na` b\nx! y #k#")
+        );
+    }
+
+    #[test]
+    fn markdown_roundtrip() {
+        let markup = "*Hi* @alice, _see_ https://simplex.chat\n!2 ok *bold*! `code`\n```rust\nfn main() {}\n```\nDone";
+        let text = RichText::from(Markdown::parse(markup));
+
+        assert_eq!(text.to_string(), markup);
+        assert_eq!(Markdown::parse(&text.to_string()), Markdown::parse(markup));
+    }
+}
@@ -0,0 +1,481 @@
+//! In-memory mock backend for testing bots offline.
+//!
+//! [`MockClient`] implements [`ClientApi`] by answering commands with programmable expectations
+//! instead of talking to a SimpleX-Chat instance. Expectations are matched by the command type
+//! from [`crate::commands`] and optional predicates over the serialized command. [`EventSender`]
+//! pushes typed events into the paired [`EventStream`], so [`Bot::init`](crate::bot::Bot::init),
+//! dispatchers and message builders can all be exercised in `cargo test`.
+//!
+//! ```ignore
+//! let (client, events, sender) = mock::init();
+//!
+//! client
+//!     .expect::<ApiSendMessages>()
+//!     .to_chat(ContactId::from_raw(5))
+//!     .respond_json(json!({
+//!         "type": "newChatItems",
+//!         "user": user_json,
+//!         "chatItems": [],
+//!     }));
+//!
+//! sender.push(new_chat_items_event);
+//! sender.close();
+//!
+//! events.into_dispatcher(client.clone())
+//!     .on(new_messages)
+//!     .dispatch()
+//!     .await?;
+//!
+//! client.assert_satisfied();
+//! ```
+//!
+//! Expectations are checked in registration order, the first one matching the command and having
+//! calls left produces the response. Commands without a matching expectation fail with
+//! [`MockError::Unexpected`].
+
+use serde::{Deserialize, Serialize};
+use simploxide_api_types::{
+    JsonObject,
+    client_api::{ExtractResponse as _, WebSocketResponseShape},
+    errors::ChatError,
+    events::{Event, EventData},
+};
+
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
+
+use crate::{BadResponseError, ClientApi, ClientApiError, CommandSyntax, id::ChatId};
+
+pub type EventStream = crate::EventStream<Event>;
+pub type ClientResult<T = ()> = ::std::result::Result<T, MockError>;
+pub type Bot = crate::bot::Bot<MockClient>;
+
+type Predicate = Box<dyn Send + Sync + Fn(&str) -> bool>;
+type ResponderFn = Box<dyn Send + Sync + Fn(&str) -> JsonObject>;
+
+/// Creates a [`MockClient`] without expectations and an [`EventStream`] fed by the returned
+/// [`EventSender`].
+pub fn init() -> (MockClient, EventStream, EventSender) {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+
+    (
+        MockClient::default(),
+        EventStream::from(receiver),
+        EventSender { sender },
+    )
+}
+
+/// A cheaply cloneable [`ClientApi`] answering commands with the registered expectations.
+#[derive(Clone, Default)]
+pub struct MockClient {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    expectations: Mutex<Vec<Expectation>>,
+    history: Mutex<Vec<SentCommand>>,
+}
+
+impl MockClient {
+    /// Expect a command of type `C`, e.g. `client.expect::<ApiSendMessages>()`. The expectation
+    /// gets registered when one of the `respond_*` methods is called.
+    pub fn expect<C: CommandSyntax>(&self) -> ExpectBuilder<'_> {
+        self.expect_matching(Matcher::Kind(std::any::type_name::<C>()))
+    }
+
+    /// Expect a raw command starting with `prefix`. Required for commands sent via
+    /// [`ClientApi::send_raw`] directly, e.g. `/relays` or `/crc <link>`.
+    pub fn expect_raw(&self, prefix: impl Into<String>) -> ExpectBuilder<'_> {
+        self.expect_matching(Matcher::Prefix(prefix.into()))
+    }
+
+    /// Expect any command. Useful as a catch-all registered after more specific expectations.
+    pub fn expect_any(&self) -> ExpectBuilder<'_> {
+        self.expect_matching(Matcher::Any)
+    }
+
+    fn expect_matching(&self, matcher: Matcher) -> ExpectBuilder<'_> {
+        ExpectBuilder {
+            client: self,
+            matcher,
+            predicates: Vec::new(),
+            times: Some(1),
+        }
+    }
+
+    /// All commands sent through this client in the order they were sent
+    pub fn sent(&self) -> Vec<SentCommand> {
+        self.inner.history.lock().unwrap().clone()
+    }
+
+    /// Commands of type `C` sent through this client
+    pub fn sent_of<C: CommandSyntax>(&self) -> Vec<SentCommand> {
+        let kind = std::any::type_name::<C>();
+
+        self.inner
+            .history
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|cmd| cmd.kind == Some(kind))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns descriptions of expectations that still wait for calls. Expectations set with
+    /// [`ExpectBuilder::repeatedly`] are never reported.
+    pub fn unsatisfied(&self) -> Vec<String> {
+        self.inner
+            .expectations
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|exp| exp.times.is_some_and(|n| n > 0))
+            .map(|exp| format!("{} ({} call(s) left)", exp.matcher, exp.times.unwrap()))
+            .collect()
+    }
+
+    /// Panics if some expectations weren't satisfied
+    pub fn assert_satisfied(&self) {
+        let unsatisfied = self.unsatisfied();
+        assert!(
+            unsatisfied.is_empty(),
+            "Unsatisfied mock expectations:\n{}",
+            unsatisfied.join("\n")
+        );
+    }
+
+    /// Drops all registered expectations and the command history
+    pub fn reset(&self) {
+        self.inner.expectations.lock().unwrap().clear();
+        self.inner.history.lock().unwrap().clear();
+    }
+
+    fn exchange(&self, kind: Option<&'static str>, command: String) -> Result<String, MockError> {
+        let response = {
+            let mut expectations = self.inner.expectations.lock().unwrap();
+
+            expectations
+                .iter_mut()
+                .find(|exp| exp.times != Some(0) && exp.matches(kind, &command))
+                .map(|exp| {
+                    if let Some(n) = exp.times.as_mut() {
+                        *n -= 1;
+                    }
+
+                    exp.responder.respond(&command)
+                })
+        };
+
+        self.inner.history.lock().unwrap().push(SentCommand {
+            kind,
+            command: command.clone(),
+        });
+
+        match response {
+            Some(resp) => Ok(serde_json::json!({ "resp": resp }).to_string()),
+            None => {
+                log::error!("Unexpected mock command: {command}");
+                Err(MockError::Unexpected(command))
+            }
+        }
+    }
+}
+
+impl ClientApi for MockClient {
+    type ResponseShape<'de, T>
+        = WebSocketResponseShape<T>
+    where
+        T: 'de + Deserialize<'de>;
+
+    type Error = MockError;
+
+    async fn send_raw(&self, command: String) -> Result<String, Self::Error> {
+        self.exchange(None, command)
+    }
+
+    // Overridden to remember the command type so expectations can be keyed on it
+    fn send<C, R>(&self, cmd: C) -> impl Future<Output = Result<R, Self::Error>> + Send
+    where
+        C: Send + CommandSyntax,
+        R: for<'de> Deserialize<'de>,
+    {
+        let command = cmd.to_command_string();
+
+        async move {
+            let raw = self.exchange(Some(std::any::type_name::<C>()), command)?;
+            let response_shape: Self::ResponseShape<'_, R> =
+                serde_json::from_str(&raw).map_err(BadResponseError::InvalidJson)?;
+            let response = response_shape.extract_response()?;
+            Ok(response)
+        }
+    }
+}
+
+/// A command recorded by [`MockClient`]
+#[derive(Debug, Clone)]
+pub struct SentCommand {
+    /// Command type name. `None` for commands sent via [`ClientApi::send_raw`]
+    pub kind: Option<&'static str>,
+    /// Serialized command
+    pub command: String,
+}
+
+/// Obtained from [`MockClient::expect`] and similar methods. Registers an expectation when a
+/// response is set.
+pub struct ExpectBuilder<'a> {
+    client: &'a MockClient,
+    matcher: Matcher,
+    predicates: Vec<Predicate>,
+    times: Option<usize>,
+}
+
+impl ExpectBuilder<'_> {
+    /// Only match commands addressing the given chat
+    pub fn to_chat(self, chat_id: impl Into<ChatId>) -> Self {
+        let chat_ref = chat_id.into().into_chat_ref().to_command_string();
+        self.matching(move |cmd| cmd.split_whitespace().any(|token| token == chat_ref))
+    }
+
+    /// Only match commands satisfying the predicate over the serialized command
+    pub fn matching<F>(mut self, predicate: F) -> Self
+    where
+        F: 'static + Send + Sync + Fn(&str) -> bool,
+    {
+        self.predicates.push(Box::new(predicate));
+        self
+    }
+
+    /// Expect the command exactly `n` times. Default: 1
+    pub fn times(mut self, n: usize) -> Self {
+        self.times = Some(n);
+        self
+    }
+
+    /// Answer any number of matching commands
+    pub fn repeatedly(mut self) -> Self {
+        self.times = None;
+        self
+    }
+
+    /// Respond with a raw JSON response, e.g. `{"type": "cmdOk"}`
+    pub fn respond_json(self, response: JsonObject) {
+        self.register(Responder::Json(response));
+    }
+
+    /// Respond with a serializable response, e.g.
+    /// [`ApiSendMessagesResponse`](crate::responses::ApiSendMessagesResponse)
+    pub fn respond_with<R: Serialize>(self, response: R) {
+        self.respond_json(serde_json::to_value(response).expect("Serializable response"));
+    }
+
+    /// Respond with a `chatCmdError`
+    pub fn respond_error(self, error: ChatError) {
+        self.respond_json(serde_json::json!({
+            "type": "chatCmdError",
+            "chatError": error,
+        }));
+    }
+
+    /// Compute the JSON response from the serialized command
+    pub fn respond_fn<F>(self, f: F)
+    where
+        F: 'static + Send + Sync + Fn(&str) -> JsonObject,
+    {
+        self.register(Responder::Fn(Box::new(f)));
+    }
+
+    fn register(self, responder: Responder) {
+        self.client
+            .inner
+            .expectations
+            .lock()
+            .unwrap()
+            .push(Expectation {
+                matcher: self.matcher,
+                predicates: self.predicates,
+                responder,
+                times: self.times,
+            });
+    }
+}
+
+struct Expectation {
+    matcher: Matcher,
+    predicates: Vec<Predicate>,
+    responder: Responder,
+    times: Option<usize>,
+}
+
+impl Expectation {
+    fn matches(&self, kind: Option<&'static str>, command: &str) -> bool {
+        let matches = match &self.matcher {
+            Matcher::Kind(expected) => kind == Some(expected),
+            Matcher::Prefix(prefix) => command.starts_with(prefix.as_str()),
+            Matcher::Any => true,
+        };
+
+        matches && self.predicates.iter().all(|p| p(command))
+    }
+}
+
+enum Matcher {
+    Kind(&'static str),
+    Prefix(String),
+    Any,
+}
+
+impl std::fmt::Display for Matcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Kind(kind) => write!(f, "{}", kind.rsplit("::").next().unwrap_or(kind)),
+            Self::Prefix(prefix) => write!(f, "{prefix:?}"),
+            Self::Any => write!(f, "<any command>"),
+        }
+    }
+}
+
+enum Responder {
+    Json(JsonObject),
+    Fn(ResponderFn),
+}
+
+impl Responder {
+    fn respond(&self, command: &str) -> JsonObject {
+        match self {
+            Self::Json(json) => json.clone(),
+            Self::Fn(f) => f(command),
+        }
+    }
+}
+
+/// Pushes events into the [`EventStream`] created by [`init`]. The stream ends when all
+/// senders are dropped or closed.
+#[derive(Clone)]
+pub struct EventSender {
+    sender: tokio::sync::mpsc::UnboundedSender<Event>,
+}
+
+impl EventSender {
+    /// Push a typed event, e.g. [`NewChatItems`](crate::events::NewChatItems)
+    pub fn push<Ev: EventData>(&self, event: Ev) {
+        self.push_event(Arc::new(event).into_event());
+    }
+
+    pub fn push_event(&self, event: Event) {
+        // The stream may be already dropped by the code under test which is not an error
+        let _ = self.sender.send(event);
+    }
+
+    /// Push an event from its JSON representation, e.g. `{"type": "newChatItems", ...}`
+    pub fn push_json(&self, json: JsonObject) -> Result<(), serde_json::Error> {
+        self.push_event(serde_json::from_value(json)?);
+        Ok(())
+    }
+
+    /// Drops the sender. The stream ends after all buffered events are consumed and all other
+    /// clones are closed as well
+    pub fn close(self) {}
+}
+
+#[derive(Debug)]
+pub enum MockError {
+    /// No expectation matched the command
+    Unexpected(String),
+    /// SimpleX command error or unexpected(undocumented) response.
+    BadResponse(BadResponseError),
+}
+
+impl std::error::Error for MockError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Unexpected(_) => None,
+            Self::BadResponse(error) => Some(error),
+        }
+    }
+}
+
+impl std::fmt::Display for MockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unexpected(cmd) => write!(f, "Unexpected mock command: {cmd}"),
+            Self::BadResponse(err) => err.fmt(f),
+        }
+    }
+}
+
+impl From<BadResponseError> for MockError {
+    fn from(err: BadResponseError) -> Self {
+        Self::BadResponse(err)
+    }
+}
+
+impl From<Infallible> for MockError {
+    fn from(never: Infallible) -> Self {
+        match never {}
+    }
+}
+
+impl ClientApiError for MockError {
+    fn bad_response(&self) -> Option<&BadResponseError> {
+        if let Self::BadResponse(resp) = self {
+            Some(resp)
+        } else {
+            None
+        }
+    }
+
+    fn bad_response_mut(&mut self) -> Option<&mut BadResponseError> {
+        if let Self::BadResponse(resp) = self {
+            Some(resp)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{commands::ApiSetContactCustomData, ext::ClientApiExt as _, id::ContactId};
+
+    #[tokio::test]
+    async fn mock_expectations() {
+        let (client, _, _) = init();
+
+        client
+            .expect::<ApiSetContactCustomData>()
+            .to_chat(ContactId::from_raw(5))
+            .respond_json(serde_json::json!({ "type": "cmdOk" }));
+
+        assert!(
+            client
+                .set_contact_custom_data(ContactId::from_raw(7), None)
+                .await
+                .is_err()
+        );
+        assert_eq!(client.unsatisfied().len(), 1);
+
+        client
+            .set_contact_custom_data(ContactId::from_raw(5), None)
+            .await
+            .unwrap();
+
+        client.assert_satisfied();
+        assert_eq!(client.sent_of::<ApiSetContactCustomData>().len(), 2);
+
+        // Commands are sent when polled, like with the real clients
+        client
+            .expect::<ApiSetContactCustomData>()
+            .respond_json(serde_json::json!({ "type": "cmdOk" }));
+
+        let pending = client.send::<_, JsonObject>(ApiSetContactCustomData::new(5));
+        drop(pending);
+
+        assert_eq!(client.sent().len(), 2);
+        assert_eq!(client.unsatisfied().len(), 1);
+    }
+}
@@ -0,0 +1,487 @@
+//! Vote collection for [`Poll`] messages.
+//!
+//! SimpleX has no buttons so polls are answered with numbered text replies or emoji reactions.
+//! The [`PollCollector`] matches them back to the tracked polls and aggregates the results per
+//! chat until the poll closes.
+//!
+//! ```ignore
+//! let polls = PollCollector::new();
+//!
+//! let poll = Poll::yes_no("Pizza tonight?");
+//! let response = bot.send_msg(chat, poll.clone()).await?;
+//! polls.track(chat, &response.chat_items[0], poll, Some(Duration::from_secs(600)));
+//!
+//! events.into_dispatcher(bot)
+//!     .on(polls.reaction_handler())
+//!     .on(async move |ev: Arc<NewChatItems>, bot| {
+//!         if polls.record_messages(&ev) > 0 {
+//!             return Ok(StreamEvents::Continue);
+//!         }
+//!         // handle other messages
+//!     })
+//!     .dispatch()
+//!     .await?;
+//!
+//! // Later, e.g. from the scheduler
+//! for (chat, _, results) in polls.close_expired() {
+//!     bot.send_msg(chat, results.to_string()).await?;
+//! }
+//! ```
+//!
+//! A text reply quoting the poll votes in that poll, an unquoted reply votes in the latest open
+//! poll of the chat accepting it. Every voter has a single ballot per poll: a new text reply
+//! replaces the previous choice, reactions replace it in single choice polls and add up in
+//! multiple choice ones.
+
+use simploxide_api_types::{
+    AChatItem, CIDirection, MsgReaction,
+    events::{ChatItemReaction, NewChatItems},
+};
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Ready,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    StreamEvents,
+    id::{ChatId, ContactId, MemberId, MessageId},
+    messages::{MsgContentExt as _, Poll},
+};
+
+/// Who voted in the poll
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Voter {
+    Contact(ContactId),
+    Member(MemberId),
+}
+
+impl Voter {
+    /// `None` for the messages sent by the user
+    pub fn from_direction(chat_id: ChatId, dir: &CIDirection) -> Option<Self> {
+        match (chat_id, dir) {
+            (ChatId::Direct(contact_id), CIDirection::DirectRcv) => Some(Self::Contact(contact_id)),
+            (_, CIDirection::GroupRcv { group_member, .. }) => {
+                Some(Self::Member(MemberId::from(group_member)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Aggregated poll votes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PollResults {
+    pub question: String,
+    pub options: Vec<String>,
+    /// The number of votes per option
+    pub counts: Vec<usize>,
+    /// The number of voters
+    pub voters: usize,
+    /// The poll deadline has passed
+    pub closed: bool,
+}
+
+impl PollResults {
+    /// The options with the most votes. Empty if nobody voted
+    pub fn winners(&self) -> Vec<usize> {
+        let max = self.counts.iter().copied().max().unwrap_or(0);
+
+        if max == 0 {
+            return Vec::new();
+        }
+
+        (0..self.counts.len())
+            .filter(|i| self.counts[*i] == max)
+            .collect()
+    }
+}
+
+impl std::fmt::Display for PollResults {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "*{}*", self.question)?;
+
+        for (i, (option, count)) in self.options.iter().zip(&self.counts).enumerate() {
+            writeln!(f, "{}. {option}: {count}", i + 1)?;
+        }
+
+        write!(f, "\nVoters: {}", self.voters)
+    }
+}
+
+/// Collects the votes for the tracked polls. Cheap to clone.
+#[derive(Clone, Default)]
+pub struct PollCollector {
+    chats: Arc<Mutex<HashMap<ChatId, Vec<TrackedPoll>>>>,
+}
+
+struct TrackedPoll {
+    message_id: MessageId,
+    poll: Poll,
+    closes_at: Option<Instant>,
+    votes: BTreeMap<Voter, Vec<usize>>,
+}
+
+impl TrackedPoll {
+    fn is_open(&self, now: Instant) -> bool {
+        self.closes_at.is_none_or(|closes_at| now < closes_at)
+    }
+
+    fn results(&self, now: Instant) -> PollResults {
+        let mut counts = vec![0; self.poll.options().len()];
+
+        for choice in self.votes.values().flatten() {
+            counts[*choice] += 1;
+        }
+
+        PollResults {
+            question: self.poll.question().to_owned(),
+            options: self.poll.options().to_vec(),
+            counts,
+            voters: self.votes.len(),
+            closed: !self.is_open(now),
+        }
+    }
+}
+
+impl PollCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start collecting votes for the sent poll. Votes are accepted for `open_for` or until
+    /// [`Self::close`] if it is `None`
+    pub fn track(
+        &self,
+        chat_id: impl Into<ChatId>,
+        message_id: impl Into<MessageId>,
+        poll: Poll,
+        open_for: Option<Duration>,
+    ) {
+        let poll = TrackedPoll {
+            message_id: message_id.into(),
+            poll,
+            closes_at: open_for.map(|open_for| Instant::now() + open_for),
+            votes: BTreeMap::new(),
+        };
+
+        self.chats
+            .lock()
+            .unwrap()
+            .entry(chat_id.into())
+            .or_default()
+            .push(poll);
+    }
+
+    /// Record the vote if the message is a reply to a tracked poll. Returns whether the message
+    /// was counted as a vote
+    pub fn record_message(&self, item: &AChatItem) -> bool {
+        let Some(chat_id) = ChatId::from_chat_info(&item.chat_info) else {
+            return false;
+        };
+
+        let Some(voter) = Voter::from_direction(chat_id, &item.chat_item.chat_dir) else {
+            return false;
+        };
+
+        let Some(text) = item
+            .chat_item
+            .content
+            .rcv_msg_content()
+            .and_then(|content| content.text_part())
+        else {
+            return false;
+        };
+
+        let quoted = item
+            .chat_item
+            .quoted_item
+            .as_ref()
+            .and_then(|quote| quote.item_id)
+            .and_then(|id| MessageId::try_from(id).ok());
+
+        self.record_reply(chat_id, voter, quoted, text.trim())
+    }
+
+    /// Record votes from all received messages. Returns the number of messages counted as votes
+    pub fn record_messages(&self, ev: &NewChatItems) -> usize {
+        ev.chat_items
+            .iter()
+            .filter(|item| self.record_message(item))
+            .count()
+    }
+
+    /// Record the vote if the reaction is set on a tracked poll. Returns whether the reaction was
+    /// counted as a vote
+    pub fn record_reaction(&self, ev: &ChatItemReaction) -> bool {
+        let Some(chat_id) = ChatId::from_chat_info(&ev.reaction.chat_info) else {
+            return false;
+        };
+
+        let reaction = &ev.reaction.chat_reaction;
+        let Some(voter) = Voter::from_direction(chat_id, &reaction.chat_dir) else {
+            return false;
+        };
+
+        let MsgReaction::Emoji { emoji, .. } = &reaction.reaction else {
+            return false;
+        };
+
+        self.record_emoji(
+            chat_id,
+            MessageId::from(&reaction.chat_item),
+            voter,
+            emoji,
+            ev.added,
+        )
+    }
+
+    /// The [`ChatItemReaction`] handler for [`Dispatcher::on`](crate::dispatcher::Dispatcher::on)
+    pub fn reaction_handler<Ctx, E>(
+        &self,
+    ) -> impl Fn(Arc<ChatItemReaction>, Ctx) -> Ready<Result<StreamEvents, E>> + use<Ctx, E> {
+        let collector = self.clone();
+        move |ev, _| {
+            collector.record_reaction(&ev);
+            std::future::ready(Ok(StreamEvents::Continue))
+        }
+    }
+
+    /// The current results of the poll. `None` if the poll isn't tracked
+    pub fn results(
+        &self,
+        chat_id: impl Into<ChatId>,
+        message_id: impl Into<MessageId>,
+    ) -> Option<PollResults> {
+        let message_id = message_id.into();
+        let chats = self.chats.lock().unwrap();
+
+        chats
+            .get(&chat_id.into())?
+            .iter()
+            .find(|tracked| tracked.message_id == message_id)
+            .map(|tracked| tracked.results(Instant::now()))
+    }
+
+    /// The current results of all polls tracked in the chat, oldest first
+    pub fn chat_results(&self, chat_id: impl Into<ChatId>) -> Vec<(MessageId, PollResults)> {
+        let now = Instant::now();
+        let chats = self.chats.lock().unwrap();
+
+        chats
+            .get(&chat_id.into())
+            .into_iter()
+            .flatten()
+            .map(|tracked| (tracked.message_id, tracked.results(now)))
+            .collect()
+    }
+
+    /// Stop tracking the poll and return its final results
+    pub fn close(
+        &self,
+        chat_id: impl Into<ChatId>,
+        message_id: impl Into<MessageId>,
+    ) -> Option<PollResults> {
+        let chat_id = chat_id.into();
+        let message_id = message_id.into();
+        let mut chats = self.chats.lock().unwrap();
+
+        let polls = chats.get_mut(&chat_id)?;
+        let index = polls
+            .iter()
+            .position(|tracked| tracked.message_id == message_id)?;
+        let tracked = polls.remove(index);
+
+        if polls.is_empty() {
+            chats.remove(&chat_id);
+        }
+
+        let mut results = tracked.results(Instant::now());
+        results.closed = true;
+        Some(results)
+    }
+
+    /// Stop tracking all polls past their deadline and return their final results
+    pub fn close_expired(&self) -> Vec<(ChatId, MessageId, PollResults)> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+
+        self.chats.lock().unwrap().retain(|chat_id, polls| {
+            polls.retain(|tracked| {
+                if tracked.is_open(now) {
+                    return true;
+                }
+
+                expired.push((*chat_id, tracked.message_id, tracked.results(now)));
+                false
+            });
+
+            !polls.is_empty()
+        });
+
+        expired
+    }
+
+    fn record_reply(
+        &self,
+        chat_id: ChatId,
+        voter: Voter,
+        quoted: Option<MessageId>,
+        text: &str,
+    ) -> bool {
+        let now = Instant::now();
+        let mut chats = self.chats.lock().unwrap();
+
+        let Some(polls) = chats.get_mut(&chat_id) else {
+            return false;
+        };
+
+        let vote = polls.iter_mut().rev().find_map(|tracked| {
+            if !tracked.is_open(now) || quoted.is_some_and(|id| id != tracked.message_id) {
+                return None;
+            }
+
+            let choices = tracked.poll.parse_reply(text)?;
+            Some((tracked, choices))
+        });
+
+        match vote {
+            Some((tracked, choices)) => {
+                tracked.votes.insert(voter, choices);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn record_emoji(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        voter: Voter,
+        emoji: &str,
+        added: bool,
+    ) -> bool {
+        let now = Instant::now();
+        let mut chats = self.chats.lock().unwrap();
+
+        let Some(tracked) = chats.get_mut(&chat_id).and_then(|polls| {
+            polls
+                .iter_mut()
+                .find(|tracked| tracked.message_id == message_id)
+        }) else {
+            return false;
+        };
+
+        let Some(choice) = tracked.poll.reaction_option(emoji) else {
+            return false;
+        };
+
+        if !tracked.is_open(now) {
+            return false;
+        }
+
+        let multiple = tracked.poll.is_multiple();
+        let choices = tracked.votes.entry(voter).or_default();
+
+        if !added {
+            choices.retain(|c| *c != choice);
+        } else if multiple {
+            if !choices.contains(&choice) {
+                choices.push(choice);
+            }
+        } else {
+            *choices = vec![choice];
+        }
+
+        if choices.is_empty() {
+            tracked.votes.remove(&voter);
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{id::GroupId, messages::MessageLike as _};
+
+    #[test]
+    fn poll_message() {
+        let poll = Poll::yes_no("Pizza?");
+        let (msg, _) = poll.clone().into_builder_parts();
+
+        assert_eq!(Poll::from_content(&msg.msg_content), Some(poll));
+        assert_eq!(
+            msg.msg_content.text_part(),
+            Some("*Pizza?*\n1. 👍 Yes\n2. 👎 No\n\n_Reply with the option number or react_")
+        );
+
+        // User input doesn't break the markup
+        let poll = Poll::new("*Lunch* or _dinner_?", ["*lunch*", "_dinner_"]);
+        assert_eq!(
+            poll.to_string(),
+            "\u{2060}*Lunch\u{2060}* *or \u{2060}_dinner_?*\n1. \u{2060}*lunch*\n2. \u{2060}_dinner_\n\n_Reply with the option number_"
+        );
+    }
+
+    #[test]
+    fn collect_votes() {
+        let chat = ChatId::from(GroupId::from_raw(1));
+        let alice = Voter::Member(MemberId::from_raw(2));
+        let bob = Voter::Member(MemberId::from_raw(3));
+        let poll_id = MessageId::from_raw(10);
+
+        let polls = PollCollector::new();
+        polls.track(
+            chat,
+            poll_id,
+            Poll::new("Lunch?", ["Pizza", "Sushi", "Salad"]).with_reactions(["👍", "🚀"]),
+            None,
+        );
+
+        assert!(polls.record_reply(chat, alice, None, "2"));
+        assert!(polls.record_reply(chat, bob, Some(poll_id), "3."));
+        assert!(!polls.record_reply(chat, bob, None, "1, 2"));
+        assert!(!polls.record_reply(chat, bob, None, "4"));
+        assert!(!polls.record_reply(chat, bob, Some(MessageId::from_raw(9)), "1"));
+        assert!(!polls.record_reply(chat, bob, None, "hello"));
+
+        // Replaces the text vote
+        assert!(polls.record_emoji(chat, poll_id, bob, "👍", true));
+        assert!(!polls.record_emoji(chat, poll_id, bob, "😢", true));
+
+        let results = polls.results(chat, poll_id).unwrap();
+        assert_eq!(results.counts, [1, 1, 0]);
+        assert_eq!(results.voters, 2);
+        assert_eq!(results.winners(), [0, 1]);
+        assert!(!results.closed);
+
+        assert!(polls.record_emoji(chat, poll_id, bob, "👍", false));
+        assert_eq!(polls.results(chat, poll_id).unwrap().voters, 1);
+
+        let multi_id = MessageId::from_raw(11);
+        polls.track(
+            chat,
+            multi_id,
+            Poll::new("Days?", ["Mon", "Tue"]).allow_multiple(true),
+            Some(Duration::ZERO),
+        );
+
+        // The multiple choice poll is closed, the vote goes to the first one
+        assert!(polls.record_reply(chat, bob, None, "1"));
+        assert_eq!(polls.results(chat, poll_id).unwrap().counts, [1, 1, 0]);
+
+        let expired = polls.close_expired();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].1, multi_id);
+        assert!(expired[0].2.closed);
+
+        let results = polls.close(chat, poll_id).unwrap();
+        assert!(results.closed);
+        assert!(polls.chat_results(chat).is_empty());
+    }
+}
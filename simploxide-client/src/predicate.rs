@@ -0,0 +1,314 @@
+//! Event predicates filtering events by their content rather than by [`EventKind`].
+//!
+//! Predicates can be installed on the [`EventStream`] with [`EventStream::set_predicate`] or used
+//! as handler guards with [`Dispatcher::on_if`] and [`Dispatcher::seq_if`]. Any
+//! `Fn(&Event) -> bool` closure is a predicate as well.
+//!
+//! Chat item predicates([`content`], [`sender_role`], etc) match events carrying at least one
+//! matching item, e.g. [`Event::NewChatItems`], [`Event::ChatItemUpdated`] or file events.
+//!
+//! Guards registered later are checked first, so register the broad guards before the narrow
+//! ones:
+//!
+//! ```rust
+//! use simploxide_client::predicate::{self, EventPredicate as _};
+//!
+//! events.into_dispatcher(bot)
+//!     .on(direct_msgs)
+//!     .on_if(predicate::groups(), member_msgs)
+//!     .on_if(
+//!         predicate::groups().and(predicate::sender_role_at_least(GroupMemberRole::Admin)),
+//!         admin_msgs,
+//!     )
+//!     .dispatch()
+//!     .await;
+//! ```
+//!
+//! [`EventKind`]: crate::events::EventKind
+//! [`EventStream`]: crate::EventStream
+//! [`EventStream::set_predicate`]: crate::EventStream::set_predicate
+//! [`Dispatcher::on_if`]: crate::dispatcher::Dispatcher::on_if
+//! [`Dispatcher::seq_if`]: crate::dispatcher::Dispatcher::seq_if
+
+use simploxide_api_types::{AChatItem, CIDirection, GroupMemberRole, MsgContent, events::Event};
+
+use crate::{
+    ext::{EventExt as _, MentionsExt as _},
+    id::{ChatId, ContactId, GroupId, MemberId},
+};
+
+/// A condition on event content. See the [module docs](self)
+pub trait EventPredicate: Send + Sync {
+    fn matches(&self, ev: &Event) -> bool;
+
+    /// Matches when both predicates match
+    fn and<P: EventPredicate>(self, other: P) -> And<Self, P>
+    where
+        Self: Sized,
+    {
+        And(self, other)
+    }
+
+    /// Matches when any of the predicates matches
+    fn or<P: EventPredicate>(self, other: P) -> Or<Self, P>
+    where
+        Self: Sized,
+    {
+        Or(self, other)
+    }
+
+    /// Inverts the predicate
+    fn not(self) -> Not<Self>
+    where
+        Self: Sized,
+    {
+        Not(self)
+    }
+}
+
+impl<F> EventPredicate for F
+where
+    F: Send + Sync + Fn(&Event) -> bool,
+{
+    fn matches(&self, ev: &Event) -> bool {
+        self(ev)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct And<P1, P2>(P1, P2);
+
+impl<P1: EventPredicate, P2: EventPredicate> EventPredicate for And<P1, P2> {
+    fn matches(&self, ev: &Event) -> bool {
+        self.0.matches(ev) && self.1.matches(ev)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Or<P1, P2>(P1, P2);
+
+impl<P1: EventPredicate, P2: EventPredicate> EventPredicate for Or<P1, P2> {
+    fn matches(&self, ev: &Event) -> bool {
+        self.0.matches(ev) || self.1.matches(ev)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Not<P>(P);
+
+impl<P: EventPredicate> EventPredicate for Not<P> {
+    fn matches(&self, ev: &Event) -> bool {
+        !self.0.matches(ev)
+    }
+}
+
+/// Matches events from the specified chat. A [`ChatId::Group`] without scope matches all scopes of
+/// the group. See [`crate::ext::EventExt::chat_id`].
+pub fn chat(id: impl Into<ChatId>) -> InChat {
+    InChat(id.into())
+}
+
+/// Matches events from the specified group
+pub fn group(id: impl Into<GroupId>) -> InChat {
+    InChat(ChatId::Group {
+        id: id.into(),
+        scope: None,
+    })
+}
+
+/// Matches events from the direct chat with the specified contact
+pub fn contact(id: impl Into<ContactId>) -> InChat {
+    InChat(ChatId::Direct(id.into()))
+}
+
+/// Matches events from direct chats
+pub fn direct() -> ChatKind {
+    ChatKind::Direct
+}
+
+/// Matches events from group chats
+pub fn groups() -> ChatKind {
+    ChatKind::Group
+}
+
+/// Matches events carrying messages with the specified content type
+pub fn content(content_type: ContentType) -> Content {
+    Content(content_type)
+}
+
+/// Matches events carrying group messages sent by a member with the specified role
+pub fn sender_role(role: GroupMemberRole) -> SenderRole {
+    SenderRole {
+        role,
+        or_higher: false,
+    }
+}
+
+/// Matches events carrying group messages sent by a member with the specified or a higher role.
+/// The roles are ordered as `observer < author < member < moderator < admin < owner`, relays don't
+/// have any rank.
+pub fn sender_role_at_least(role: GroupMemberRole) -> SenderRole {
+    SenderRole {
+        role,
+        or_higher: true,
+    }
+}
+
+/// Matches events carrying group messages sent by the specified member
+pub fn sender(id: impl Into<MemberId>) -> Sender {
+    Sender(id.into())
+}
+
+/// Matches events carrying group messages that mention the user. See
+/// [`crate::ext::MentionsExt`].
+pub fn mentions_user() -> MentionsUser {
+    MentionsUser
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InChat(ChatId);
+
+impl EventPredicate for InChat {
+    fn matches(&self, ev: &Event) -> bool {
+        match (self.0, ev.chat_id()) {
+            (ChatId::Group { id, scope: None }, Some(ChatId::Group { id: ev_id, .. })) => {
+                id == ev_id
+            }
+            (expected, actual) => Some(expected) == actual,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatKind {
+    Direct,
+    Group,
+    Local,
+}
+
+impl EventPredicate for ChatKind {
+    fn matches(&self, ev: &Event) -> bool {
+        matches!(
+            (self, ev.chat_id()),
+            (Self::Direct, Some(ChatId::Direct(_)))
+                | (Self::Group, Some(ChatId::Group { .. }))
+                | (Self::Local, Some(ChatId::Local(_)))
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentType {
+    Text,
+    Link,
+    Image,
+    Video,
+    Voice,
+    File,
+    Report,
+    Chat,
+    Unknown,
+}
+
+impl ContentType {
+    pub fn of(content: &MsgContent) -> Self {
+        match content {
+            MsgContent::Text { .. } => Self::Text,
+            MsgContent::Link { .. } => Self::Link,
+            MsgContent::Image { .. } => Self::Image,
+            MsgContent::Video { .. } => Self::Video,
+            MsgContent::Voice { .. } => Self::Voice,
+            MsgContent::File { .. } => Self::File,
+            MsgContent::Report { .. } => Self::Report,
+            MsgContent::Chat { .. } => Self::Chat,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Content(ContentType);
+
+impl EventPredicate for Content {
+    fn matches(&self, ev: &Event) -> bool {
+        any_chat_item(ev, |item| {
+            let content = &item.chat_item.content;
+            content
+                .rcv_msg_content()
+                .or_else(|| content.snd_msg_content())
+                .is_some_and(|content| ContentType::of(content) == self.0)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SenderRole {
+    role: GroupMemberRole,
+    or_higher: bool,
+}
+
+impl EventPredicate for SenderRole {
+    fn matches(&self, ev: &Event) -> bool {
+        any_chat_item(ev, |item| match &item.chat_item.chat_dir {
+            CIDirection::GroupRcv { group_member, .. } if self.or_higher => {
+                match (rank(group_member.member_role), rank(self.role)) {
+                    (Some(actual), Some(expected)) => actual >= expected,
+                    _ => group_member.member_role == self.role,
+                }
+            }
+            CIDirection::GroupRcv { group_member, .. } => group_member.member_role == self.role,
+            _ => false,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sender(MemberId);
+
+impl EventPredicate for Sender {
+    fn matches(&self, ev: &Event) -> bool {
+        any_chat_item(ev, |item| match &item.chat_item.chat_dir {
+            CIDirection::GroupRcv { group_member, .. } => MemberId::from(group_member) == self.0,
+            _ => false,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MentionsUser;
+
+impl EventPredicate for MentionsUser {
+    fn matches(&self, ev: &Event) -> bool {
+        any_chat_item(ev, AChatItem::mentions_user)
+    }
+}
+
+fn rank(role: GroupMemberRole) -> Option<u8> {
+    match role {
+        GroupMemberRole::Observer => Some(0),
+        GroupMemberRole::Author => Some(1),
+        GroupMemberRole::Member => Some(2),
+        GroupMemberRole::Moderator => Some(3),
+        GroupMemberRole::Admin => Some(4),
+        GroupMemberRole::Owner => Some(5),
+        _ => None,
+    }
+}
+
+fn any_chat_item(ev: &Event, mut f: impl FnMut(&AChatItem) -> bool) -> bool {
+    match ev {
+        Event::NewChatItems(ev) => ev.chat_items.iter().any(f),
+        Event::ChatItemUpdated(ev) => f(&ev.chat_item),
+        Event::ChatItemsDeleted(ev) => ev
+            .chat_item_deletions
+            .iter()
+            .any(|deletion| f(&deletion.deleted_chat_item)),
+        Event::RcvFileDescrReady(ev) => f(&ev.chat_item),
+        Event::RcvFileComplete(ev) => f(&ev.chat_item),
+        Event::RcvFileStart(ev) => f(&ev.chat_item),
+        Event::RcvFileAccepted(ev) => f(&ev.chat_item),
+        Event::RcvFileSndCancelled(ev) => f(&ev.chat_item),
+        Event::SndFileCompleteXftp(ev) => f(&ev.chat_item),
+        _ => false,
+    }
+}
@@ -8,11 +8,12 @@ pub use crate::{
     est_size::EstSize as _,
     events::*,
     ext::{
-        ClientApiExt as _, DeleteMode, FileSourceExt as _, FilterChatItems as _, GroupLinkExt as _,
-        Reaction,
+        ClientApiExt as _, DeleteMode, EventExt as _, FileSourceExt as _, FilterChatItems as _,
+        GroupLinkExt as _, MentionsExt as _, Reaction, ReactionsExt as _,
     },
     id::*,
     messages::*,
+    predicate::EventPredicate as _,
     preferences,
     preview::ImagePreview,
     remote::{CtrlError, CtrlHandle},
@@ -0,0 +1,447 @@
+//! Callbacks for reactions on specific sent messages.
+//!
+//! Register a callback for a message the bot has sent and feed the [`ChatItemReaction`] events
+//! into the [`ReactionWatcher`]. The watcher matches the events to the messages, keeps their
+//! reaction tallies and drops the callbacks once they expire.
+//!
+//! ```ignore
+//! let reactions = ReactionWatcher::new();
+//!
+//! let response = bot.send_msg(chat, "Delete the account? React 👍 to confirm").await?;
+//! reactions.confirm(
+//!     chat,
+//!     &response.chat_items[0],
+//!     "👍",
+//!     Some(Duration::from_secs(60)),
+//!     async |reaction, bot: ws::Bot| {
+//!         bot.send_msg(reaction.chat_id, "Account deleted").await?;
+//!         Ok(())
+//!     },
+//! );
+//!
+//! events.into_dispatcher(bot)
+//!     .on(reactions.handler())
+//!     .dispatch()
+//!     .await?;
+//! ```
+//!
+//! The tallies include all reactions on the message, not only the ones received after the
+//! callback was registered. Use [`ReactionsExt`](crate::ext::ReactionsExt) to tally reactions of
+//! messages that aren't watched.
+
+use simploxide_api_types::{MsgReaction, events::ChatItemReaction};
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    StreamEvents,
+    ext::ReactionsExt as _,
+    id::{ChatId, MessageId},
+    poll::Voter,
+};
+
+pub type ReactionFuture<T, E> = Pin<Box<dyn Send + Future<Output = Result<T, E>>>>;
+
+type WatchedMessages<Ctx, E> = HashMap<(ChatId, MessageId), Watched<Ctx, E>>;
+
+type BoxCallback<Ctx, E> =
+    Arc<dyn Send + Sync + Fn(ReactionInput, Ctx) -> ReactionFuture<Watch, E>>;
+
+type BoxFilter = Box<dyn Send + Sync + Fn(&ReactionInput) -> bool>;
+
+/// What to do with the callback after it returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    /// Keep receiving reactions on the message
+    Keep,
+    /// Remove the callback
+    Done,
+}
+
+/// A reaction passed into the callback
+#[derive(Debug, Clone)]
+pub struct ReactionInput {
+    pub chat_id: ChatId,
+    pub message_id: MessageId,
+    /// `None` for the reactions set by the user
+    pub from: Option<Voter>,
+    /// `false` if the reaction was removed
+    pub added: bool,
+    /// The reactions on the message after the change, see [`ReactionsExt::reaction_tally`](crate::ext::ReactionsExt::reaction_tally)
+    pub tally: BTreeMap<String, usize>,
+    event: Arc<ChatItemReaction>,
+}
+
+impl ReactionInput {
+    /// The reaction emoji. Empty for non-emoji reactions
+    pub fn emoji(&self) -> &str {
+        match &self.event.reaction.chat_reaction.reaction {
+            MsgReaction::Emoji { emoji, .. } => emoji,
+            _ => "",
+        }
+    }
+
+    /// The full event the reaction was received in
+    pub fn event(&self) -> &Arc<ChatItemReaction> {
+        &self.event
+    }
+}
+
+/// Matches reactions to the watched messages. Cheap to clone.
+pub struct ReactionWatcher<Ctx, E> {
+    messages: Arc<Mutex<WatchedMessages<Ctx, E>>>,
+}
+
+struct Watched<Ctx, E> {
+    callback: BoxCallback<Ctx, E>,
+    /// One-shot watches are removed before calling the callback with the first reaction passing
+    /// the filter, so concurrent reactions can't trigger the callback twice
+    once: Option<BoxFilter>,
+    expires_at: Option<Instant>,
+    tally: BTreeMap<String, usize>,
+}
+
+impl<Ctx, E> Clone for ReactionWatcher<Ctx, E> {
+    fn clone(&self) -> Self {
+        Self {
+            messages: Arc::clone(&self.messages),
+        }
+    }
+}
+
+impl<Ctx, E> Default for ReactionWatcher<Ctx, E> {
+    fn default() -> Self {
+        Self {
+            messages: Default::default(),
+        }
+    }
+}
+
+impl<Ctx, E> ReactionWatcher<Ctx, E>
+where
+    Ctx: 'static + Send,
+    E: 'static + Send,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call the callback for every reaction set or removed on the message until it returns
+    /// [`Watch::Done`] or `expires_in` elapses. Replaces the previous callback of the message.
+    ///
+    /// The callback signature is `AsyncFn(reaction: ReactionInput, ctx: Ctx) -> Result<Watch, E>`
+    pub fn watch<F, Fut>(
+        &self,
+        chat_id: impl Into<ChatId>,
+        message_id: impl Into<MessageId>,
+        expires_in: Option<Duration>,
+        callback: F,
+    ) where
+        F: 'static + Send + Sync + Fn(ReactionInput, Ctx) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<Watch, E>>,
+    {
+        self.insert(
+            (chat_id.into(), message_id.into()),
+            expires_in,
+            None,
+            Arc::new(move |reaction, ctx| Box::pin(callback(reaction, ctx))),
+        );
+    }
+
+    /// Call the callback once when someone else sets the `emoji` reaction on the message
+    pub fn confirm<F, Fut>(
+        &self,
+        chat_id: impl Into<ChatId>,
+        message_id: impl Into<MessageId>,
+        emoji: impl Into<String>,
+        expires_in: Option<Duration>,
+        callback: F,
+    ) where
+        F: 'static + Send + Sync + Fn(ReactionInput, Ctx) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<(), E>>,
+    {
+        let emoji = emoji.into();
+        let callback = Arc::new(callback);
+
+        self.insert(
+            (chat_id.into(), message_id.into()),
+            expires_in,
+            Some(Box::new(move |reaction| {
+                reaction.added && reaction.from.is_some() && reaction.emoji() == emoji
+            })),
+            Arc::new(move |reaction, ctx| {
+                let callback = Arc::clone(&callback);
+                Box::pin(async move {
+                    callback(reaction, ctx).await?;
+                    Ok(Watch::Done)
+                })
+            }),
+        );
+    }
+
+    fn insert(
+        &self,
+        key: (ChatId, MessageId),
+        expires_in: Option<Duration>,
+        once: Option<BoxFilter>,
+        callback: BoxCallback<Ctx, E>,
+    ) {
+        let watched = Watched {
+            callback,
+            once,
+            expires_at: expires_in.map(|expires_in| Instant::now() + expires_in),
+            tally: BTreeMap::new(),
+        };
+
+        self.messages.lock().unwrap().insert(key, watched);
+    }
+
+    /// Stop watching the message
+    pub fn unwatch(&self, chat_id: impl Into<ChatId>, message_id: impl Into<MessageId>) {
+        self.messages
+            .lock()
+            .unwrap()
+            .remove(&(chat_id.into(), message_id.into()));
+    }
+
+    pub fn is_watching(
+        &self,
+        chat_id: impl Into<ChatId>,
+        message_id: impl Into<MessageId>,
+    ) -> bool {
+        let now = Instant::now();
+
+        self.messages
+            .lock()
+            .unwrap()
+            .get(&(chat_id.into(), message_id.into()))
+            .is_some_and(|watched| !watched.is_expired(now))
+    }
+
+    /// The last known reactions on the watched message. `None` if the message isn't watched
+    pub fn tally(
+        &self,
+        chat_id: impl Into<ChatId>,
+        message_id: impl Into<MessageId>,
+    ) -> Option<BTreeMap<String, usize>> {
+        let now = Instant::now();
+
+        self.messages
+            .lock()
+            .unwrap()
+            .get(&(chat_id.into(), message_id.into()))
+            .filter(|watched| !watched.is_expired(now))
+            .map(|watched| watched.tally.clone())
+    }
+
+    /// Drop the expired callbacks. Expired callbacks are never called, this only frees the memory
+    pub fn purge_expired(&self) {
+        let now = Instant::now();
+
+        self.messages
+            .lock()
+            .unwrap()
+            .retain(|_, watched| !watched.is_expired(now));
+    }
+
+    /// The [`ChatItemReaction`] handler for [`Dispatcher::on`](crate::dispatcher::Dispatcher::on)
+    pub fn handler(
+        &self,
+    ) -> impl Fn(Arc<ChatItemReaction>, Ctx) -> ReactionFuture<StreamEvents, E> + use<Ctx, E> {
+        let watcher = self.clone();
+        move |ev, ctx| {
+            let watcher = watcher.clone();
+            Box::pin(async move {
+                watcher.route(ev, ctx).await?;
+                Ok(StreamEvents::Continue)
+            })
+        }
+    }
+
+    /// Pass the reaction to the callback of the message. Returns whether the message is watched
+    pub async fn route(&self, ev: Arc<ChatItemReaction>, ctx: Ctx) -> Result<bool, E> {
+        let Some(chat_id) = ChatId::from_chat_info(&ev.reaction.chat_info) else {
+            return Ok(false);
+        };
+
+        let reaction = &ev.reaction.chat_reaction;
+        let key = (chat_id, MessageId::from(&reaction.chat_item));
+        let tally = reaction.chat_item.reaction_tally();
+        let now = Instant::now();
+
+        let input = ReactionInput {
+            chat_id,
+            message_id: key.1,
+            from: Voter::from_direction(chat_id, &reaction.chat_dir),
+            added: ev.added,
+            tally: tally.clone(),
+            event: Arc::clone(&ev),
+        };
+
+        // One-shot watches are claimed under the lock, other callbacks decide after they return
+        let (callback, claimed) = {
+            let mut messages = self.messages.lock().unwrap();
+
+            let Some(watched) = messages.get_mut(&key) else {
+                return Ok(false);
+            };
+
+            if watched.is_expired(now) {
+                messages.remove(&key);
+                return Ok(false);
+            }
+
+            watched.tally = tally;
+
+            match &watched.once {
+                Some(filter) if filter(&input) => (messages.remove(&key).unwrap().callback, true),
+                Some(_) => return Ok(true),
+                None => (Arc::clone(&watched.callback), false),
+            }
+        };
+
+        if callback(input, ctx).await? == Watch::Done && !claimed {
+            let mut messages = self.messages.lock().unwrap();
+
+            // The callback might have been replaced while it was running
+            if messages
+                .get(&key)
+                .is_some_and(|watched| Arc::ptr_eq(&watched.callback, &callback))
+            {
+                messages.remove(&key);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl<Ctx, E> Watched<Ctx, E> {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn reaction(item_id: i64, emoji: &str, added: bool, total: usize) -> Arc<ChatItemReaction> {
+        let reactions: Vec<_> = (total > 0)
+            .then(|| {
+                serde_json::json!({
+                    "reaction": { "type": "emoji", "emoji": emoji },
+                    "userReacted": false,
+                    "totalReacted": total,
+                })
+            })
+            .into_iter()
+            .collect();
+
+        let ev = serde_json::json!({
+            "user": test_utils::user(),
+            "added": added,
+            "reaction": {
+                "chatInfo": test_utils::local_chat(),
+                "chatReaction": {
+                    "chatDir": { "type": "localRcv" },
+                    "chatItem": test_utils::chat_item(item_id, "", false, reactions.into()),
+                    "sentAt": "",
+                    "reaction": { "type": "emoji", "emoji": emoji },
+                },
+            },
+        });
+
+        Arc::new(serde_json::from_value(ev).unwrap())
+    }
+
+    #[tokio::test]
+    async fn watch_reactions() {
+        let chat = ChatId::Local(crate::id::UserId::from_raw(1));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let watcher = ReactionWatcher::<(), ()>::new();
+
+        let counter = Arc::clone(&calls);
+        watcher.watch(chat, MessageId::from_raw(5), None, move |reaction, _| {
+            let calls = counter.fetch_add(1, Ordering::Relaxed) + 1;
+            assert_eq!(reaction.emoji(), "👍");
+            assert!(reaction.from.is_none());
+
+            async move { Ok(if calls == 2 { Watch::Done } else { Watch::Keep }) }
+        });
+        watcher.watch(
+            chat,
+            MessageId::from_raw(6),
+            Some(Duration::ZERO),
+            |_, _| async { panic!("expired callbacks are never called") },
+        );
+
+        assert!(watcher.route(reaction(5, "👍", true, 1), ()).await.unwrap());
+        assert_eq!(
+            watcher.tally(chat, MessageId::from_raw(5)),
+            Some(BTreeMap::from([("👍".to_owned(), 1)]))
+        );
+
+        assert!(!watcher.route(reaction(6, "👍", true, 1), ()).await.unwrap());
+        assert!(!watcher.route(reaction(7, "👍", true, 1), ()).await.unwrap());
+
+        assert!(
+            watcher
+                .route(reaction(5, "👍", false, 0), ())
+                .await
+                .unwrap()
+        );
+        assert!(!watcher.is_watching(chat, MessageId::from_raw(5)));
+        assert!(!watcher.route(reaction(5, "👍", true, 1), ()).await.unwrap());
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn one_shot_watch_runs_once() {
+        let chat = ChatId::Local(crate::id::UserId::from_raw(1));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let watcher = ReactionWatcher::<(), ()>::new();
+
+        let counter = Arc::clone(&calls);
+        watcher.insert(
+            (chat, MessageId::from_raw(5)),
+            None,
+            Some(Box::new(|reaction| reaction.added)),
+            Arc::new(move |_, _| {
+                let counter = Arc::clone(&counter);
+                Box::pin(async move {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    Ok(Watch::Done)
+                })
+            }),
+        );
+
+        // Rejected by the filter, the watch stays
+        assert!(
+            watcher
+                .route(reaction(5, "👍", false, 0), ())
+                .await
+                .unwrap()
+        );
+        assert!(watcher.is_watching(chat, MessageId::from_raw(5)));
+
+        let (first, second) = tokio::join!(
+            watcher.route(reaction(5, "👍", true, 1), ()),
+            watcher.route(reaction(5, "👍", true, 2), ()),
+        );
+
+        assert!(first.unwrap() ^ second.unwrap());
+        assert!(!watcher.is_watching(chat, MessageId::from_raw(5)));
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,682 @@
+//! Record-and-replay transport for WebSocket sessions.
+//!
+//! [`Recorder`] writes every command, its correlated response and every event with timestamps
+//! into a JSONL file. Wrap any client with [`Recording`] and any raw event queue with
+//! [`Recorder::record_events`], or use [`connect`] that does both for a `simplex-chat` WebSocket
+//! server:
+//!
+//! ```ignore
+//! let recorder = Recorder::create("session.jsonl").await?;
+//! let (client, events) = record::connect("ws://127.0.0.1:5225", recorder.clone()).await?;
+//!
+//! let bot = Bot::init(client, settings).await?;
+//! // ...run dispatchers as usual
+//!
+//! recorder.flush().await?;
+//! ```
+//!
+//! [`Replay`] feeds the recorded file back as a [`ReplayClient`] + [`ws::EventStream`] pair, so the
+//! same dispatcher code can reproduce a recorded conversation deterministically. Events keep their
+//! recorded order relative to the commands: an event recorded after a command is held back until
+//! the replayed session sends that command.
+//!
+//! ```ignore
+//! let (client, events) = Replay::open("session.jsonl").await?.into_parts();
+//! // Bot initialisation commands are replayed as well
+//! let bot = Bot::init(client, settings).await?;
+//!
+//! events.into_dispatcher(bot)
+//!     .on(new_messages)
+//!     .dispatch()
+//!     .await?;
+//! ```
+//!
+//! ### File format
+//!
+//! Each line is a JSON object with the `ts` field containing milliseconds since the UNIX epoch
+//! and the `type` field which is one of:
+//!
+//! - `command`: `{"id": 1, "cmd": "/_get chats 1"}`
+//! - `response`: `{"id": 1, "resp": "<raw response JSON>"}`
+//! - `failure`: `{"id": 1, "error": "..."}`, `id` is `null` for event queue failures
+//! - `event`: `{"event": "<raw event JSON>"}`
+//!
+//! Responses and events are stored verbatim as strings to reproduce them byte-to-byte.
+//!
+//! ### Limitations
+//!
+//! The recording wraps the [`ClientApi`] and the event receiver, not the `simploxide_ws_core`
+//! `RawClient` and `EventQueue`: commands sent through the raw ws-core client directly are not
+//! recorded.
+
+use serde::{Deserialize, Serialize};
+use simploxide_api_types::client_api::WebSocketResponseShape;
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _},
+    sync::{mpsc, oneshot, watch},
+};
+
+use std::{
+    collections::VecDeque,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    BadResponseError, ClientApi, ClientApiError,
+    ws::{self, ConnectError, CoreError, EventResult, WsError},
+};
+
+/// Connects to a `simplex-chat` WebSocket server like [`ws::connect`] but records the session
+/// with the `recorder`.
+pub async fn connect<S: AsRef<str>>(
+    uri: S,
+    recorder: Recorder,
+) -> Result<(Recording<ws::Client>, ws::EventStream), ConnectError> {
+    let (client, mut events) = ws::connect(uri).await?;
+    events.receiver = recorder.record_events(events.receiver);
+
+    Ok((Recording::new(client, recorder), events))
+}
+
+/// A single line of the recording
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// Milliseconds since the UNIX epoch
+    pub ts: u64,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Record {
+    Command { id: u64, cmd: String },
+    Response { id: u64, resp: String },
+    Failure { id: Option<u64>, error: String },
+    Event { event: String },
+}
+
+impl Entry {
+    fn now(record: Record) -> Self {
+        let ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        Self { ts, record }
+    }
+}
+
+enum WriterMsg {
+    Entry(Entry),
+    Flush(oneshot::Sender<std::io::Result<()>>),
+}
+
+/// A cheaply cloneable handle writing entries to a JSONL file in a background task. The file
+/// is closed when all handles get dropped.
+#[derive(Clone)]
+pub struct Recorder {
+    writer: mpsc::UnboundedSender<WriterMsg>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Recorder {
+    /// Creates or truncates the file at `path` and starts recording into it
+    pub async fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let file = tokio::fs::File::create(path).await?;
+        let (writer, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(writer_task(tokio::io::BufWriter::new(file), receiver));
+
+        Ok(Self {
+            writer,
+            next_id: Arc::new(AtomicU64::new(1)),
+        })
+    }
+
+    /// Waits until all entries recorded so far are written to the file
+    pub async fn flush(&self) -> std::io::Result<()> {
+        let (tx, rx) = oneshot::channel();
+
+        if self.writer.send(WriterMsg::Flush(tx)).is_err() {
+            return Err(std::io::Error::other("recorder writer task is dead"));
+        }
+
+        rx.await
+            .unwrap_or_else(|_| Err(std::io::Error::other("recorder writer task is dead")))
+    }
+
+    /// Records all events passing through the receiver. The returned receiver yields the same
+    /// events.
+    pub fn record_events(
+        &self,
+        mut events: mpsc::UnboundedReceiver<EventResult>,
+    ) -> mpsc::UnboundedReceiver<EventResult> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let recorder = self.clone();
+
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                match &event {
+                    Ok(ev) => recorder.record(Record::Event { event: ev.clone() }),
+                    Err(e) => recorder.record(Record::Failure {
+                        id: None,
+                        error: e.to_string(),
+                    }),
+                }
+
+                if tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        rx
+    }
+
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn record(&self, record: Record) {
+        if self
+            .writer
+            .send(WriterMsg::Entry(Entry::now(record)))
+            .is_err()
+        {
+            log::error!("Recorder writer task is dead, the entry is lost");
+        }
+    }
+}
+
+async fn writer_task(
+    mut file: tokio::io::BufWriter<tokio::fs::File>,
+    mut receiver: mpsc::UnboundedReceiver<WriterMsg>,
+) {
+    let mut result = Ok(());
+
+    while let Some(msg) = receiver.recv().await {
+        match msg {
+            WriterMsg::Entry(entry) => {
+                // Serializing a struct with string fields cannot fail
+                let mut line = serde_json::to_vec(&entry).unwrap();
+                line.push(b'\n');
+
+                if let Err(e) = file.write_all(&line).await {
+                    log::error!("Failed to write the recording entry: {e}");
+                    result = Err(e);
+                }
+            }
+            WriterMsg::Flush(responder) => {
+                let flushed = std::mem::replace(&mut result, Ok(())).and(file.flush().await);
+                let _ = responder.send(flushed);
+            }
+        }
+    }
+
+    if let Err(e) = file.flush().await {
+        log::error!("Failed to flush the recording: {e}");
+    }
+}
+
+/// A client wrapper recording all commands and responses
+#[derive(Clone)]
+pub struct Recording<C> {
+    client: C,
+    recorder: Recorder,
+}
+
+impl<C> Recording<C> {
+    pub fn new(client: C, recorder: Recorder) -> Self {
+        Self { client, recorder }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.client
+    }
+
+    pub fn recorder(&self) -> &Recorder {
+        &self.recorder
+    }
+
+    pub fn into_inner(self) -> C {
+        self.client
+    }
+}
+
+impl<C: ClientApi + Send + Sync> ClientApi for Recording<C> {
+    type ResponseShape<'de, T>
+        = C::ResponseShape<'de, T>
+    where
+        T: 'de + Deserialize<'de>;
+
+    type Error = C::Error;
+
+    async fn send_raw(&self, command: String) -> Result<String, Self::Error> {
+        let id = self.recorder.next_id();
+        self.recorder.record(Record::Command {
+            id,
+            cmd: command.clone(),
+        });
+
+        let result = self.client.send_raw(command).await;
+
+        match &result {
+            Ok(resp) => self.recorder.record(Record::Response {
+                id,
+                resp: resp.clone(),
+            }),
+            Err(e) => self.recorder.record(Record::Failure {
+                id: Some(id),
+                error: e.to_string(),
+            }),
+        }
+
+        result
+    }
+}
+
+/// A recorded session loaded from a file
+pub struct Replay {
+    entries: Vec<Entry>,
+    realtime: bool,
+}
+
+impl Replay {
+    /// Loads the recording. Fails on IO errors and malformed lines.
+    pub async fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let file = tokio::fs::File::open(path).await?;
+        let mut lines = tokio::io::BufReader::new(file).lines();
+        let mut entries = Vec::new();
+
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let entry = serde_json::from_str(&line).map_err(std::io::Error::other)?;
+            entries.push(entry);
+        }
+
+        Ok(Self::from_entries(entries))
+    }
+
+    pub fn from_entries(entries: Vec<Entry>) -> Self {
+        Self {
+            entries,
+            realtime: false,
+        }
+    }
+
+    /// Deliver events with the recorded delays between them. By default events are delivered as
+    /// soon as the commands recorded before them are replayed.
+    pub fn realtime(mut self, realtime: bool) -> Self {
+        self.realtime = realtime;
+        self
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Splits the recording into a client answering recorded commands and a stream yielding
+    /// recorded events.
+    pub fn into_parts(self) -> (ReplayClient, ws::EventStream) {
+        let mut exchanges = VecDeque::new();
+        let mut events = Vec::new();
+
+        for entry in self.entries {
+            match entry.record {
+                Record::Command { id, cmd } => exchanges.push_back(Exchange {
+                    id,
+                    cmd,
+                    result: None,
+                }),
+                Record::Response { id, resp } => {
+                    if let Some(ex) = exchanges.iter_mut().find(|ex| ex.id == id) {
+                        ex.result = Some(Ok(resp));
+                    }
+                }
+                Record::Failure {
+                    id: Some(id),
+                    error,
+                } => {
+                    if let Some(ex) = exchanges.iter_mut().find(|ex| ex.id == id) {
+                        ex.result = Some(Err(error));
+                    }
+                }
+                Record::Failure { id: None, error } => {
+                    let error = WsError::Io(std::io::Error::other(error));
+                    events.push((exchanges.len(), entry.ts, Err(CoreError::new(error))));
+                }
+                Record::Event { event } => events.push((exchanges.len(), entry.ts, Ok(event))),
+            }
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (replayed, _) = watch::channel(0);
+        let replayed = Arc::new(replayed);
+        let mut events = events.into_iter().peekable();
+
+        // Events recorded before the first command are available right away
+        if !self.realtime {
+            while let Some((_, _, event)) = events.next_if(|(commands, ..)| *commands == 0) {
+                let _ = tx.send(event);
+            }
+        }
+
+        if events.peek().is_some() {
+            let mut progress = replayed.subscribe();
+            let realtime = self.realtime;
+
+            tokio::spawn(async move {
+                let mut prev_ts = None;
+
+                for (commands, ts, event) in events {
+                    // The stream ends if the client is dropped before sending the commands
+                    if progress.wait_for(|sent| *sent >= commands).await.is_err() {
+                        break;
+                    }
+
+                    if realtime {
+                        let delay = ts.saturating_sub(prev_ts.unwrap_or(ts));
+                        tokio::time::sleep(Duration::from_millis(delay)).await;
+                        prev_ts = Some(ts);
+                    }
+
+                    if tx.send(event).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        let client = ReplayClient {
+            exchanges: Arc::new(Mutex::new(exchanges)),
+            replayed,
+        };
+
+        (client, ws::EventStream::from(rx))
+    }
+}
+
+struct Exchange {
+    id: u64,
+    cmd: String,
+    result: Option<Result<String, String>>,
+}
+
+/// A [`ClientApi`] answering commands with the recorded responses.
+///
+/// Each command is matched with the earliest not yet replayed recorded command with the same
+/// text, so concurrent requests may be replayed in a different order than recorded.
+#[derive(Clone)]
+pub struct ReplayClient {
+    exchanges: Arc<Mutex<VecDeque<Exchange>>>,
+    /// The number of replayed commands, releases the events recorded after them
+    replayed: Arc<watch::Sender<usize>>,
+}
+
+impl ReplayClient {
+    /// Recorded commands that were not replayed yet
+    pub fn remaining(&self) -> Vec<String> {
+        self.exchanges
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|ex| ex.cmd.clone())
+            .collect()
+    }
+}
+
+impl ClientApi for ReplayClient {
+    type ResponseShape<'de, T>
+        = WebSocketResponseShape<T>
+    where
+        T: 'de + Deserialize<'de>;
+
+    type Error = ReplayError;
+
+    async fn send_raw(&self, command: String) -> Result<String, Self::Error> {
+        let mut exchanges = self.exchanges.lock().unwrap();
+
+        let Some(pos) = exchanges.iter().position(|ex| ex.cmd == command) else {
+            log::error!("Replay diverged from the recording at: {command}");
+            return Err(ReplayError::Diverged(command));
+        };
+
+        let exchange = exchanges.remove(pos).unwrap();
+        drop(exchanges);
+        self.replayed.send_modify(|sent| *sent += 1);
+
+        match exchange.result {
+            Some(Ok(resp)) => Ok(resp),
+            Some(Err(error)) => Err(ReplayError::Failure(error)),
+            None => Err(ReplayError::NoResponse(command)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The command is not present in the recording
+    Diverged(String),
+    /// The command was recorded but the session ended before the response arrived
+    NoResponse(String),
+    /// The recorded command failed with a transport error
+    Failure(String),
+    /// SimpleX command error or unexpected(undocumented) response.
+    BadResponse(BadResponseError),
+}
+
+impl std::error::Error for ReplayError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Diverged(_) | Self::NoResponse(_) | Self::Failure(_) => None,
+            Self::BadResponse(error) => Some(error),
+        }
+    }
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Diverged(cmd) => write!(f, "Command is missing in the recording: {cmd}"),
+            Self::NoResponse(cmd) => write!(f, "No response was recorded for: {cmd}"),
+            Self::Failure(err) => write!(f, "Recorded failure: {err}"),
+            Self::BadResponse(err) => err.fmt(f),
+        }
+    }
+}
+
+impl From<BadResponseError> for ReplayError {
+    fn from(err: BadResponseError) -> Self {
+        Self::BadResponse(err)
+    }
+}
+
+impl From<ws::ClientError> for ReplayError {
+    fn from(err: ws::ClientError) -> Self {
+        match err {
+            ws::ClientError::WebSocketFailure(e) => Self::Failure(e.to_string()),
+            ws::ClientError::BadResponse(e) => Self::BadResponse(e),
+        }
+    }
+}
+
+impl ClientApiError for ReplayError {
+    fn bad_response(&self) -> Option<&BadResponseError> {
+        if let Self::BadResponse(resp) = self {
+            Some(resp)
+        } else {
+            None
+        }
+    }
+
+    fn bad_response_mut(&mut self) -> Option<&mut BadResponseError> {
+        if let Self::BadResponse(resp) = self {
+            Some(resp)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::{ext::ClientApiExt as _, id::ContactId, mock};
+
+    #[tokio::test]
+    async fn record_and_replay() {
+        let path = std::env::temp_dir().join(format!(
+            "simploxide-record-{}-{:?}.jsonl",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+
+        let (mock_client, _, _) = mock::init();
+        mock_client
+            .expect_raw("/_set custom")
+            .times(2)
+            .respond_json(serde_json::json!({ "type": "cmdOk" }));
+
+        let recorder = Recorder::create(&path).await.unwrap();
+        let client = Recording::new(mock_client, recorder.clone());
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut recorded_events = recorder.record_events(rx);
+        tx.send(Ok(r#"{"type":"first"}"#.to_owned())).unwrap();
+        tx.send(Ok(r#"{"type":"second"}"#.to_owned())).unwrap();
+        drop(tx);
+
+        while recorded_events.recv().await.is_some() {}
+
+        client
+            .set_contact_custom_data(ContactId::from_raw(5), None)
+            .await
+            .unwrap();
+        client
+            .set_contact_custom_data(ContactId::from_raw(7), None)
+            .await
+            .unwrap();
+        // Unexpected by the mock, recorded as a failure
+        assert!(client.send_raw("/relays".to_owned()).await.is_err());
+
+        recorder.flush().await.unwrap();
+        drop((client, recorder));
+
+        let replay = Replay::open(&path).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(replay.entries().len(), 8);
+
+        let (client, events) = replay.into_parts();
+        let mut events = events.into_receiver();
+        assert_eq!(events.recv().await.unwrap().unwrap(), r#"{"type":"first"}"#);
+        assert_eq!(
+            events.recv().await.unwrap().unwrap(),
+            r#"{"type":"second"}"#
+        );
+        assert!(events.recv().await.is_none());
+
+        // Commands are matched by text, not by the recorded order
+        client
+            .set_contact_custom_data(ContactId::from_raw(7), None)
+            .await
+            .unwrap();
+        assert_eq!(client.remaining().len(), 2);
+
+        assert!(matches!(
+            client.send_raw("/relays".to_owned()).await,
+            Err(ReplayError::Failure(_))
+        ));
+        assert!(matches!(
+            client
+                .set_contact_custom_data(ContactId::from_raw(7), None)
+                .await,
+            Err(ReplayError::Diverged(_))
+        ));
+        // A diverged command doesn't consume the remaining exchanges
+        client
+            .set_contact_custom_data(ContactId::from_raw(5), None)
+            .await
+            .unwrap();
+        assert!(client.remaining().is_empty());
+    }
+
+    #[tokio::test]
+    async fn replay_without_response() {
+        let entries = vec![
+            Entry::now(Record::Command {
+                id: 1,
+                cmd: "/relays".to_owned(),
+            }),
+            Entry::now(Record::Failure {
+                id: None,
+                error: "connection reset".to_owned(),
+            }),
+        ];
+
+        let (client, events) = Replay::from_entries(entries).into_parts();
+        let mut events = events.into_receiver();
+
+        assert!(matches!(
+            client.send_raw("/relays".to_owned()).await,
+            Err(ReplayError::NoResponse(_))
+        ));
+        assert!(events.recv().await.unwrap().is_err());
+        assert!(matches!(
+            client.send_raw("/relays".to_owned()).await,
+            Err(ReplayError::Diverged(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn replay_keeps_event_order() {
+        let event = |event: &str| {
+            Entry::now(Record::Event {
+                event: event.to_owned(),
+            })
+        };
+        let command = |id, cmd: &str| {
+            Entry::now(Record::Command {
+                id,
+                cmd: cmd.to_owned(),
+            })
+        };
+        let response = |id| {
+            Entry::now(Record::Response {
+                id,
+                resp: r#"{"resp":{"type":"cmdOk"}}"#.to_owned(),
+            })
+        };
+
+        let entries = vec![
+            event("first"),
+            command(1, "/a"),
+            response(1),
+            event("second"),
+            command(2, "/b"),
+            event("third"),
+            response(2),
+        ];
+
+        let (client, events) = Replay::from_entries(entries).into_parts();
+        let mut events = events.into_receiver();
+        assert_eq!(events.recv().await.unwrap().unwrap(), "first");
+
+        let held = tokio::time::timeout(Duration::from_millis(20), events.recv()).await;
+        assert!(held.is_err(), "the event is recorded after the command");
+
+        client.send_raw("/a".to_owned()).await.unwrap();
+        assert_eq!(events.recv().await.unwrap().unwrap(), "second");
+
+        client.send_raw("/b".to_owned()).await.unwrap();
+        assert_eq!(events.recv().await.unwrap().unwrap(), "third");
+        assert!(events.recv().await.is_none());
+    }
+}
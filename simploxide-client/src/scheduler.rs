@@ -0,0 +1,890 @@
+//! Scheduled and recurring message delivery.
+//!
+//! [`Scheduler`] delivers messages in a background task. Jobs are kept in a [`JobStore`], so with
+//! [`FileStore`] pending and recurring jobs survive restarts. Any message that can be sent with
+//! [`MessageBuilder`] can be scheduled:
+//!
+//! ```ignore
+//! let scheduler = Scheduler::start(client.clone(), FileStore::new("jobs.json")).await?;
+//!
+//! // One-shot reminder
+//! let job = bot
+//!     .send_msg(chat, "Time to stretch!")
+//!     .schedule(&scheduler, Schedule::after(Duration::from_secs(3600)))
+//!     .await?;
+//!
+//! // Changed our mind
+//! job.cancel().await?;
+//!
+//! // Every day at 09:30 UTC
+//! bot.schedule(&scheduler, Schedule::daily(9, 30), group, "Standup!").await?;
+//!
+//! // Jobs restored after a restart can be found with `Scheduler::jobs`
+//! for job in scheduler.jobs().await? {
+//!     println!("{} next run at {:?}", job.id, job.next_run);
+//! }
+//! ```
+//!
+//! Message previews are resolved when the job is scheduled while attached files are read at the
+//! delivery time, so they must stay in place until then. Jobs which were due while the scheduler
+//! was stopped get delivered once right after the start.
+//!
+//! Due jobs are delivered concurrently in separate tasks, so slow deliveries don't hold up
+//! scheduling and cancelling other jobs. Failed deliveries are retried with an exponential backoff
+//! of up to 10 minutes, one-shot jobs stay in the store until they are delivered or cancelled.
+//! Retries of recurring jobs never postpone their next regular run.
+//!
+//! [`MessageBuilder`]: crate::messages::MessageBuilder
+
+use serde::{Deserialize, Serialize};
+use simploxide_api_types::{ChatRef, ComposedMessage, commands::ApiSendMessages};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinSet,
+};
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::{ClientApi, dispatcher::Backoff, id::ChatId, preferences};
+
+/// The scheduler re-checks the wall clock at least this often
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Delays between redelivery attempts of failed jobs
+const RETRY_BACKOFF: Backoff =
+    Backoff::exponential(Duration::from_secs(5), Duration::from_secs(10 * 60));
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// Job IDs are derived from the scheduling time in microseconds since the UNIX epoch and grow
+/// monotonically, so IDs of delivered or cancelled jobs aren't reused after a restart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct JobId(u64);
+
+impl JobId {
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for JobId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// When to deliver a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Schedule {
+    /// Once at the specified time
+    At { time: SystemTime },
+    /// Every `interval` starting at `start`
+    Every {
+        start: SystemTime,
+        interval: Duration,
+    },
+    /// Every day at the specified time of the day
+    #[serde(rename_all = "camelCase")]
+    Daily {
+        hour: u8,
+        minute: u8,
+        utc_offset_mins: i16,
+    },
+}
+
+impl Schedule {
+    pub fn at(time: SystemTime) -> Self {
+        Self::At { time }
+    }
+
+    pub fn after(delay: Duration) -> Self {
+        Self::at(SystemTime::now() + delay)
+    }
+
+    /// Every `interval` starting in one `interval` from now
+    ///
+    /// # Panics
+    ///
+    /// If `interval` is zero
+    pub fn every(interval: Duration) -> Self {
+        Self::every_from(SystemTime::now() + interval, interval)
+    }
+
+    /// # Panics
+    ///
+    /// If `interval` is zero
+    pub fn every_from(start: SystemTime, interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "Schedule interval must be non-zero");
+        Self::Every { start, interval }
+    }
+
+    /// Every day at `hour:minute` UTC. Use [`Self::with_utc_offset`] to set a different time
+    /// zone.
+    ///
+    /// # Panics
+    ///
+    /// If `hour` or `minute` is out of range
+    pub fn daily(hour: u8, minute: u8) -> Self {
+        assert!(hour < 24 && minute < 60, "Invalid time {hour}:{minute}");
+
+        Self::Daily {
+            hour,
+            minute,
+            utc_offset_mins: 0,
+        }
+    }
+
+    /// Interpret the [`Self::Daily`] time in the time zone with the specified UTC offset, e.g.
+    /// `-300` for UTC-05:00. Has no effect on other schedules.
+    pub fn with_utc_offset(mut self, offset_mins: i16) -> Self {
+        if let Self::Daily {
+            utc_offset_mins, ..
+        } = &mut self
+        {
+            *utc_offset_mins = offset_mins;
+        }
+
+        self
+    }
+
+    /// Returns `false` for schedules that cannot be delivered: [`Self::Every`] with a zero
+    /// `interval` or [`Self::Daily`] with an out of range time. Such schedules can be constructed
+    /// directly or deserialized, the [`Scheduler`] rejects them.
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            Self::At { .. } => true,
+            Self::Every { interval, .. } => !interval.is_zero(),
+            Self::Daily { hour, minute, .. } => hour < 24 && minute < 60,
+        }
+    }
+
+    /// The first delivery time of a job scheduled at `now`
+    pub fn first_run(&self, now: SystemTime) -> SystemTime {
+        match *self {
+            Self::At { time } => time,
+            Self::Every { start, .. } => start,
+            Self::Daily { .. } => self.next_after(now).unwrap_or(now),
+        }
+    }
+
+    /// The earliest delivery time strictly after `time`. Returns `None` for one-shot and invalid
+    /// schedules, see [`Self::is_valid`].
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        if !self.is_valid() {
+            return None;
+        }
+
+        match *self {
+            Self::At { .. } => None,
+            Self::Every { start, interval } => match time.duration_since(start) {
+                Ok(elapsed) => {
+                    let periods = elapsed.as_nanos() / interval.as_nanos() + 1;
+                    let offset = interval.as_nanos() * periods;
+                    Some(start + nanos_to_duration(offset))
+                }
+                Err(_) => Some(start),
+            },
+            Self::Daily {
+                hour,
+                minute,
+                utc_offset_mins,
+            } => {
+                let secs = time
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+
+                let time_of_day = i64::from(hour) * 3600 + i64::from(minute) * 60
+                    - i64::from(utc_offset_mins) * 60;
+                let time_of_day = time_of_day.rem_euclid(DAY_SECS as i64) as u64;
+
+                let mut next = secs - secs % DAY_SECS + time_of_day;
+                if next <= secs {
+                    next += DAY_SECS;
+                }
+
+                Some(SystemTime::UNIX_EPOCH + Duration::from_secs(next))
+            }
+        }
+    }
+}
+
+fn nanos_to_duration(nanos: u128) -> Duration {
+    const NANOS_PER_SEC: u128 = 1_000_000_000;
+    Duration::new(
+        (nanos / NANOS_PER_SEC) as u64,
+        (nanos % NANOS_PER_SEC) as u32,
+    )
+}
+
+/// A pending message delivery
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Job {
+    pub id: JobId,
+    pub chat: ChatRef,
+    pub schedule: Schedule,
+    pub next_run: SystemTime,
+    pub sign: bool,
+    pub ttl: Option<Duration>,
+    pub msg: ComposedMessage,
+    /// Failed delivery attempts since the last successful delivery
+    #[serde(default)]
+    pub failures: u32,
+}
+
+impl Job {
+    pub fn chat_id(&self) -> Option<ChatId> {
+        ChatId::from_chat_ref(&self.chat)
+    }
+}
+
+/// Persistent storage of the scheduled jobs. The store is owned by the scheduler task, all calls
+/// are sequential.
+pub trait JobStore: 'static + Send {
+    /// Load the jobs saved previously. Called once on [`Scheduler::start`]
+    fn load(&mut self) -> impl Future<Output = std::io::Result<Vec<Job>>> + Send;
+
+    /// Insert a new job or replace an existing job with the same ID
+    fn save(&mut self, job: &Job) -> impl Future<Output = std::io::Result<()>> + Send;
+
+    fn remove(&mut self, id: JobId) -> impl Future<Output = std::io::Result<()>> + Send;
+}
+
+/// Doesn't persist anything. All jobs are lost when the scheduler stops.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryStore;
+
+impl JobStore for MemoryStore {
+    async fn load(&mut self) -> std::io::Result<Vec<Job>> {
+        Ok(Vec::new())
+    }
+
+    async fn save(&mut self, _: &Job) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    async fn remove(&mut self, _: JobId) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps all jobs in a JSON file. The file is rewritten on every change, which is fine for the
+/// typical bot workloads of up to a few thousand jobs.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    jobs: BTreeMap<JobId, Job>,
+}
+
+impl FileStore {
+    /// The file is created on the first write if it doesn't exist
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            jobs: BTreeMap::new(),
+        }
+    }
+
+    async fn flush(&self) -> std::io::Result<()> {
+        let jobs: Vec<&Job> = self.jobs.values().collect();
+        let json = serde_json::to_vec_pretty(&jobs)?;
+
+        // Write-then-rename so a crash doesn't leave a truncated file behind
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+
+        tokio::fs::write(&tmp, json).await?;
+        tokio::fs::rename(&tmp, &self.path).await
+    }
+}
+
+impl JobStore for FileStore {
+    async fn load(&mut self) -> std::io::Result<Vec<Job>> {
+        let jobs: Vec<Job> = match tokio::fs::read(&self.path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        if let Some(job) = jobs.iter().find(|job| !job.schedule.is_valid()) {
+            return Err(invalid_schedule(job.schedule));
+        }
+
+        self.jobs = jobs.iter().map(|job| (job.id, job.clone())).collect();
+        Ok(jobs)
+    }
+
+    async fn save(&mut self, job: &Job) -> std::io::Result<()> {
+        self.jobs.insert(job.id, job.clone());
+        self.flush().await
+    }
+
+    async fn remove(&mut self, id: JobId) -> std::io::Result<()> {
+        if self.jobs.remove(&id).is_some() {
+            self.flush().await
+        } else {
+            Ok(())
+        }
+    }
+}
+
+enum Command {
+    Add(Box<Job>, oneshot::Sender<std::io::Result<()>>),
+    Cancel(JobId, oneshot::Sender<std::io::Result<bool>>),
+    List(oneshot::Sender<Vec<Job>>),
+}
+
+/// A cheaply cloneable handle to the scheduler task. The task stops when all handles get
+/// dropped, pending jobs stay in the [`JobStore`].
+#[derive(Clone)]
+pub struct Scheduler {
+    commands: mpsc::UnboundedSender<Command>,
+    last_id: Arc<AtomicU64>,
+}
+
+impl Scheduler {
+    /// Load the jobs from the `store` and start delivering them with the `client`. Fails with
+    /// [`std::io::ErrorKind::InvalidData`] if some stored job has an invalid schedule.
+    pub async fn start<C, S>(client: C, mut store: S) -> std::io::Result<Self>
+    where
+        C: 'static + Send + ClientApi,
+        C::Error: Send,
+        S: JobStore,
+    {
+        let jobs: BTreeMap<JobId, Job> = store
+            .load()
+            .await?
+            .into_iter()
+            .map(|job| (job.id, job))
+            .collect();
+
+        if let Some(job) = jobs.values().find(|job| !job.schedule.is_valid()) {
+            return Err(invalid_schedule(job.schedule));
+        }
+
+        let last_id = jobs.keys().next_back().map_or(0, |id| id.0);
+        let (commands, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(scheduler_task(client, store, jobs, receiver));
+
+        Ok(Self {
+            commands,
+            last_id: Arc::new(AtomicU64::new(last_id)),
+        })
+    }
+
+    /// Cancel the job. Returns `false` if the job doesn't exist or was already delivered
+    pub async fn cancel(&self, id: JobId) -> std::io::Result<bool> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Command::Cancel(id, tx))?;
+        rx.await.unwrap_or_else(|_| Err(task_is_dead()))
+    }
+
+    /// Returns all pending jobs
+    pub async fn jobs(&self) -> std::io::Result<Vec<Job>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Command::List(tx))?;
+        rx.await.map_err(|_| task_is_dead())
+    }
+
+    pub(crate) async fn add(
+        &self,
+        chat_id: ChatId,
+        schedule: Schedule,
+        sign: bool,
+        ttl: Option<Duration>,
+        msg: ComposedMessage,
+    ) -> std::io::Result<JobHandle> {
+        if !schedule.is_valid() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid schedule: {schedule:?}"),
+            ));
+        }
+
+        let job = Job {
+            id: self.next_id(),
+            chat: chat_id.into_chat_ref(),
+            next_run: schedule.first_run(SystemTime::now()),
+            schedule,
+            sign,
+            ttl,
+            msg,
+            failures: 0,
+        };
+
+        let id = job.id;
+        let (tx, rx) = oneshot::channel();
+        self.send(Command::Add(Box::new(job), tx))?;
+        rx.await.unwrap_or_else(|_| Err(task_is_dead()))?;
+
+        Ok(JobHandle {
+            id,
+            scheduler: self.clone(),
+        })
+    }
+
+    fn send(&self, command: Command) -> std::io::Result<()> {
+        self.commands.send(command).map_err(|_| task_is_dead())
+    }
+
+    fn next_id(&self) -> JobId {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        let next = |last: u64| last.saturating_add(1).max(now);
+        let last = self
+            .last_id
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                Some(next(last))
+            })
+            .unwrap();
+
+        JobId(next(last))
+    }
+}
+
+/// Obtained when scheduling a message
+#[derive(Clone)]
+pub struct JobHandle {
+    id: JobId,
+    scheduler: Scheduler,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> JobId {
+        self.id
+    }
+
+    /// See [`Scheduler::cancel`]
+    pub async fn cancel(&self) -> std::io::Result<bool> {
+        self.scheduler.cancel(self.id).await
+    }
+}
+
+fn task_is_dead() -> std::io::Error {
+    std::io::Error::other("scheduler task is dead")
+}
+
+fn invalid_schedule(schedule: Schedule) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("Invalid stored schedule: {schedule:?}"),
+    )
+}
+
+/// Deliveries in progress: the job and the time it was started at
+type InFlight = HashMap<tokio::task::Id, (JobId, SystemTime)>;
+
+async fn scheduler_task<C, S>(
+    client: C,
+    mut store: S,
+    mut jobs: BTreeMap<JobId, Job>,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+) where
+    C: 'static + Send + ClientApi,
+    C::Error: Send,
+    S: JobStore,
+{
+    let client = Arc::new(client);
+    let mut deliveries = JoinSet::new();
+    let mut in_flight = HashMap::new();
+
+    loop {
+        let delay = jobs
+            .values()
+            .filter(|job| !is_in_flight(&in_flight, job.id))
+            .map(|job| job.next_run)
+            .min()
+            .map_or(MAX_SLEEP, |next_run| {
+                next_run
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default()
+                    .min(MAX_SLEEP)
+            });
+
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(Command::Add(job, responder)) => {
+                    let result = store.save(&job).await;
+                    if result.is_ok() {
+                        jobs.insert(job.id, *job);
+                    }
+
+                    let _ = responder.send(result);
+                }
+                Some(Command::Cancel(id, responder)) => {
+                    let result = match jobs.remove(&id) {
+                        Some(_) => store.remove(id).await.map(|_| true),
+                        None => Ok(false),
+                    };
+
+                    let _ = responder.send(result);
+                }
+                Some(Command::List(responder)) => {
+                    let _ = responder.send(jobs.values().cloned().collect());
+                }
+                None => break,
+            },
+            Some(joined) = deliveries.join_next_with_id() => {
+                finish_delivery(joined, &mut in_flight, &mut store, &mut jobs).await;
+            }
+            _ = tokio::time::sleep(delay) => {
+                start_due_jobs(&client, &jobs, &mut in_flight, &mut deliveries);
+            }
+        }
+    }
+
+    // Let the started deliveries complete so the store doesn't get stale
+    while let Some(joined) = deliveries.join_next_with_id().await {
+        finish_delivery(joined, &mut in_flight, &mut store, &mut jobs).await;
+    }
+}
+
+fn is_in_flight(in_flight: &InFlight, id: JobId) -> bool {
+    in_flight.values().any(|(job_id, _)| *job_id == id)
+}
+
+fn start_due_jobs<C>(
+    client: &Arc<C>,
+    jobs: &BTreeMap<JobId, Job>,
+    in_flight: &mut InFlight,
+    deliveries: &mut JoinSet<Result<(), String>>,
+) where
+    C: 'static + Send + ClientApi,
+    C::Error: Send,
+{
+    let now = SystemTime::now();
+    let mut due: Vec<_> = jobs
+        .values()
+        .filter(|job| job.next_run <= now && !is_in_flight(in_flight, job.id))
+        .collect();
+
+    due.sort_unstable_by_key(|job| (job.next_run, job.id));
+
+    for job in due {
+        let client = Arc::clone(client);
+        let command = ApiSendMessages {
+            send_ref: job.chat.clone(),
+            live_message: false,
+            sign_messages: job.sign,
+            ttl: job.ttl.map(preferences::timed_messages::ttl_to_secs),
+            composed_messages: vec![job.msg.clone()],
+        };
+
+        let task = deliveries.spawn(async move {
+            match client.api_send_messages(command).await {
+                Ok(_) => Ok(()),
+                Err(e) => Err(e.to_string()),
+            }
+        });
+
+        in_flight.insert(task.id(), (job.id, now));
+    }
+}
+
+async fn finish_delivery<S: JobStore>(
+    joined: Result<(tokio::task::Id, Result<(), String>), tokio::task::JoinError>,
+    in_flight: &mut InFlight,
+    store: &mut S,
+    jobs: &mut BTreeMap<JobId, Job>,
+) {
+    let (task, result) = match joined {
+        Ok((task, result)) => (task, result),
+        Err(e) => (e.id(), Err(e.to_string())),
+    };
+
+    let (id, now) = in_flight
+        .remove(&task)
+        .expect("Every delivery task is tracked");
+
+    // Cancelled while being delivered
+    let Some(job) = jobs.get_mut(&id) else {
+        return;
+    };
+
+    let result = match (result, job.schedule.next_after(now)) {
+        (Err(e), next_run) => {
+            let retry_at = now + RETRY_BACKOFF.delay(job.failures);
+            log::error!("Failed to deliver the scheduled job {id}, retrying at {retry_at:?}: {e}");
+
+            job.failures = job.failures.saturating_add(1);
+            job.next_run = next_run.map_or(retry_at, |next_run| next_run.min(retry_at));
+            store.save(job).await
+        }
+        (Ok(_), Some(next_run)) => {
+            job.failures = 0;
+            job.next_run = next_run;
+            store.save(job).await
+        }
+        (Ok(_), None) => {
+            jobs.remove(&id);
+            store.remove(id).await
+        }
+    };
+
+    if let Err(e) = result {
+        log::error!("Failed to update the scheduled job {id} in the store: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn schedule_times() {
+        let every = Schedule::every_from(ts(100), Duration::from_secs(30));
+        assert_eq!(every.first_run(ts(0)), ts(100));
+        assert_eq!(every.next_after(ts(50)), Some(ts(100)));
+        assert_eq!(every.next_after(ts(100)), Some(ts(130)));
+        assert_eq!(every.next_after(ts(199)), Some(ts(220)));
+
+        // 1970-01-02 10:00:00 UTC
+        let now = ts(DAY_SECS + 10 * 3600);
+        let daily = Schedule::daily(9, 30);
+        assert_eq!(daily.first_run(now), ts(2 * DAY_SECS + 9 * 3600 + 1800));
+        assert_eq!(
+            daily.with_utc_offset(-60).next_after(now),
+            ts(DAY_SECS + 10 * 3600 + 1800).into()
+        );
+        assert_eq!(
+            Schedule::daily(0, 0).with_utc_offset(120).next_after(now),
+            ts(2 * DAY_SECS - 2 * 3600).into()
+        );
+
+        assert_eq!(Schedule::at(now).next_after(ts(0)), None);
+
+        let zero = Schedule::Every {
+            start: ts(100),
+            interval: Duration::ZERO,
+        };
+        assert!(!zero.is_valid());
+        assert_eq!(zero.next_after(ts(200)), None);
+        assert!(every.is_valid() && daily.is_valid());
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn failed_delivery_and_invalid_schedules() {
+        use crate::{ext::ClientApiExt as _, id::ContactId, mock};
+
+        let path = std::env::temp_dir().join(format!(
+            "simploxide-failed-jobs-{}.json",
+            std::process::id()
+        ));
+        // No expectations, every delivery fails
+        let (client, _, _) = mock::init();
+        let scheduler = Scheduler::start(client.clone(), FileStore::new(&path))
+            .await
+            .unwrap();
+
+        let chat = ContactId::from_raw(1);
+        let scheduled_at = SystemTime::now();
+        let job = client
+            .send_message(chat, "reminder")
+            .schedule(&scheduler, Schedule::after(Duration::from_millis(20)))
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(client.sent().len(), 1);
+
+        let jobs = scheduler.jobs().await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].id, job.id());
+        assert_eq!(jobs[0].failures, 1);
+        assert!(jobs[0].next_run >= scheduled_at + RETRY_BACKOFF.delay(0));
+
+        let zero = Schedule::Every {
+            start: SystemTime::now(),
+            interval: Duration::ZERO,
+        };
+        let err = client
+            .send_message(chat, "spam")
+            .schedule(&scheduler, zero)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        drop((scheduler, job));
+
+        let mut restored = FileStore::new(&path).load().await.unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].failures, 1);
+
+        restored[0].schedule = zero;
+        std::fs::write(&path, serde_json::to_vec(&restored).unwrap()).unwrap();
+
+        let err = Scheduler::start(client, FileStore::new(&path))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn delivery_and_persistence() {
+        use crate::{
+            commands::ApiSendMessages, ext::ClientApiExt as _, id::ContactId, mock, test_utils,
+        };
+
+        let path =
+            std::env::temp_dir().join(format!("simploxide-jobs-{}.json", std::process::id()));
+        let (client, _, _) = mock::init();
+        client
+            .expect::<ApiSendMessages>()
+            .repeatedly()
+            .respond_json(serde_json::json!({
+                "type": "newChatItems",
+                "user": test_utils::user(),
+                "chatItems": [],
+            }));
+
+        let scheduler = Scheduler::start(client.clone(), FileStore::new(&path))
+            .await
+            .unwrap();
+
+        let chat = ContactId::from_raw(1);
+        client
+            .send_message(chat, "soon")
+            .schedule(&scheduler, Schedule::after(Duration::from_millis(20)))
+            .await
+            .unwrap();
+        let later = client
+            .send_message(chat, "later")
+            .schedule(&scheduler, Schedule::after(Duration::from_secs(3600)))
+            .await
+            .unwrap();
+        let cancelled = client
+            .send_message(chat, "never")
+            .schedule(&scheduler, Schedule::after(Duration::from_millis(20)))
+            .await
+            .unwrap();
+
+        assert!(cancelled.cancel().await.unwrap());
+        assert!(!cancelled.cancel().await.unwrap());
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let sent = client.sent_of::<ApiSendMessages>();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].command.contains("soon"));
+
+        let jobs: Vec<_> = scheduler
+            .jobs()
+            .await
+            .unwrap()
+            .iter()
+            .map(|job| job.id)
+            .collect();
+        assert_eq!(jobs, [later.id()]);
+        drop((scheduler, later));
+
+        let restored = FileStore::new(&path).load().await.unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].chat_id(), Some(chat.into()));
+
+        let scheduler = Scheduler::start(client.clone(), FileStore::new(&path))
+            .await
+            .unwrap();
+        let jobs: Vec<_> = scheduler
+            .jobs()
+            .await
+            .unwrap()
+            .iter()
+            .map(|job| job.id)
+            .collect();
+        assert_eq!(jobs, [restored[0].id]);
+
+        let next = client
+            .send_message(chat, "next")
+            .schedule(&scheduler, Schedule::every(Duration::from_secs(60)))
+            .await
+            .unwrap();
+        assert!(next.id() > restored[0].id);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn ids_and_slow_deliveries() {
+        use crate::{
+            commands::ApiSendMessages, ext::ClientApiExt as _, id::ContactId, mock, test_utils,
+        };
+
+        let (client, _, _) = mock::init();
+        client
+            .expect::<ApiSendMessages>()
+            .repeatedly()
+            .respond_fn(|_| {
+                // Mock responses are synchronous, let the runtime move other tasks off this thread
+                tokio::task::block_in_place(|| std::thread::sleep(Duration::from_millis(300)));
+                serde_json::json!({
+                    "type": "newChatItems",
+                    "user": test_utils::user(),
+                    "chatItems": [],
+                })
+            });
+
+        let chat = ContactId::from_raw(1);
+        let scheduler = Scheduler::start(client.clone(), MemoryStore).await.unwrap();
+        let first = client
+            .send_message(chat, "first")
+            .schedule(&scheduler, Schedule::after(Duration::from_secs(3600)))
+            .await
+            .unwrap();
+        assert!(first.cancel().await.unwrap());
+        drop(scheduler);
+
+        // The store is empty but the IDs keep growing
+        let scheduler = Scheduler::start(client.clone(), MemoryStore).await.unwrap();
+        let slow = client
+            .send_message(chat, "slow")
+            .schedule(&scheduler, Schedule::after(Duration::ZERO))
+            .await
+            .unwrap();
+        assert!(slow.id() > first.id());
+
+        // The delivery in progress doesn't block the scheduler
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let started = std::time::Instant::now();
+        let jobs = scheduler.jobs().await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(100));
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].id, slow.id());
+
+        let next = client
+            .send_message(chat, "next")
+            .schedule(&scheduler, Schedule::after(Duration::from_secs(3600)))
+            .await
+            .unwrap();
+        assert!(next.id() > slow.id());
+        assert!(next.cancel().await.unwrap());
+        assert!(started.elapsed() < Duration::from_millis(150));
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(scheduler.jobs().await.unwrap().is_empty());
+        assert_eq!(client.sent_of::<ApiSendMessages>().len(), 1);
+    }
+}
@@ -0,0 +1,176 @@
+//! JSON fixtures for the unit tests
+
+// Most of the fixtures are used only by the mock-based tests
+#![cfg_attr(not(feature = "mock"), allow(dead_code))]
+
+use simploxide_api_types::JsonObject;
+
+pub fn user() -> JsonObject {
+    let pref = serde_json::json!({ "allow": "yes" });
+
+    serde_json::json!({
+        "userId": 1,
+        "agentUserId": 1,
+        "userContactId": 1,
+        "localDisplayName": "bot",
+        "profile": { "profileId": 1, "displayName": "bot", "fullName": "", "localAlias": "" },
+        "fullPreferences": {
+            "timedMessages": { "allow": "yes" },
+            "fullDelete": pref,
+            "reactions": pref,
+            "voice": pref,
+            "files": pref,
+            "calls": pref,
+            "sessions": pref,
+            "commands": [],
+        },
+        "activeUser": true,
+        "activeOrder": 1,
+        "showNtfs": true,
+        "sendRcptsContacts": false,
+        "sendRcptsSmallGroups": false,
+        "autoAcceptMemberContacts": false,
+    })
+}
+
+/// The note folder of the user 1, the only chat type without heavy contact or group info
+pub fn local_chat() -> JsonObject {
+    serde_json::json!({
+        "type": "local",
+        "noteFolder": {
+            "noteFolderId": 1,
+            "userId": 1,
+            "createdAt": "",
+            "updatedAt": "",
+            "chatTs": "",
+        },
+    })
+}
+
+/// A text message, `received` selects the direction
+pub fn chat_item(id: i64, text: &str, received: bool, reactions: JsonObject) -> JsonObject {
+    let (dir, content) = if received {
+        ("localRcv", "rcvMsgContent")
+    } else {
+        ("localSnd", "sndMsgContent")
+    };
+
+    serde_json::json!({
+        "chatDir": { "type": dir },
+        "meta": {
+            "itemId": id,
+            "itemTs": "",
+            "itemText": text,
+            "itemStatus": { "type": "sndNew" },
+            "createdAt": "",
+            "updatedAt": "",
+        },
+        "content": {
+            "type": content,
+            "msgContent": { "type": "text", "text": text },
+        },
+        "mentions": {},
+        "reactions": reactions,
+    })
+}
+
+/// A connected group member with the given `groupMemberId`
+pub fn group_member(id: i64) -> JsonObject {
+    serde_json::json!({
+        "groupMemberId": id,
+        "groupId": 1,
+        "indexInGroup": id,
+        "memberId": format!("member-{id}"),
+        "memberRole": "member",
+        "memberCategory": "invitee",
+        "memberStatus": "connected",
+        "memberSettings": { "showMessages": true },
+        "blockedByAdmin": false,
+        "invitedBy": { "type": "unknown" },
+        "localDisplayName": format!("member{id}"),
+        "memberProfile": {
+            "profileId": id,
+            "displayName": format!("member{id}"),
+            "fullName": "",
+            "localAlias": "",
+        },
+        "memberContactProfileId": id,
+        "memberChatVRange": { "minVersion": 1, "maxVersion": 1 },
+        "createdAt": "",
+        "updatedAt": "",
+    })
+}
+
+/// An active contact with the given `contactId`
+pub fn contact(id: i64) -> JsonObject {
+    let pref = serde_json::json!({
+        "enabled": { "forUser": true, "forContact": true },
+        "userPreference": { "type": "user", "preference": { "allow": "yes" } },
+        "contactPreference": { "allow": "yes" },
+    });
+
+    serde_json::json!({
+        "contactId": id,
+        "localDisplayName": format!("contact{id}"),
+        "profile": {
+            "profileId": id,
+            "displayName": format!("contact{id}"),
+            "fullName": "",
+            "localAlias": "",
+        },
+        "contactUsed": true,
+        "contactStatus": "active",
+        "chatSettings": { "enableNtfs": "all", "favorite": false },
+        "userPreferences": {},
+        "mergedPreferences": {
+            "timedMessages": pref,
+            "fullDelete": pref,
+            "reactions": pref,
+            "voice": pref,
+            "files": pref,
+            "calls": pref,
+            "sessions": pref,
+        },
+        "createdAt": "",
+        "updatedAt": "",
+        "chatTags": [],
+    })
+}
+
+/// A group with the given `groupId` where the user is a member
+pub fn group_info(id: i64) -> JsonObject {
+    let on = serde_json::json!({ "enable": "on" });
+
+    let mut membership = group_member(100);
+    membership["groupId"] = id.into();
+
+    serde_json::json!({
+        "groupId": id,
+        "localDisplayName": format!("group{id}"),
+        "groupProfile": { "displayName": format!("group{id}"), "fullName": "" },
+        "localAlias": "",
+        "fullGroupPreferences": {
+            "timedMessages": on,
+            "directMessages": on,
+            "fullDelete": on,
+            "reactions": on,
+            "voice": on,
+            "files": on,
+            "simplexLinks": on,
+            "reports": on,
+            "history": on,
+            "support": on,
+            "sessions": on,
+            "comments": on,
+            "signMessages": on,
+            "commands": [],
+        },
+        "membership": membership,
+        "chatSettings": { "enableNtfs": "all", "favorite": false },
+        "createdAt": "",
+        "updatedAt": "",
+        "chatTags": [],
+        "groupSummary": { "currentMembers": 1 },
+        "membersRequireAttention": 0,
+    })
+}
@@ -1,5 +1,10 @@
 use serde::Deserialize;
 use serde_aux::prelude::*;
+use simploxide_api_types::{AChat, AChatItem, ChatRef, utils::CommandSyntax};
+
+use std::fmt::Write as _;
+
+use crate::{ext::ChatPagination, id::MessageId};
 
 pub fn cast_file_size(file_size: u64) -> std::io::Result<usize> {
     file_size.try_into().map_err(file_is_too_large)
@@ -51,3 +56,77 @@ pub struct ChatRelay {
     #[serde(default)]
     pub enabled: bool,
 }
+
+/// `/_get chat <chatRef> <pagination>[ search=<text>]`
+pub struct GetChat {
+    pub chat_ref: ChatRef,
+    pub pagination: ChatPagination,
+    pub search: Option<String>,
+}
+
+impl CommandSyntax for GetChat {
+    const COMMAND_BUF_SIZE: usize = 64;
+
+    fn append_command_syntax(&self, buf: &mut String) {
+        buf.push_str("/_get chat ");
+        self.chat_ref.append_command_syntax(buf);
+        write!(buf, " {}", self.pagination).unwrap();
+        if let Some(search) = &self.search {
+            buf.push_str(" search=");
+            buf.push_str(search);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ApiChatResp {
+    pub chat: AChat,
+}
+
+/// `/_get item info <chatRef> <chatItemId>`
+pub struct GetChatItemInfo {
+    pub chat_ref: ChatRef,
+    pub item_id: MessageId,
+}
+
+impl CommandSyntax for GetChatItemInfo {
+    const COMMAND_BUF_SIZE: usize = 64;
+
+    fn append_command_syntax(&self, buf: &mut String) {
+        buf.push_str("/_get item info ");
+        self.chat_ref.append_command_syntax(buf);
+        write!(buf, " {}", self.item_id).unwrap();
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChatItemInfoResp {
+    #[serde(rename = "chatItem")]
+    pub chat_item: AChatItem,
+}
+
+/// `/_forward <toChatRef> <fromChatRef> <chatItemId>[,<chatItemId>...]`
+pub struct ForwardChatItems {
+    pub to_chat_ref: ChatRef,
+    pub from_chat_ref: ChatRef,
+    pub item_ids: Vec<MessageId>,
+}
+
+impl CommandSyntax for ForwardChatItems {
+    const COMMAND_BUF_SIZE: usize = 64;
+
+    fn append_command_syntax(&self, buf: &mut String) {
+        buf.push_str("/_forward ");
+        self.to_chat_ref.append_command_syntax(buf);
+        buf.push(' ');
+        self.from_chat_ref.append_command_syntax(buf);
+        buf.push(' ');
+
+        for (i, id) in self.item_ids.iter().enumerate() {
+            if i > 0 {
+                buf.push(',');
+            }
+            write!(buf, "{id}").unwrap();
+        }
+    }
+}
@@ -0,0 +1,307 @@
+//! A fake SimpleX WebSocket daemon for integration tests.
+//!
+//! [`FakeDaemon`] is a local WebSocket server speaking the `{"corrId": .., "cmd": ..}` /
+//! `{"corrId": .., "resp": ..}` protocol of `simplex-chat -p`. Tests script replies per command
+//! prefix, push unsolicited events, delay responses and drop connections mid-flight.
+//!
+//! ```ignore
+//! let daemon = FakeDaemon::start().await?;
+//! daemon.on("/users", Reply::json(json!({"type": "usersList", "users": []})));
+//! daemon.on("/_send", Reply::json(json!({"type": "cmdOk"})).delayed(Duration::from_secs(1)));
+//! daemon.on("/crash", Reply::drop_connection());
+//!
+//! let (client, events) = simploxide_ws_core::connect(&daemon.url()).await?;
+//! daemon.push_event(json!({"type": "contactConnected"}));
+//! ```
+//!
+//! The `/v` command is answered with [`MAX_SUPPORTED_VERSION`] unless scripted otherwise.
+//! Unscripted commands are answered with an undocumented `{"type": "unscripted"}` response.
+
+use futures::{SinkExt as _, StreamExt as _};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use simploxide_core::MAX_SUPPORTED_VERSION;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinHandle,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+type ReplyFn = Box<dyn Send + Sync + Fn(&str) -> Value>;
+type ConnectionSender = mpsc::UnboundedSender<Control>;
+
+/// A running fake daemon. The server stops when the value is dropped.
+pub struct FakeDaemon {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    server: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct Shared {
+    scripts: Mutex<Vec<(String, Arc<Reply>)>>,
+    connections: Mutex<Vec<ConnectionSender>>,
+    received: Mutex<Vec<String>>,
+}
+
+impl FakeDaemon {
+    /// Starts the daemon on a random localhost port
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared::default());
+
+        let daemon = Self {
+            addr,
+            shared: Arc::clone(&shared),
+            server: tokio::spawn(accept_task(listener, shared)),
+        };
+
+        let version = MAX_SUPPORTED_VERSION.to_string();
+        daemon.on(
+            "/v",
+            Reply::json(json!({
+                "type": "versionInfo",
+                "versionInfo": { "version": version.trim_start_matches('v') },
+            })),
+        );
+
+        Ok(daemon)
+    }
+
+    /// WebSocket URL for [`crate::connect`]
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Script a reply for commands starting with `prefix`. The latest registered matching script
+    /// wins, so scripts can be overridden.
+    pub fn on(&self, prefix: impl Into<String>, reply: Reply) {
+        self.shared
+            .scripts
+            .lock()
+            .unwrap()
+            .push((prefix.into(), Arc::new(reply)));
+    }
+
+    /// Send an unsolicited event to all connected clients
+    pub fn push_event(&self, event: Value) {
+        self.broadcast(|| Control::Send(json!({ "resp": event }).to_string()));
+    }
+
+    /// Send a raw text frame to all connected clients
+    pub fn push_raw(&self, frame: impl Into<String>) {
+        let frame = frame.into();
+        self.broadcast(|| Control::Send(frame.clone()));
+    }
+
+    /// Abruptly drop all connections without a closing handshake
+    pub fn drop_connections(&self) {
+        self.broadcast(|| Control::Drop);
+    }
+
+    /// Commands received so far in the order of arrival
+    pub fn received(&self) -> Vec<String> {
+        self.shared.received.lock().unwrap().clone()
+    }
+
+    /// Number of live client connections
+    pub fn connections(&self) -> usize {
+        let mut connections = self.shared.connections.lock().unwrap();
+        connections.retain(|conn| !conn.is_closed());
+        connections.len()
+    }
+
+    fn broadcast(&self, msg: impl Fn() -> Control) {
+        let mut connections = self.shared.connections.lock().unwrap();
+        connections.retain(|conn| conn.send(msg()).is_ok());
+    }
+}
+
+impl Drop for FakeDaemon {
+    fn drop(&mut self) {
+        self.server.abort();
+        self.drop_connections();
+    }
+}
+
+/// A scripted reply for [`FakeDaemon::on`]
+pub struct Reply {
+    kind: ReplyKind,
+    delay: Option<Duration>,
+}
+
+enum ReplyKind {
+    Json(Value),
+    Fn(ReplyFn),
+    Ignore,
+    DropConnection,
+}
+
+impl Reply {
+    /// Respond with the value placed into the `resp` field
+    pub fn json(resp: Value) -> Self {
+        Self::new(ReplyKind::Json(resp))
+    }
+
+    /// Compute the `resp` value from the command
+    pub fn with<F>(f: F) -> Self
+    where
+        F: 'static + Send + Sync + Fn(&str) -> Value,
+    {
+        Self::new(ReplyKind::Fn(Box::new(f)))
+    }
+
+    /// Never respond leaving the request pending
+    pub fn ignore() -> Self {
+        Self::new(ReplyKind::Ignore)
+    }
+
+    /// Abruptly drop the connection instead of responding
+    pub fn drop_connection() -> Self {
+        Self::new(ReplyKind::DropConnection)
+    }
+
+    /// Delay the reply. Replies without delays are sent in the order of commands arrival.
+    pub fn delayed(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    fn new(kind: ReplyKind) -> Self {
+        Self { kind, delay: None }
+    }
+
+    fn control(&self, corr_id: &str, cmd: &str) -> Option<Control> {
+        let resp = match &self.kind {
+            ReplyKind::Json(json) => json.clone(),
+            ReplyKind::Fn(f) => f(cmd),
+            ReplyKind::Ignore => return None,
+            ReplyKind::DropConnection => return Some(Control::Drop),
+        };
+
+        Some(Control::Send(
+            json!({ "corrId": corr_id, "resp": resp }).to_string(),
+        ))
+    }
+}
+
+enum Control {
+    Send(String),
+    Drop,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(rename = "corrId")]
+    corr_id: String,
+    cmd: String,
+}
+
+async fn accept_task(listener: TcpListener, shared: Arc<Shared>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(connection_task(stream, Arc::clone(&shared)));
+            }
+            Err(e) => {
+                log::error!("Fake daemon failed to accept a connection: {e}");
+                break;
+            }
+        }
+    }
+}
+
+async fn connection_task(stream: TcpStream, shared: Arc<Shared>) {
+    let mut ws = match tokio_tungstenite::accept_async(stream).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            log::error!("Fake daemon handshake failed: {e}");
+            return;
+        }
+    };
+
+    let (sender, mut controls) = mpsc::unbounded_channel();
+    shared.connections.lock().unwrap().push(sender.clone());
+
+    loop {
+        tokio::select! {
+            msg = ws.next() => match msg {
+                Some(Ok(Message::Text(text))) => process_request(&shared, &sender, &text),
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => break,
+            },
+            control = controls.recv() => match control {
+                Some(Control::Send(frame)) => {
+                    if ws.send(Message::text(frame)).await.is_err() {
+                        break;
+                    }
+                }
+                // Dropping the socket without a closing handshake
+                Some(Control::Drop) | None => break,
+            },
+        }
+    }
+
+    log::debug!("Fake daemon connection finished");
+}
+
+fn process_request(shared: &Shared, sender: &ConnectionSender, text: &str) {
+    let request: Request = match serde_json::from_str(text) {
+        Ok(req) => req,
+        Err(e) => {
+            log::error!("Fake daemon got an invalid request {text:?}: {e}");
+            return;
+        }
+    };
+
+    shared.received.lock().unwrap().push(request.cmd.clone());
+
+    let reply = shared
+        .scripts
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|(prefix, _)| request.cmd.starts_with(prefix.as_str()))
+        .map(|(_, reply)| Arc::clone(reply));
+
+    let Some(reply) = reply else {
+        let _ = sender.send(Control::Send(
+            json!({
+                "corrId": request.corr_id,
+                "resp": { "type": "unscripted", "cmd": request.cmd },
+            })
+            .to_string(),
+        ));
+        return;
+    };
+
+    let Some(control) = reply.control(&request.corr_id, &request.cmd) else {
+        return;
+    };
+
+    match reply.delay {
+        Some(delay) => {
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                let _ = sender.send(control);
+            });
+        }
+        None => {
+            let _ = sender.send(control);
+        }
+    }
+}
@@ -71,6 +71,12 @@ static REQUEST_ID: AtomicUsize = AtomicUsize::new(0);
 #[cfg(feature = "cli")]
 pub mod cli;
 
+#[cfg(any(test, feature = "fake"))]
+pub mod fake;
+
+#[cfg(test)]
+mod tests;
+
 type RequestId = usize;
 fn next_request_id() -> RequestId {
     REQUEST_ID.fetch_add(1, Ordering::Relaxed)
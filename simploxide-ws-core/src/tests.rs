@@ -0,0 +1,134 @@
+use serde_json::{Value, json};
+
+use std::time::Duration;
+
+use crate::{
+    connect,
+    fake::{FakeDaemon, Reply},
+    tungstenite,
+};
+
+fn resp(response: &str) -> Value {
+    let json: Value = serde_json::from_str(response).unwrap();
+    json["resp"].clone()
+}
+
+#[tokio::test]
+async fn responses_are_routed_by_corr_id() {
+    let daemon = FakeDaemon::start().await.unwrap();
+    daemon.on(
+        "/slow",
+        Reply::json(json!({"type": "slow"})).delayed(Duration::from_millis(200)),
+    );
+    daemon.on("/fast", Reply::json(json!({"type": "fast"})));
+
+    let (client, _events) = connect(&daemon.url()).await.unwrap();
+
+    let (slow, fast) = tokio::join!(
+        client.send("/slow".to_owned()),
+        client.send("/fast".to_owned())
+    );
+
+    assert_eq!(resp(&slow.unwrap())["type"], "slow");
+    assert_eq!(resp(&fast.unwrap())["type"], "fast");
+    assert!(client.version().await.unwrap().is_supported());
+}
+
+#[tokio::test]
+async fn events_are_delivered_independently() {
+    let daemon = FakeDaemon::start().await.unwrap();
+    daemon.on("/ping", Reply::ignore());
+
+    let (client, mut events) = connect(&daemon.url()).await.unwrap();
+    let pending = tokio::spawn(async move { client.send("/ping".to_owned()).await });
+
+    while daemon.received().is_empty() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    daemon.push_event(json!({"type": "contactConnected"}));
+
+    let event = events.next_event().await.unwrap().unwrap();
+    assert_eq!(resp(&event)["type"], "contactConnected");
+    assert!(!pending.is_finished());
+}
+
+#[tokio::test]
+async fn disconnect_delivers_scheduled_responses() {
+    let daemon = FakeDaemon::start().await.unwrap();
+    daemon.on(
+        "/slow",
+        Reply::json(json!({"type": "slow"})).delayed(Duration::from_millis(200)),
+    );
+
+    let (client, mut events) = connect(&daemon.url()).await.unwrap();
+    let late_client = client.clone();
+
+    let pending = tokio::spawn({
+        let client = client.clone();
+        async move { client.send("/slow".to_owned()).await }
+    });
+
+    while daemon.received().is_empty() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    client.disconnect().await;
+
+    assert_eq!(resp(&pending.await.unwrap().unwrap())["type"], "slow");
+    assert!(matches!(
+        late_client
+            .send("/slow".to_owned())
+            .await
+            .unwrap_err()
+            .as_ref(),
+        tungstenite::Error::AlreadyClosed
+    ));
+    assert!(events.next_event().await.is_none());
+}
+
+#[tokio::test]
+async fn connection_drop_resolves_pending_futures() {
+    let daemon = FakeDaemon::start().await.unwrap();
+    daemon.on("/hang", Reply::ignore());
+    daemon.on(
+        "/crash",
+        Reply::drop_connection().delayed(Duration::from_millis(100)),
+    );
+
+    let (client, mut events) = connect(&daemon.url()).await.unwrap();
+
+    let (hang, crash) = tokio::join!(
+        client.send("/hang".to_owned()),
+        client.send("/crash".to_owned())
+    );
+
+    assert!(hang.is_err());
+    assert!(crash.is_err());
+    assert!(client.send("/hang".to_owned()).await.is_err());
+
+    assert!(events.next_event().await.unwrap().is_err());
+    assert!(events.next_event().await.is_none());
+}
+
+#[tokio::test]
+async fn buffered_events_survive_connection_drop() {
+    let daemon = FakeDaemon::start().await.unwrap();
+    let (_client, mut events) = connect(&daemon.url()).await.unwrap();
+
+    while daemon.connections() == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    daemon.push_event(json!({"type": "first"}));
+    daemon.push_event(json!({"type": "second"}));
+    daemon.drop_connections();
+
+    let first = events.next_event().await.unwrap().unwrap();
+    let second = events.next_event().await.unwrap().unwrap();
+
+    assert_eq!(resp(&first)["type"], "first");
+    assert_eq!(resp(&second)["type"], "second");
+    assert!(events.next_event().await.unwrap().is_err());
+    assert!(events.next_event().await.is_none());
+}